@@ -0,0 +1,122 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use uuid::Uuid;
+use js_sys::Date;
+use tab_organizer::{Database, Timer};
+use tab_organizer::keyspace;
+use tab_organizer::state::options::{AutoUnload, KEEP_LOADED_LABEL};
+
+use super::{State, BrowserTab, BrowserWindow};
+
+
+// How many milliseconds between each check for idle tabs
+const CHECK_INTERVAL: u32 = 60_000;
+
+
+pub(crate) fn settings(db: &Database) -> AutoUnload {
+    keyspace::AUTO_UNLOAD.get(db).unwrap_or_default()
+}
+
+pub(crate) fn set_settings(state: &mut State, settings: &AutoUnload) {
+    keyspace::AUTO_UNLOAD.set(&state.db, settings);
+    run(state);
+}
+
+
+fn host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_at(url.find("://")? + 3);
+
+    let authority = rest.split(|c| c == '/' || c == '?' || c == '#').next()?;
+
+    // Removes the username, password, and port
+    let host = authority.rsplit('@').next()?;
+    host.split(':').next()
+}
+
+fn is_excluded_domain(url: Option<&str>, excluded_domains: &[String]) -> bool {
+    match url.and_then(host) {
+        Some(host) => {
+            let host = host.to_lowercase();
+
+            excluded_domains.iter().any(|domain| {
+                let domain = domain.trim().trim_start_matches('.').to_lowercase();
+
+                !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+            })
+        },
+        None => false,
+    }
+}
+
+
+fn timestamp_focused(tab: &BrowserTab) -> f64 {
+    tab.serialized.timestamp_focused.unwrap_or(tab.serialized.timestamp_created)
+}
+
+fn can_unload(window: &BrowserWindow, tab: &BrowserTab, settings: &AutoUnload) -> bool {
+    !window.is_tab_focused(tab.serialized.uuid) &&
+    !tab.serialized.pinned &&
+    !tab.playing_audio &&
+    !tab.has_attention &&
+    !tab.serialized.has_label(KEEP_LOADED_LABEL) &&
+    !is_excluded_domain(tab.serialized.url.as_deref(), &settings.excluded_domains)
+}
+
+
+/// Unloads (or discards) the tabs which haven't been focused recently, and the least recently focused tabs in windows which have too many loaded tabs
+pub(crate) fn run(state: &mut State) {
+    let settings = settings(&state.db);
+
+    if !settings.enabled {
+        return;
+    }
+
+    let now = Date::now();
+
+    let mut uuids: Vec<Uuid> = vec![];
+
+    for window in state.window_ids.values() {
+        let mut loaded: Vec<&BrowserTab> = window.tabs.iter()
+            .filter_map(|uuid| state.tab_ids.get(state.ids.get(uuid)?))
+            .filter(|tab| !tab.is_unloading && !tab.status.is_discarded())
+            .collect();
+
+        // The least recently focused tabs are unloaded first
+        loaded.sort_by(|a, b| timestamp_focused(a).partial_cmp(&timestamp_focused(b)).unwrap());
+
+        let mut count = loaded.len();
+
+        for tab in loaded {
+            if can_unload(window, tab, &settings) {
+                let is_idle = (now - timestamp_focused(tab)) >= settings.idle_duration;
+
+                let is_over_limit = match settings.max_loaded_tabs {
+                    Some(max) => count > max,
+                    None => false,
+                };
+
+                if is_idle || is_over_limit {
+                    uuids.push(tab.serialized.uuid);
+                    count -= 1;
+                }
+            }
+        }
+    }
+
+    if !uuids.is_empty() {
+        if settings.discard {
+            state.discard_tabs(&uuids);
+
+        } else {
+            state.unload_tabs(&uuids);
+        }
+    }
+}
+
+
+pub(crate) fn start(state: Rc<RefCell<State>>) {
+    Timer::new(CHECK_INTERVAL, move || {
+        run(&mut state.borrow_mut());
+        start(state);
+    }).forget();
+}
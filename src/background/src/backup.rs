@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use js_sys::Date;
+use tab_organizer::{generate_uuid, time, serialize_str, Database, TimeDifference};
+use tab_organizer::keyspace::{self, Record};
+use tab_organizer::state::{SerializedWindow, SerializedTab};
+use tab_organizer::compress::{compress_str, decompress_str};
+use tab_organizer::state::options::{BackupSummary, BackupReason, ExportFormat, ImportError};
+
+use super::export;
+
+
+// TODO make these into options ?
+const BACKUP_INTERVAL: f64 = 6.0 * TimeDifference::HOUR;
+const MAX_BACKUPS: usize = 10;
+// The snapshots are limited separately, so that restoring or migrating doesn't remove the regular backups
+const MAX_SNAPSHOTS: usize = 5;
+
+
+// The snapshots are stored as separate keys, so that listing them doesn't need to decompress anything.
+// They are compressed strings rather than JSON, so they don't use keyspace::Record.
+pub(crate) const PREFIX: &str = "backup-ids.";
+
+fn key(uuid: Uuid) -> String {
+    format!("{}{}", PREFIX, uuid)
+}
+
+
+/// Returns the backups, from oldest to newest
+pub(crate) fn list(db: &Database) -> Vec<BackupSummary> {
+    keyspace::BACKUPS.get(db).unwrap_or_else(|| vec![])
+}
+
+
+pub(crate) fn is_due(db: &Database) -> bool {
+    match list(db).into_iter().rev().find(|backup| backup.reason == BackupReason::Regular) {
+        Some(backup) => Date::now() - backup.timestamp_created >= BACKUP_INTERVAL,
+        None => true,
+    }
+}
+
+
+/// Takes a snapshot of every window and tab, and removes the oldest snapshots which have the same reason.
+///
+/// The `Database` must be fully loaded.
+pub(crate) fn create(db: &Database, reason: BackupReason) {
+    time!("Creating backup", {
+        let windows = export::read_windows(db);
+
+        let summary = BackupSummary {
+            uuid: generate_uuid(),
+            timestamp_created: Date::now(),
+            windows: windows.len(),
+            tabs: windows.iter().map(|window| window.tabs.len()).sum(),
+            reason,
+        };
+
+        // This uses the JSON export format, so it can be restored with the importer
+        add(db, summary, &export::export(&windows, ExportFormat::Json));
+    })
+}
+
+
+/// Takes a snapshot of the raw records, without deserializing them.
+///
+/// This is used before migrating, because the records might be an older version. It uses the same
+/// format as `Database::to_json`, so the importer migrates it when it is restored.
+pub(crate) fn create_snapshot(db: &Database) -> Uuid {
+    time!("Creating snapshot", {
+        let mut output: BTreeMap<String, String> = BTreeMap::new();
+
+        for key in db.keys_with_prefix("") {
+            // The backups are skipped, otherwise every snapshot would contain the previous snapshots
+            if key != keyspace::BACKUPS.name() && !key.starts_with(PREFIX) {
+                if let Some(value) = db.get_raw(&key) {
+                    output.insert(key, value);
+                }
+            }
+        }
+
+        let summary = BackupSummary {
+            uuid: generate_uuid(),
+            timestamp_created: Date::now(),
+            windows: db.keys_with_prefix(SerializedWindow::PREFIX).len(),
+            tabs: db.keys_with_prefix(SerializedTab::PREFIX).len(),
+            reason: BackupReason::BeforeMigration,
+        };
+
+        let uuid = summary.uuid;
+
+        add(db, summary, &serialize_str(&output));
+
+        uuid
+    })
+}
+
+
+fn add(db: &Database, summary: BackupSummary, data: &str) {
+    db.set_raw(&key(summary.uuid), compress_str(data));
+
+    let reason = summary.reason;
+
+    let max = if reason == BackupReason::Regular { MAX_BACKUPS } else { MAX_SNAPSHOTS };
+
+    let mut backups = list(db);
+
+    backups.push(summary);
+
+    let count = backups.iter().filter(|backup| backup.reason == reason).count();
+
+    if count > max {
+        let mut extra = count - max;
+
+        // The backups are sorted from oldest to newest
+        backups.retain(|backup| {
+            if extra > 0 && backup.reason == reason {
+                extra -= 1;
+                db.remove(&key(backup.uuid));
+                false
+
+            } else {
+                true
+            }
+        });
+    }
+
+    keyspace::BACKUPS.set(db, &backups);
+}
+
+
+/// Returns the decompressed snapshot, it can be imported with `import::parse`
+pub(crate) fn read(db: &Database, uuid: Uuid) -> Result<String, ImportError> {
+    let data = db.get_raw(&key(uuid)).ok_or(ImportError::MissingBackup { uuid })?;
+
+    decompress_str(&data).map_err(|error| ImportError::InvalidBackup { message: error.to_string() })
+}
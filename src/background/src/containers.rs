@@ -0,0 +1,85 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::future::Future;
+use js_sys::{Array, Object};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_extension::{browser, ContextualIdentity, Event};
+use tab_organizer::{spawn, Listener};
+use tab_organizer::state::sidebar::{Container, ServerMessage};
+
+use super::State;
+
+
+/// Returns the containers in the same order as the browser, it is empty if containers are disabled
+pub(crate) fn containers() -> impl Future<Output = Result<Vec<Container>, JsValue>> {
+    let fut = JsFuture::from(browser.contextual_identities().query(&Object::new()));
+
+    async move {
+        // Older versions of Firefox return false (or reject) when containers are disabled
+        let identities = match fut.await {
+            Ok(identities) => match identities.dyn_into::<Array>() {
+                Ok(identities) => identities,
+                Err(_) => return Ok(vec![]),
+            },
+            Err(_) => return Ok(vec![]),
+        };
+
+        Ok(identities.iter().map(|identity| {
+            let identity: ContextualIdentity = identity.unchecked_into();
+
+            Container {
+                cookie_store_id: identity.cookie_store_id(),
+                name: identity.name(),
+                color_code: identity.color_code(),
+            }
+        }).collect())
+    }
+}
+
+
+fn send_containers(state: &Rc<RefCell<State>>) {
+    let fut = containers();
+    let state = state.clone();
+
+    spawn(async move {
+        let containers = fut.await?;
+
+        let mut state = state.borrow_mut();
+
+        state.containers = Some(containers.iter().map(|container| container.cookie_store_id.clone()).collect());
+
+        let message = ServerMessage::Containers { containers };
+
+        for window in state.window_ids.values() {
+            window.send_message(&message);
+        }
+
+        Ok(())
+    });
+}
+
+fn on_changed(state: &Rc<RefCell<State>>, event: Event) {
+    let state = state.clone();
+
+    let listener: Listener<dyn FnMut(JsValue)> = Listener::new(event, Closure::new(move |_: JsValue| {
+        send_containers(&state);
+    }));
+
+    listener.forget();
+}
+
+
+/// Sends the containers to every sidebar whenever a container is created, changed, or removed.
+///
+/// It also keeps `State::containers` up to date, so that tabs aren't loaded into a container which doesn't exist.
+pub(crate) fn listen(state: &Rc<RefCell<State>>) {
+    send_containers(state);
+
+    let identities = browser.contextual_identities();
+
+    on_changed(state, identities.on_created());
+    on_changed(state, identities.on_updated());
+    on_changed(state, identities.on_removed());
+}
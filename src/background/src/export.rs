@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use uuid::Uuid;
+use js_sys::Date;
+use wasm_bindgen::prelude::*;
+use tab_organizer::{serialize_str, pretty_date, Database};
+use tab_organizer::keyspace::{self, Record};
+use tab_organizer::state::{SerializedWindow, SerializedTab};
+use tab_organizer::state::options::{ExportFormat, ExportFilter, WindowSummary};
+
+use super::migrate;
+
+
+pub(crate) struct ExportedWindow {
+    // The name is calculated before filtering, so that "Window 2" stays the same when exporting a subset
+    pub(crate) name: String,
+    pub(crate) window: SerializedWindow,
+    pub(crate) tabs: Vec<SerializedTab>,
+}
+
+
+pub(crate) fn read_windows(db: &Database) -> Vec<ExportedWindow> {
+    let windows = keyspace::WINDOWS.get(db).unwrap_or_else(|| vec![]);
+
+    windows.into_iter().filter_map(|uuid| {
+        db.windows().get(uuid)
+
+    }).enumerate().map(|(index, window)| {
+        let name = match &window.name {
+            Some(name) => name.clone(),
+            None => format!("Window {}", index + 1),
+        };
+
+        let tabs = window.tabs.iter().filter_map(|uuid| {
+            db.tabs().get(*uuid)
+        }).collect();
+
+        ExportedWindow { name, window, tabs }
+    }).collect()
+}
+
+
+/// Only keeps the tabs which match, windows which don't have any matching tabs are removed
+pub(crate) fn filter_tabs<F>(windows: Vec<ExportedWindow>, mut f: F) -> Vec<ExportedWindow> where F: FnMut(&SerializedTab) -> bool {
+    windows.into_iter().filter_map(|mut window| {
+        window.tabs.retain(|tab| f(tab));
+
+        if window.tabs.is_empty() {
+            None
+
+        } else {
+            Some(window)
+        }
+    }).collect()
+}
+
+pub(crate) fn filter(windows: Vec<ExportedWindow>, filter: &ExportFilter) -> Vec<ExportedWindow> {
+    match filter {
+        ExportFilter::All => windows,
+        ExportFilter::Window { uuid } => windows.into_iter().filter(|window| window.window.uuid == *uuid).collect(),
+        ExportFilter::Label { name } => filter_tabs(windows, |tab| tab.has_label(name)),
+    }
+}
+
+pub(crate) fn select_tabs(windows: Vec<ExportedWindow>, uuids: &[Uuid]) -> Vec<ExportedWindow> {
+    filter_tabs(windows, |tab| uuids.contains(&tab.uuid))
+}
+
+
+/// The windows and labels which can be chosen in the options page
+pub(crate) fn summary(windows: &[ExportedWindow]) -> (Vec<WindowSummary>, Vec<String>) {
+    let mut labels = BTreeSet::new();
+
+    let windows = windows.into_iter().map(|window| {
+        for tab in window.tabs.iter() {
+            for label in tab.labels.iter() {
+                labels.insert(label.name.clone());
+            }
+        }
+
+        WindowSummary {
+            uuid: window.window.uuid,
+            name: window.name.clone(),
+            tabs: window.tabs.len(),
+        }
+    }).collect();
+
+    (windows, labels.into_iter().collect())
+}
+
+
+fn url(tab: &SerializedTab) -> &str {
+    tab.url.as_deref().unwrap_or("")
+}
+
+fn title(tab: &SerializedTab) -> &str {
+    tab.title.as_deref().or(tab.url.as_deref()).unwrap_or("")
+}
+
+fn iso_date(timestamp: f64) -> String {
+    Date::new(&JsValue::from(timestamp)).to_iso_string().into()
+}
+
+
+fn escape_html(input: &str) -> String {
+    input
+        .replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+// https://docs.microsoft.com/en-us/previous-versions/windows/internet-explorer/ie-developer/platform-apis/aa753582(v=vs.85)
+fn bookmarks_html(windows: &[ExportedWindow]) -> String {
+    let mut output = String::from(
+        "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+         <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+         <TITLE>Bookmarks</TITLE>\n\
+         <H1>Bookmarks</H1>\n\
+         <DL><p>\n"
+    );
+
+    for window in windows {
+        let add_date = (window.window.timestamp_created / 1000.0).floor();
+
+        output.push_str(&format!("    <DT><H3 ADD_DATE=\"{}\">{}</H3>\n", add_date, escape_html(&window.name)));
+        output.push_str("    <DL><p>\n");
+
+        for tab in window.tabs.iter() {
+            let add_date = (tab.timestamp_created / 1000.0).floor();
+
+            let labels: Vec<&str> = tab.labels.iter().map(|label| label.name.as_str()).collect();
+
+            output.push_str(&format!(
+                "        <DT><A HREF=\"{}\" ADD_DATE=\"{}\"{}{}>{}</A>\n",
+                escape_html(url(tab)),
+                add_date,
+                tab.timestamp_focused.map(|x| format!(" LAST_VISIT=\"{}\"", (x / 1000.0).floor())).unwrap_or_else(|| "".to_string()),
+                if labels.is_empty() { "".to_string() } else { format!(" TAGS=\"{}\"", escape_html(&labels.join(","))) },
+                escape_html(title(tab)),
+            ));
+        }
+
+        output.push_str("    </DL><p>\n");
+    }
+
+    output.push_str("</DL><p>\n");
+    output
+}
+
+
+// These would otherwise break the link syntax or add formatting to the title
+fn escape_markdown(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for char in input.chars() {
+        match char {
+            '\\' | '[' | ']' | '(' | ')' | '*' | '_' | '`' => {
+                output.push('\\');
+                output.push(char);
+            },
+            _ => {
+                output.push(char);
+            },
+        }
+    }
+
+    output
+}
+
+// The URL is inside of <>, so it cannot contain <, > or whitespace
+fn escape_markdown_url(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for char in input.chars() {
+        if char == '<' || char == '>' || char.is_whitespace() {
+            for byte in char.encode_utf8(&mut [0; 4]).bytes() {
+                output.push_str(&format!("%{:02X}", byte));
+            }
+
+        } else {
+            output.push(char);
+        }
+    }
+
+    output
+}
+
+fn markdown_tab(output: &mut String, tab: &SerializedTab) {
+    output.push_str(&format!("- [{}](<{}>)\n", escape_markdown(title(tab)), escape_markdown_url(url(tab))));
+}
+
+// Tabs without a label are listed first, just like in the sidebar
+fn markdown(windows: &[ExportedWindow]) -> String {
+    let mut output = String::new();
+
+    for (index, window) in windows.into_iter().enumerate() {
+        if index != 0 {
+            output.push_str("\n");
+        }
+
+        output.push_str(&format!("# {}\n\n", escape_markdown(&window.name)));
+
+        let mut labels: BTreeMap<&str, Vec<&SerializedTab>> = BTreeMap::new();
+
+        for tab in window.tabs.iter() {
+            if tab.labels.is_empty() {
+                markdown_tab(&mut output, tab);
+
+            } else {
+                for label in tab.labels.iter() {
+                    labels.entry(&label.name).or_insert_with(|| vec![]).push(tab);
+                }
+            }
+        }
+
+        for (name, tabs) in labels {
+            output.push_str(&format!("\n## {}\n\n", escape_markdown(name)));
+
+            for tab in tabs {
+                markdown_tab(&mut output, tab);
+            }
+        }
+    }
+
+    output
+}
+
+
+// https://tools.ietf.org/html/rfc4180
+fn escape_csv(input: &str) -> String {
+    if input.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", input.replace("\"", "\"\""))
+
+    } else {
+        input.to_string()
+    }
+}
+
+fn csv(windows: &[ExportedWindow]) -> String {
+    let mut output = String::from("window,title,url,labels,pinned,created,focused\r\n");
+
+    for window in windows {
+        let name = &window.name;
+
+        for tab in window.tabs.iter() {
+            let labels: Vec<&str> = tab.labels.iter().map(|label| label.name.as_str()).collect();
+
+            let row = [
+                escape_csv(name),
+                escape_csv(tab.title.as_deref().unwrap_or("")),
+                escape_csv(url(tab)),
+                escape_csv(&labels.join(";")),
+                tab.pinned.to_string(),
+                iso_date(tab.timestamp_created),
+                tab.timestamp_focused.map(iso_date).unwrap_or_else(|| "".to_string()),
+            ];
+
+            output.push_str(&row.join(","));
+            output.push_str("\r\n");
+        }
+    }
+
+    output
+}
+
+
+fn urls(windows: &[ExportedWindow]) -> String {
+    let mut output = String::new();
+
+    for window in windows {
+        for tab in window.tabs.iter() {
+            if let Some(url) = &tab.url {
+                output.push_str(url);
+                output.push_str("\n");
+            }
+        }
+    }
+
+    output
+}
+
+
+// This uses the same format as Database::to_json, so it can be imported again
+fn json(windows: &[ExportedWindow]) -> String {
+    let mut output: BTreeMap<String, String> = BTreeMap::new();
+
+    output.insert(keyspace::VERSION.name().to_string(), serialize_str(&migrate::LATEST_VERSION));
+
+    output.insert(keyspace::WINDOWS.name().to_string(), serialize_str(&windows.into_iter().map(|window| window.window.uuid).collect::<Vec<Uuid>>()));
+
+    for window in windows {
+        // Only the exported tabs are included in the window
+        let serialized = SerializedWindow {
+            tabs: window.tabs.iter().map(|tab| tab.uuid).collect(),
+            ..window.window.clone()
+        };
+
+        output.insert(SerializedWindow::key(serialized.uuid), serialize_str(&serialized));
+
+        for tab in window.tabs.iter() {
+            output.insert(SerializedTab::key(tab.uuid), serialize_str(tab));
+        }
+    }
+
+    serde_json::to_string_pretty(&output).unwrap()
+}
+
+
+pub(crate) fn export(windows: &[ExportedWindow], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Json => json(windows),
+        ExportFormat::BookmarksHtml => bookmarks_html(windows),
+        ExportFormat::Markdown => markdown(windows),
+        ExportFormat::Csv => csv(windows),
+        ExportFormat::Urls => urls(windows),
+    }
+}
+
+/// Exports the windows and then asks the user where to save the file
+pub(crate) fn download(windows: &[ExportedWindow], format: ExportFormat) -> impl Future<Output = Result<(), JsValue>> {
+    let output = export(windows, format);
+
+    tab_organizer::download(&format!("Tab Organizer ({}).{}", pretty_date(), format.extension()), format.mime_type(), &output)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tab_organizer::state::{SerializedWindow, SerializedTab, Label};
+    use tab_organizer::state::options::ImportFormat;
+    use crate::import;
+    use super::{ExportedWindow, select_tabs, json, markdown};
+
+    const WINDOW_1: &str = "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11";
+    const WINDOW_2: &str = "5d2c4e1a-9b7f-4c3d-8e6a-1f0b2c3d4e5f";
+    const TAB_1: &str = "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c";
+    const TAB_2: &str = "7a1e2b3c-4d5e-4f60-8a7b-9c0d1e2f3a4b";
+    const TAB_3: &str = "e3b0c442-98fc-4c14-9afb-f4c8996fb924";
+
+    fn tab(uuid: &str, url: &str, title: &str, labels: &[&str]) -> SerializedTab {
+        let mut tab = SerializedTab::new(uuid.parse().unwrap(), 1.0);
+
+        tab.url = Some(url.to_string());
+        tab.title = Some(title.to_string());
+        tab.labels = labels.into_iter().map(|name| Label { name: name.to_string(), timestamp_added: 2.0 }).collect();
+
+        tab
+    }
+
+    fn window(uuid: &str, name: &str, tabs: Vec<SerializedTab>) -> ExportedWindow {
+        let mut window = SerializedWindow::new(uuid.parse().unwrap(), 1.0);
+
+        window.name = Some(name.to_string());
+        window.tabs = tabs.iter().map(|tab| tab.uuid).collect();
+
+        ExportedWindow { name: name.to_string(), window, tabs }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut pinned = tab(TAB_1, "https://example.com/", "Example", &["work"]);
+        pinned.pinned = true;
+        pinned.collapsed = true;
+        pinned.timestamp_focused = Some(3.0);
+        pinned.cookie_store_id = Some("firefox-container-1".to_string());
+
+        let windows = vec![
+            window(WINDOW_1, "First", vec![
+                pinned,
+                tab(TAB_2, "https://example.org/", "Not exported", &[]),
+            ]),
+            window(WINDOW_2, "Second", vec![
+                tab(TAB_3, "https://example.net/", "Other", &["work", "read later"]),
+            ]),
+        ];
+
+        // The window only contains the exported tabs
+        let windows = select_tabs(windows, &[TAB_1.parse().unwrap(), TAB_3.parse().unwrap()]);
+
+        let (format, imported) = import::parse(&json(&windows), 1.0).unwrap();
+
+        assert_eq!(format, ImportFormat::TabOrganizer);
+        assert_eq!(imported.len(), 2);
+
+        for (exported, imported) in windows.iter().zip(imported.iter()) {
+            assert_eq!(imported.window.uuid, exported.window.uuid);
+            assert_eq!(imported.window.name, exported.window.name);
+            assert_eq!(imported.window.tabs, exported.tabs.iter().map(|tab| tab.uuid).collect::<Vec<_>>());
+
+            assert_eq!(imported.tabs.len(), exported.tabs.len());
+
+            for (exported, imported) in exported.tabs.iter().zip(imported.tabs.iter()) {
+                assert_eq!(serde_json::to_value(imported).unwrap(), serde_json::to_value(exported).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn markdown_escaping() {
+        let windows = vec![
+            window(WINDOW_1, "Work [1]", vec![
+                tab(TAB_1, "https://example.com/a b", r"a_b *c* `d` (e) [f] \", &[]),
+                tab(TAB_2, "https://example.org/(x)", "Labeled", &["x_y"]),
+            ]),
+        ];
+
+        assert_eq!(markdown(&windows), concat!(
+            "# Work \\[1\\]\n",
+            "\n",
+            "- [a\\_b \\*c\\* \\`d\\` \\(e\\) \\[f\\] \\\\](<https://example.com/a%20b>)\n",
+            "\n",
+            "## x\\_y\n",
+            "\n",
+            "- [Labeled](<https://example.org/(x)>)\n",
+        ));
+    }
+}
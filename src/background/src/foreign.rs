@@ -0,0 +1,520 @@
+use serde_derive::Deserialize;
+use tab_organizer::generate_uuid;
+use tab_organizer::state::{SerializedWindow, SerializedTab, Label};
+use tab_organizer::state::options::{ImportFormat, ImportError};
+
+use super::import::ImportedWindow;
+
+
+struct ForeignTab {
+    url: String,
+    title: Option<String>,
+    favicon_url: Option<String>,
+    pinned: bool,
+    timestamp_focused: Option<f64>,
+}
+
+impl ForeignTab {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            title: None,
+            favicon_url: None,
+            pinned: false,
+            timestamp_focused: None,
+        }
+    }
+}
+
+
+// Every tab and window gets a fresh UUID
+fn make_window<F>(name: Option<String>, tabs: Vec<ForeignTab>, timestamp_created: f64, mut f: F) -> ImportedWindow where F: FnMut(&mut SerializedTab) {
+    let tabs: Vec<SerializedTab> = tabs.into_iter().map(|tab| {
+        let mut serialized = SerializedTab::new(generate_uuid(), timestamp_created);
+
+        serialized.url = Some(tab.url);
+        serialized.title = tab.title;
+        serialized.favicon_url = tab.favicon_url;
+        serialized.pinned = tab.pinned;
+        serialized.timestamp_focused = tab.timestamp_focused;
+
+        f(&mut serialized);
+
+        serialized
+    }).collect();
+
+    let mut window = SerializedWindow::new(generate_uuid(), timestamp_created);
+
+    window.name = name;
+    window.tabs = tabs.iter().map(|tab| tab.uuid).collect();
+
+    ImportedWindow { window, tabs }
+}
+
+// The tabs are labeled with the name of the collection they came from
+fn make_labeled_window(name: String, tabs: Vec<ForeignTab>, timestamp_created: f64) -> ImportedWindow {
+    let label = Label {
+        name: name.clone(),
+        timestamp_added: timestamp_created,
+    };
+
+    make_window(Some(name), tabs, timestamp_created, move |tab| {
+        tab.labels.push(label.clone());
+    })
+}
+
+
+fn non_empty(windows: Vec<ImportedWindow>) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let windows: Vec<ImportedWindow> = windows.into_iter().filter(|window| !window.tabs.is_empty()).collect();
+
+    if windows.is_empty() {
+        Err(vec![ImportError::NoTabs])
+
+    } else {
+        Ok(windows)
+    }
+}
+
+
+/// Guesses the format based on the contents, it does not fully validate the data
+pub(crate) fn detect(data: &str) -> ImportFormat {
+    if data.trim_start().starts_with("{") {
+        match serde_json::from_str::<serde_json::Value>(data) {
+            Ok(serde_json::Value::Object(object)) => {
+                if object.contains_key("collections") || object.contains_key("sessions") {
+                    ImportFormat::SessionBuddy
+
+                // Firefox uses ["sessionrestore", 1] for the version
+                } else if object.get("version").map(|version| version.is_array()).unwrap_or(false) {
+                    ImportFormat::FirefoxSession
+
+                } else {
+                    ImportFormat::TabOrganizer
+                }
+            },
+
+            // Invalid JSON is reported by the Tab Organizer importer
+            _ => ImportFormat::TabOrganizer,
+        }
+
+    } else if data.lines().any(|line| line.contains(" | ")) {
+        ImportFormat::OneTab
+
+    } else {
+        ImportFormat::Urls
+    }
+}
+
+
+// OneTab exports one tab per line as `url | title`, and the groups are separated by blank lines
+pub(crate) fn onetab(data: &str, timestamp_created: f64) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let mut groups: Vec<Vec<ForeignTab>> = vec![vec![]];
+
+    for line in data.lines() {
+        if line.trim().is_empty() {
+            if !groups.last().unwrap().is_empty() {
+                groups.push(vec![]);
+            }
+
+        } else {
+            // This isn't trimmed first, because tabs without a title end with " | "
+            let mut parts = line.splitn(2, " | ");
+
+            let mut tab = ForeignTab::new(parts.next().unwrap().trim().to_string());
+
+            tab.title = parts.next().map(|title| title.trim().to_string()).filter(|title| !title.is_empty());
+
+            groups.last_mut().unwrap().push(tab);
+        }
+    }
+
+    non_empty(groups.into_iter().enumerate().map(|(index, tabs)| {
+        make_labeled_window(format!("OneTab {}", index + 1), tabs, timestamp_created)
+    }).collect())
+}
+
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionBuddyTab {
+    url: String,
+    title: Option<String>,
+    fav_icon_url: Option<String>,
+    #[serde(default)]
+    pinned: bool,
+}
+
+#[derive(Deserialize)]
+struct SessionBuddyWindow {
+    #[serde(default)]
+    tabs: Vec<SessionBuddyTab>,
+}
+
+#[derive(Deserialize)]
+struct SessionBuddyCollection {
+    #[serde(alias = "name")]
+    title: Option<String>,
+    #[serde(default)]
+    windows: Vec<SessionBuddyWindow>,
+}
+
+// Older versions of Session Buddy call them sessions rather than collections
+#[derive(Deserialize)]
+struct SessionBuddyExport {
+    #[serde(default, alias = "sessions")]
+    collections: Vec<SessionBuddyCollection>,
+}
+
+pub(crate) fn session_buddy(data: &str, timestamp_created: f64) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let export: SessionBuddyExport = serde_json::from_str(data).map_err(|error| {
+        vec![ImportError::InvalidJson { message: error.to_string() }]
+    })?;
+
+    let mut windows = vec![];
+
+    for (index, collection) in export.collections.into_iter().enumerate() {
+        let name = collection.title
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| format!("Session Buddy {}", index + 1));
+
+        for window in collection.windows {
+            windows.push(make_labeled_window(name.clone(), window.tabs.into_iter().map(|tab| {
+                ForeignTab {
+                    url: tab.url,
+                    title: tab.title,
+                    favicon_url: tab.fav_icon_url,
+                    pinned: tab.pinned,
+                    timestamp_focused: None,
+                }
+            }).collect(), timestamp_created));
+        }
+    }
+
+    non_empty(windows)
+}
+
+
+pub(crate) fn urls(data: &str, timestamp_created: f64) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let tabs = data.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| ForeignTab::new(line.to_string()))
+        .collect();
+
+    non_empty(vec![make_labeled_window("Imported URLs".to_string(), tabs, timestamp_created)])
+}
+
+
+#[derive(Deserialize)]
+struct FirefoxEntry {
+    url: String,
+    title: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FirefoxTab {
+    #[serde(default)]
+    entries: Vec<FirefoxEntry>,
+    // This is 1-based
+    index: Option<usize>,
+    last_accessed: Option<f64>,
+    #[serde(default)]
+    pinned: bool,
+    image: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FirefoxWindow {
+    #[serde(default)]
+    tabs: Vec<FirefoxTab>,
+}
+
+// TODO import _closedWindows as well ?
+#[derive(Deserialize)]
+struct FirefoxSession {
+    #[serde(default)]
+    windows: Vec<FirefoxWindow>,
+}
+
+impl FirefoxTab {
+    fn into_foreign(mut self) -> Option<ForeignTab> {
+        let len = self.entries.len();
+
+        // The tab's current page is the entry at the index, every other entry is the history
+        let index = self.index.unwrap_or(len).min(len).checked_sub(1)?;
+
+        let entry = self.entries.swap_remove(index);
+
+        Some(ForeignTab {
+            url: entry.url,
+            title: entry.title,
+            favicon_url: self.image,
+            pinned: self.pinned,
+            timestamp_focused: self.last_accessed,
+        })
+    }
+}
+
+// This is the decompressed contents of sessionstore.jsonlz4 or the files in sessionstore-backups
+pub(crate) fn firefox_session(data: &str, timestamp_created: f64) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let session: FirefoxSession = serde_json::from_str(data).map_err(|error| {
+        vec![ImportError::InvalidJson { message: error.to_string() }]
+    })?;
+
+    non_empty(session.windows.into_iter().map(|window| {
+        let tabs = window.tabs.into_iter().filter_map(FirefoxTab::into_foreign).collect();
+        make_window(None, tabs, timestamp_created, |_| {})
+    }).collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tab_organizer::state::options::ImportFormat;
+    use super::{detect, onetab, session_buddy, urls, firefox_session};
+    use crate::import::ImportedWindow;
+
+    fn urls_of(window: &ImportedWindow) -> Vec<&str> {
+        window.tabs.iter().map(|tab| tab.url.as_deref().unwrap()).collect()
+    }
+
+    fn labels_of(window: &ImportedWindow) -> Vec<&str> {
+        window.tabs.iter().flat_map(|tab| tab.labels.iter().map(|label| label.name.as_str())).collect()
+    }
+
+    const ONETAB: &str = "\
+https://www.rust-lang.org/ | Rust Programming Language
+https://docs.rs/ | Docs.rs
+
+https://example.com/ | \n\
+https://news.ycombinator.com/ | Hacker News
+";
+
+    const SESSION_BUDDY: &str = r#"{
+        "format": "nxs.json.v1",
+        "created": 1577836800000,
+        "collections": [
+            {
+                "id": "c1",
+                "title": "Research",
+                "windows": [
+                    {
+                        "tabs": [
+                            { "id": 1, "title": "Rust", "url": "https://www.rust-lang.org/", "favIconUrl": "https://www.rust-lang.org/favicon.ico", "pinned": true },
+                            { "id": 2, "title": "Docs.rs", "url": "https://docs.rs/" }
+                        ]
+                    },
+                    {
+                        "tabs": [
+                            { "id": 3, "url": "https://example.com/" }
+                        ]
+                    }
+                ]
+            },
+            {
+                "id": "c2",
+                "title": "",
+                "windows": [
+                    { "tabs": [{ "id": 4, "title": "Hacker News", "url": "https://news.ycombinator.com/" }] }
+                ]
+            }
+        ]
+    }"#;
+
+    const URLS: &str = "
+https://www.rust-lang.org/
+  https://docs.rs/
+
+https://example.com/
+";
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(detect(ONETAB), ImportFormat::OneTab);
+        assert_eq!(detect(SESSION_BUDDY), ImportFormat::SessionBuddy);
+        assert_eq!(detect(URLS), ImportFormat::Urls);
+        assert_eq!(detect(r#"{ "version": 5, "windows": [] }"#), ImportFormat::TabOrganizer);
+        assert_eq!(detect(r#"{ "version": ["sessionrestore", 1], "windows": [] }"#), ImportFormat::FirefoxSession);
+    }
+
+    #[test]
+    fn detect_ambiguous() {
+        // Session Buddy is checked before the Firefox version
+        assert_eq!(detect(r#"{ "version": ["sessionrestore", 1], "sessions": [] }"#), ImportFormat::SessionBuddy);
+
+        // Invalid JSON is reported by the Tab Organizer importer, rather than being imported as URLs
+        assert_eq!(detect("{ https://example.com/"), ImportFormat::TabOrganizer);
+
+        // A single `url | title` line makes the whole list OneTab, but the other lines are still imported as URLs
+        let data = "https://example.com/\nhttps://example.org/ | Example\n";
+
+        assert_eq!(detect(data), ImportFormat::OneTab);
+
+        let windows = onetab(data, 1.0).ok().unwrap();
+        assert_eq!(windows.len(), 1);
+        assert_eq!(urls_of(&windows[0]), vec!["https://example.com/", "https://example.org/"]);
+        assert_eq!(windows[0].tabs[0].title, None);
+        assert_eq!(windows[0].tabs[1].title.as_deref(), Some("Example"));
+    }
+
+    #[test]
+    fn parse_onetab() {
+        let windows = onetab(ONETAB, 1.0).ok().unwrap();
+
+        assert_eq!(windows.len(), 2);
+
+        assert_eq!(windows[0].window.name.as_deref(), Some("OneTab 1"));
+        assert_eq!(urls_of(&windows[0]), vec!["https://www.rust-lang.org/", "https://docs.rs/"]);
+        assert_eq!(windows[0].tabs[0].title.as_deref(), Some("Rust Programming Language"));
+        assert_eq!(labels_of(&windows[0]), vec!["OneTab 1", "OneTab 1"]);
+
+        assert_eq!(windows[1].window.name.as_deref(), Some("OneTab 2"));
+        assert_eq!(urls_of(&windows[1]), vec!["https://example.com/", "https://news.ycombinator.com/"]);
+        assert_eq!(windows[1].tabs[0].title, None);
+
+        for window in windows.iter() {
+            assert_eq!(window.window.tabs, window.tabs.iter().map(|tab| tab.uuid).collect::<Vec<_>>());
+            assert_eq!(window.window.timestamp_created, 1.0);
+        }
+    }
+
+    #[test]
+    fn parse_session_buddy() {
+        let windows = session_buddy(SESSION_BUDDY, 1.0).ok().unwrap();
+
+        assert_eq!(windows.len(), 3);
+
+        assert_eq!(windows[0].window.name.as_deref(), Some("Research"));
+        assert_eq!(urls_of(&windows[0]), vec!["https://www.rust-lang.org/", "https://docs.rs/"]);
+        assert!(windows[0].tabs[0].pinned);
+        assert!(!windows[0].tabs[1].pinned);
+        assert_eq!(windows[0].tabs[0].favicon_url.as_deref(), Some("https://www.rust-lang.org/favicon.ico"));
+
+        // Every window in a collection gets the collection's label
+        assert_eq!(windows[1].window.name.as_deref(), Some("Research"));
+        assert_eq!(labels_of(&windows[1]), vec!["Research"]);
+
+        // Collections without a title are named after their position
+        assert_eq!(windows[2].window.name.as_deref(), Some("Session Buddy 2"));
+        assert_eq!(labels_of(&windows[2]), vec!["Session Buddy 2"]);
+    }
+
+    #[test]
+    fn parse_urls() {
+        let windows = urls(URLS, 1.0).ok().unwrap();
+
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].window.name.as_deref(), Some("Imported URLs"));
+        assert_eq!(urls_of(&windows[0]), vec!["https://www.rust-lang.org/", "https://docs.rs/", "https://example.com/"]);
+        assert_eq!(labels_of(&windows[0]), vec!["Imported URLs", "Imported URLs", "Imported URLs"]);
+    }
+
+    // This is trimmed down from sessionstore-backups/recovery.jsonlz4, after decompressing it
+    const FIREFOX_SESSION: &str = r#"{
+        "version": ["sessionrestore", 1],
+        "windows": [
+            {
+                "selected": 2,
+                "tabs": [
+                    {
+                        "entries": [
+                            { "url": "https://www.rust-lang.org/", "title": "Rust Programming Language" }
+                        ],
+                        "index": 1,
+                        "lastAccessed": 1577836800000,
+                        "pinned": true,
+                        "hidden": false,
+                        "image": "https://www.rust-lang.org/favicon.ico"
+                    },
+                    {
+                        "entries": [
+                            { "url": "https://example.com/", "title": "Example" },
+                            { "url": "https://example.com/page", "title": "Page" },
+                            { "url": "https://example.com/next", "title": "Next" }
+                        ],
+                        "index": 2,
+                        "lastAccessed": 1577836900000
+                    },
+                    {
+                        "entries": [
+                            { "url": "https://docs.rs/" },
+                            { "url": "https://docs.rs/serde" }
+                        ]
+                    },
+                    {
+                        "entries": [],
+                        "index": 1
+                    }
+                ]
+            },
+            {
+                "tabs": [
+                    {
+                        "entries": [{ "url": "about:blank" }],
+                        "index": 5
+                    }
+                ]
+            },
+            {
+                "tabs": []
+            }
+        ],
+        "_closedWindows": [],
+        "session": { "lastUpdate": 1577837000000 }
+    }"#;
+
+    #[test]
+    fn parse_firefox_session() {
+        assert_eq!(detect(FIREFOX_SESSION), ImportFormat::FirefoxSession);
+
+        let windows = firefox_session(FIREFOX_SESSION, 1.0).ok().unwrap();
+
+        // Windows without any tabs are skipped
+        assert_eq!(windows.len(), 2);
+
+        let window = &windows[0];
+
+        assert_eq!(window.window.name, None);
+        assert_eq!(window.window.tabs, window.tabs.iter().map(|tab| tab.uuid).collect::<Vec<_>>());
+
+        // Tabs without any entries are skipped
+        assert_eq!(urls_of(window), vec!["https://www.rust-lang.org/", "https://example.com/page", "https://docs.rs/serde"]);
+
+        let pinned = &window.tabs[0];
+        assert!(pinned.pinned);
+        assert_eq!(pinned.title.as_deref(), Some("Rust Programming Language"));
+        assert_eq!(pinned.favicon_url.as_deref(), Some("https://www.rust-lang.org/favicon.ico"));
+        assert_eq!(pinned.timestamp_focused, Some(1577836800000.0));
+
+        // The selected index is 1-based, and the entries after it are the forward history
+        let selected = &window.tabs[1];
+        assert!(!selected.pinned);
+        assert_eq!(selected.title.as_deref(), Some("Page"));
+        assert_eq!(selected.timestamp_focused, Some(1577836900000.0));
+
+        // Without an index the last entry is used
+        let last = &window.tabs[2];
+        assert_eq!(last.title, None);
+        assert_eq!(last.timestamp_focused, None);
+
+        // Foreign tabs are not labeled
+        assert!(window.tabs.iter().all(|tab| tab.labels.is_empty()));
+
+        // An index which is too big is clamped to the last entry
+        assert_eq!(urls_of(&windows[1]), vec!["about:blank"]);
+    }
+
+    #[test]
+    fn invalid_firefox_session() {
+        assert!(firefox_session(r#"{ "version": ["sessionrestore", 1], "windows": [{ "tabs": [{ "entries": [] }] }] }"#, 1.0).is_err());
+        assert!(firefox_session(r#"{ "version": ["sessionrestore", 1], "windows": "#, 1.0).is_err());
+    }
+
+    #[test]
+    fn no_tabs() {
+        assert!(urls("\n  \n", 1.0).is_err());
+        assert!(onetab("", 1.0).is_err());
+        assert!(session_buddy(r#"{ "collections": [{ "title": "Empty", "windows": [{ "tabs": [] }] }] }"#, 1.0).is_err());
+    }
+}
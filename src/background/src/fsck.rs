@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use js_sys::Date;
+use tab_organizer::{generate_uuid, time, Database};
+use tab_organizer::keyspace::{self, Record};
+use tab_organizer::state::{SerializedWindow, SerializedTab};
+use tab_organizer::state::options::DatabaseProblem;
+
+
+pub(crate) struct Report {
+    pub(crate) problems: Vec<DatabaseProblem>,
+    pub(crate) repaired: usize,
+}
+
+
+struct Checker<'a> {
+    db: &'a Database,
+    problems: Vec<DatabaseProblem>,
+    repaired: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn report(&mut self, problem: DatabaseProblem, repaired: bool) {
+        self.problems.push(problem);
+
+        if repaired {
+            self.repaired += 1;
+        }
+    }
+
+    fn invalid(&mut self, key: &str, message: String) {
+        self.report(DatabaseProblem::InvalidRecord { key: key.to_string(), message }, false);
+    }
+
+    // This is like Records::uuids, except it reports the keys which aren't valid UUIDs
+    fn uuids<T>(&mut self) -> Vec<Uuid> where T: Record {
+        let mut keys = self.db.keys_with_prefix(T::PREFIX);
+
+        // This makes the order of the problems deterministic
+        keys.sort();
+
+        keys.into_iter().filter_map(|key| {
+            match key[T::PREFIX.len()..].parse::<Uuid>() {
+                Ok(uuid) => Some(uuid),
+                Err(error) => {
+                    self.invalid(&key, error.to_string());
+                    None
+                },
+            }
+        }).collect()
+    }
+
+    fn window_list(&mut self) -> Vec<Uuid> {
+        match keyspace::WINDOWS.try_get(self.db) {
+            None => vec![],
+            Some(Ok(windows)) => windows,
+            Some(Err(error)) => {
+                self.invalid(keyspace::WINDOWS.name(), error.to_string());
+                vec![]
+            },
+        }
+    }
+}
+
+
+/// Checks that the `windows`, `window-ids.*` and `tab-ids.*` records are consistent with each other.
+///
+/// If `repair` is true then it also fixes the problems, except for the windows in `skip`. That is
+/// used for the windows which are open in the browser, because their records are owned by `State`.
+pub(crate) fn check(db: &Database, repair: bool, skip: &HashSet<Uuid>) -> Report {
+    time!("Checking database", {
+        let mut checker = Checker {
+            db,
+            problems: vec![],
+            repaired: 0,
+        };
+
+        let old_list = checker.window_list();
+
+        let mut list: Vec<Uuid> = vec![];
+
+        for window_uuid in old_list.iter().cloned() {
+            if list.contains(&window_uuid) {
+                checker.report(DatabaseProblem::DuplicateWindow { window_uuid }, repair);
+
+            } else if !db.windows().contains(window_uuid) {
+                checker.report(DatabaseProblem::MissingWindow { window_uuid }, repair);
+
+            } else {
+                list.push(window_uuid);
+            }
+        }
+
+        for window_uuid in checker.uuids::<SerializedWindow>() {
+            if !list.contains(&window_uuid) {
+                checker.report(DatabaseProblem::OrphanWindow { window_uuid }, repair);
+                list.push(window_uuid);
+            }
+        }
+
+        let mut seen: HashSet<Uuid> = HashSet::new();
+        let mut changed: HashMap<Uuid, SerializedWindow> = HashMap::new();
+
+        // If a window can't be read then its tabs would look like orphans, so they must not be moved
+        let mut is_complete = true;
+
+        for window_uuid in list.iter().cloned() {
+            let mut window = match db.windows().try_get(window_uuid) {
+                Some(Ok(window)) => window,
+                Some(Err(error)) => {
+                    checker.invalid(&SerializedWindow::key(window_uuid), error.to_string());
+                    is_complete = false;
+                    continue;
+                },
+                None => unreachable!(),
+            };
+
+            let repair_window = repair && !skip.contains(&window_uuid);
+
+            let old_len = window.tabs.len();
+
+            window.tabs.retain(|tab_uuid| {
+                let tab_uuid = *tab_uuid;
+
+                let problem = if seen.contains(&tab_uuid) {
+                    Some(DatabaseProblem::DuplicateTab { window_uuid, tab_uuid })
+
+                } else if !db.tabs().contains(tab_uuid) {
+                    Some(DatabaseProblem::MissingTab { window_uuid, tab_uuid })
+
+                } else {
+                    None
+                };
+
+                seen.insert(tab_uuid);
+
+                match problem {
+                    Some(problem) => {
+                        checker.report(problem, repair_window);
+                        !repair_window
+                    },
+                    None => true,
+                }
+            });
+
+            if window.tabs.len() != old_len {
+                changed.insert(window_uuid, window);
+            }
+        }
+
+        let mut orphans: Vec<Uuid> = vec![];
+
+        for tab_uuid in checker.uuids::<SerializedTab>() {
+            // Tabs which aren't loaded can't be validated, they are only checked for whether they exist
+            let result = if db.tabs().is_loaded(tab_uuid) {
+                db.tabs().try_get(tab_uuid)
+
+            } else {
+                None
+            };
+
+            if let Some(Err(error)) = result {
+                checker.invalid(&SerializedTab::key(tab_uuid), error.to_string());
+
+            } else if !seen.contains(&tab_uuid) {
+                checker.report(DatabaseProblem::OrphanTab { tab_uuid }, repair && is_complete);
+                orphans.push(tab_uuid);
+            }
+        }
+
+        if repair {
+            for (uuid, window) in changed {
+                db.windows().set(uuid, &window);
+            }
+
+            // The orphan tabs are moved into a new unloaded window, rather than deleting them
+            if is_complete && !orphans.is_empty() {
+                let mut window = SerializedWindow::new(generate_uuid(), Date::now());
+
+                window.name = Some("Recovered tabs".to_string());
+                window.tabs = orphans;
+
+                db.windows().set(window.uuid, &window);
+
+                list.push(window.uuid);
+            }
+
+            if list != old_list {
+                keyspace::WINDOWS.set(db, &list);
+            }
+        }
+
+        Report {
+            problems: checker.problems,
+            repaired: checker.repaired,
+        }
+    })
+}
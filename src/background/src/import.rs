@@ -0,0 +1,361 @@
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use tab_organizer::{generate_uuid, Database};
+use tab_organizer::keyspace::{self, Record};
+use tab_organizer::storage::MemoryStorage;
+use tab_organizer::state::{Tab, SerializedWindow, SerializedTab, sidebar};
+use tab_organizer::state::options::{ImportMode, ImportError, ImportFormat, WindowSummary};
+use tab_organizer::browser::Id;
+
+use super::{State, BrowserWindow};
+use super::{migrate, foreign};
+
+
+pub(crate) struct ImportedWindow {
+    pub(crate) window: SerializedWindow,
+    pub(crate) tabs: Vec<SerializedTab>,
+}
+
+
+// This only contains the fields which exist in every version, so it can be checked before migrating
+#[derive(Deserialize)]
+struct WindowTabs {
+    tabs: Vec<Uuid>,
+}
+
+
+fn get_record<T>(db: &Database, key: &str, errors: &mut Vec<ImportError>) -> Option<T> where T: DeserializeOwned {
+    match db.try_get::<T>(key)? {
+        Ok(value) => Some(value),
+        Err(error) => {
+            errors.push(ImportError::InvalidRecord { key: key.to_string(), message: error.to_string() });
+            None
+        },
+    }
+}
+
+
+fn check_version(db: &Database) -> Result<u32, ImportError> {
+    match keyspace::VERSION.try_get(db) {
+        None => Err(ImportError::MissingVersion),
+        Some(Err(error)) => Err(ImportError::InvalidRecord { key: keyspace::VERSION.name().to_string(), message: error.to_string() }),
+        Some(Ok(version)) => {
+            if version == 0 || version > migrate::LATEST_VERSION {
+                Err(ImportError::UnsupportedVersion { version })
+
+            } else {
+                Ok(version)
+            }
+        },
+    }
+}
+
+
+// Verifies that every window and tab which is referenced actually exists
+fn check_keys(db: &Database, errors: &mut Vec<ImportError>) {
+    let windows = if keyspace::WINDOWS.contains(db) {
+        get_record::<Vec<Uuid>>(db, keyspace::WINDOWS.name(), errors)
+
+    } else {
+        errors.push(ImportError::MissingWindows);
+        None
+    };
+
+    for window_uuid in windows.unwrap_or_else(|| vec![]) {
+        // The window might be an old version, so this uses the raw key
+        let key = SerializedWindow::key(window_uuid);
+
+        if !db.contains(&key) {
+            errors.push(ImportError::MissingWindow { window_uuid });
+
+        } else if let Some(window) = get_record::<WindowTabs>(db, &key, errors) {
+            for tab_uuid in window.tabs {
+                if !has_tab(db, tab_uuid) {
+                    errors.push(ImportError::MissingTab { window_uuid, tab_uuid });
+                }
+            }
+        }
+    }
+}
+
+
+fn read_windows(db: &Database, errors: &mut Vec<ImportError>) -> Vec<ImportedWindow> {
+    let windows = keyspace::WINDOWS.get(db).unwrap();
+
+    windows.into_iter().filter_map(|id| {
+        let window = get_record::<SerializedWindow>(db, &SerializedWindow::key(id), errors)?;
+
+        // This uses Records so that the compact format is also supported
+        let tabs = window.tabs.iter().filter_map(|id| {
+            match db.tabs().try_get(*id)? {
+                Ok(tab) => Some(tab),
+                Err(error) => {
+                    errors.push(ImportError::InvalidRecord { key: SerializedTab::key(*id), message: error.to_string() });
+                    None
+                },
+            }
+        }).collect();
+
+        Some(ImportedWindow { window, tabs })
+    }).collect()
+}
+
+
+// Validates an exported database and migrates it to the latest version
+fn parse_database(data: &str) -> Result<Vec<ImportedWindow>, Vec<ImportError>> {
+    let values = serde_json::from_str(data).map_err(|error| {
+        vec![ImportError::InvalidJson { message: error.to_string() }]
+    })?;
+
+    let db = Database::new_in_memory(MemoryStorage::from_values(values));
+
+    let version = check_version(&db).map_err(|error| vec![error])?;
+
+    let mut errors = vec![];
+
+    check_keys(&db, &mut errors);
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if version != migrate::LATEST_VERSION {
+        // The database is thrown away afterwards, so it doesn't need snapshots
+        let failures = migrate::migrate_from(&db, version, false);
+
+        // Importing must not silently lose tabs, so it fails instead
+        if !failures.is_empty() {
+            return Err(failures.into_iter().map(|failure| {
+                ImportError::InvalidRecord { key: failure.key, message: failure.message }
+            }).collect());
+        }
+    }
+
+    let windows = read_windows(&db, &mut errors);
+
+    if errors.is_empty() {
+        Ok(windows)
+
+    } else {
+        Err(errors)
+    }
+}
+
+
+/// Detects the format of the data and then parses it, `timestamp_created` is used for the tabs and windows of foreign formats.
+///
+/// It does not modify the live database, use `import` for that.
+pub(crate) fn parse(data: &str, timestamp_created: f64) -> Result<(ImportFormat, Vec<ImportedWindow>), Vec<ImportError>> {
+    let format = foreign::detect(data);
+
+    let windows = match format {
+        ImportFormat::TabOrganizer => parse_database(data),
+        ImportFormat::OneTab => foreign::onetab(data, timestamp_created),
+        ImportFormat::SessionBuddy => foreign::session_buddy(data, timestamp_created),
+        ImportFormat::FirefoxSession => foreign::firefox_session(data, timestamp_created),
+        ImportFormat::Urls => foreign::urls(data, timestamp_created),
+    }?;
+
+    Ok((format, windows))
+}
+
+
+/// The windows and labels which will be imported, so the user can check them before importing
+pub(crate) fn preview(windows: &[ImportedWindow]) -> (Vec<WindowSummary>, Vec<String>) {
+    let mut labels = BTreeSet::new();
+
+    let windows = windows.into_iter().enumerate().map(|(index, window)| {
+        for tab in window.tabs.iter() {
+            for label in tab.labels.iter() {
+                labels.insert(label.name.clone());
+            }
+        }
+
+        WindowSummary {
+            uuid: window.window.uuid,
+            name: window.window.name.clone().unwrap_or_else(|| format!("Window {}", index + 1)),
+            tabs: window.tabs.len(),
+        }
+    }).collect();
+
+    (windows, labels.into_iter().collect())
+}
+
+
+fn loaded_window(window_ids: &mut HashMap<Id, BrowserWindow>, uuid: Uuid) -> Option<&mut BrowserWindow> {
+    window_ids.values_mut().find(|window| window.serialized.uuid == uuid)
+}
+
+fn has_tab(db: &Database, uuid: Uuid) -> bool {
+    db.tabs().contains(uuid)
+}
+
+// Imported tabs must never overwrite the tabs which already exist
+fn fresh_tab(db: &Database, mut tab: SerializedTab) -> SerializedTab {
+    if has_tab(db, tab.uuid) {
+        tab.uuid = generate_uuid();
+    }
+
+    tab
+}
+
+fn remove_window(db: &Database, uuid: Uuid) {
+    if let Some(window) = db.windows().get(uuid) {
+        for uuid in window.tabs {
+            db.tabs().remove(uuid);
+        }
+    }
+
+    db.windows().remove(uuid);
+}
+
+// Adds the window into the database, it does not exist in the browser so it is unloaded
+fn insert_window(db: &Database, window_ids: &mut Vec<Uuid>, mut window: SerializedWindow, tabs: Vec<SerializedTab>) {
+    if window_ids.contains(&window.uuid) {
+        window.uuid = generate_uuid();
+    }
+
+    window.tabs = tabs.into_iter().map(|tab| {
+        let tab = fresh_tab(db, tab);
+        db.tabs().set(tab.uuid, &tab);
+        tab.uuid
+    }).collect();
+
+    db.windows().set(window.uuid, &window);
+
+    window_ids.push(window.uuid);
+}
+
+// Adds the tabs to the end of a window which exists in the browser
+fn append_tabs(db: &Database, window: &mut BrowserWindow, tabs: Vec<SerializedTab>) {
+    for tab in tabs {
+        let tab = fresh_tab(db, tab);
+
+        db.tabs().set(tab.uuid, &tab);
+
+        let tab_index = window.serialized.tabs.len();
+
+        window.serialized.tabs.push(tab.uuid);
+
+        window.send_message(&sidebar::ServerMessage::TabInserted {
+            tab_index,
+            tab: Tab::unloaded(tab),
+        });
+    }
+
+    window.serialize(db);
+}
+
+fn merge_labels(state: &mut State, tab: &SerializedTab) {
+    let labels = &tab.labels;
+
+    let changes = state.update_tabs_serialized(&[tab.uuid], move |old| {
+        let mut changes = vec![];
+
+        for label in labels {
+            if !old.has_label(&label.name) {
+                old.add_label(label.clone());
+                changes.push(sidebar::TabChange::AddedToLabel { label: label.clone() });
+            }
+        }
+
+        if changes.is_empty() {
+            None
+
+        } else {
+            Some(changes)
+        }
+    });
+
+    state.send_tab_changes(changes);
+}
+
+fn merge_window(state: &mut State, window_ids: &mut Vec<Uuid>, window: SerializedWindow, tabs: Vec<SerializedTab>) {
+    let (existing, new): (Vec<SerializedTab>, Vec<SerializedTab>) = tabs.into_iter().partition(|tab| has_tab(&state.db, tab.uuid));
+
+    for tab in existing.iter() {
+        merge_labels(state, tab);
+    }
+
+    if let Some(browser_window) = loaded_window(&mut state.window_ids, window.uuid) {
+        append_tabs(&state.db, browser_window, new);
+
+    } else if window_ids.contains(&window.uuid) {
+        let mut serialized = state.db.windows().get(window.uuid).unwrap();
+
+        for tab in new {
+            state.db.tabs().set(tab.uuid, &tab);
+            serialized.tabs.push(tab.uuid);
+        }
+
+        state.db.windows().set(serialized.uuid, &serialized);
+
+    } else {
+        insert_window(&state.db, window_ids, window, new);
+    }
+}
+
+
+/// Writes the imported windows into the live database, returns the number of imported tabs
+pub(crate) fn import(state: &mut State, windows: Vec<ImportedWindow>, mode: ImportMode) -> usize {
+    let mut window_ids = keyspace::WINDOWS.get_or_insert(&state.db, || vec![]);
+
+    let tabs = windows.iter().map(|window| window.tabs.len()).sum();
+
+    match mode {
+        ImportMode::Replace => {
+            let db = &state.db;
+            let loaded = &state.window_ids;
+
+            // Windows which are open in the browser cannot be replaced
+            window_ids.retain(|uuid| {
+                if loaded.values().any(|window| window.serialized.uuid == *uuid) {
+                    true
+
+                } else {
+                    remove_window(db, *uuid);
+                    false
+                }
+            });
+
+            for ImportedWindow { window, tabs } in windows {
+                // If the window is open in the browser then the imported tabs are merged into it, so it isn't duplicated
+                if loaded_window(&mut state.window_ids, window.uuid).is_some() {
+                    merge_window(state, &mut window_ids, window, tabs);
+
+                } else {
+                    insert_window(&state.db, &mut window_ids, window, tabs);
+                }
+            }
+        },
+
+        ImportMode::Merge => {
+            for ImportedWindow { window, tabs } in windows {
+                merge_window(state, &mut window_ids, window, tabs);
+            }
+        },
+
+        ImportMode::Append => {
+            let State { db, window_ids: loaded, focused_window, .. } = state;
+
+            match focused_window.and_then(move |uuid| loaded_window(loaded, uuid)) {
+                Some(browser_window) => {
+                    append_tabs(db, browser_window, windows.into_iter().flat_map(|window| window.tabs).collect());
+                },
+
+                // There isn't a window to append to, so it falls back to unloaded windows
+                None => {
+                    for ImportedWindow { window, tabs } in windows {
+                        insert_window(db, &mut window_ids, window, tabs);
+                    }
+                },
+            }
+        },
+    }
+
+    keyspace::WINDOWS.set(&state.db, &window_ids);
+
+    tabs
+}
@@ -2,7 +2,6 @@
 #![warn(unreachable_pub)]
 
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::{intern, JsCast};
 use std::rc::Rc;
 use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
@@ -15,12 +14,25 @@ use wasm_bindgen_futures::JsFuture;
 use js_sys::Date;
 use dominator::clone;
 use futures_signals::signal::{Mutable, SignalExt};
-use tab_organizer::{fallible_promise, spawn, log, info, object, serialize, deserialize_str, serialize_str, Listener, Database, on_connect, Port, panic_hook, set_print_logs, download, pretty_date};
+use tab_organizer::{fallible_promise, spawn, log, warn, info, object, serialize, deserialize_str, serialize_str, Listener, Database, on_connect, Port, panic_hook, set_print_logs, every_hour};
+use tab_organizer::keyspace;
 use tab_organizer::state::{Tab, TabStatus, SerializedWindow, SerializedTab, Label, sidebar, options};
 use tab_organizer::browser::{Browser, Id, BrowserChange};
 use tab_organizer::browser;
 
 mod migrate;
+mod import;
+mod foreign;
+mod export;
+mod backup;
+mod fsck;
+mod quota;
+mod sync;
+mod sessions;
+mod containers;
+mod tombstone;
+mod undo;
+mod auto_unload;
 
 
 fn merge_ids(ids: &mut Vec<Uuid>, new_ids: &[Uuid]) -> bool {
@@ -68,11 +80,28 @@ fn merge_ids(ids: &mut Vec<Uuid>, new_ids: &[Uuid]) -> bool {
 }
 
 
+fn remove_tabs(ids: js_sys::Array) {
+    if ids.length() > 0 {
+        // TODO immediately send out a message to the sidebar ?
+        let fut = web_extension::browser.tabs().remove(&ids);
+
+        // TODO should this spawn ?
+        spawn(async {
+            // TODO maybe remove each tab individually, so a single error doesn't break everything
+            let _ = fallible_promise(fut).await;
+            Ok(())
+        });
+    }
+}
+
+
 #[derive(Debug)]
 struct BrowserTab {
     serialized: SerializedTab,
     tab_id: Id,
     is_unloading: bool,
+    // The tab was closed from the sidebar, but the browser hasn't removed it yet
+    is_closing: bool,
 
     // These must be kept in sync with Tab
     playing_audio: bool,
@@ -86,6 +115,7 @@ impl BrowserTab {
             serialized,
             tab_id,
             is_unloading: false,
+            is_closing: false,
 
             // This must be kept in sync with Tab::unloaded
             playing_audio: false,
@@ -157,6 +187,7 @@ struct BrowserWindow {
     focused_tab: Option<Uuid>,
     is_unloading: bool,
     ports: Vec<Rc<Port<sidebar::ServerMessage, sidebar::ClientMessage>>>,
+    history: undo::History,
 }
 
 impl BrowserWindow {
@@ -182,11 +213,37 @@ impl BrowserWindow {
         self.real_index_to_serialized_index(index).unwrap_or_else(|| self.serialized.tabs.len())
     }
 
-    // TODO take into account opener_tab_id ?
-    fn insert_tab(&mut self, tab_uuid: Uuid, new_index: u32) -> usize {
+    /// The unloaded tabs between two browser tabs can be in any order, so the tab is put after the unloaded tabs which were opened by its opener (or their descendants)
+    fn insert_tab(&mut self, db: &Database, tab_uuid: Uuid, opener: Option<Uuid>, new_index: u32) -> usize {
         let new_index = new_index as usize;
 
-        let tab_index = self.serialized_index_left(new_index);
+        let mut tab_index = self.serialized_index_left(new_index);
+
+        if let Some(opener) = opener {
+            let right_index = self.serialized_index_right(new_index);
+
+            let mut subtree = vec![opener];
+
+            while tab_index < right_index {
+                let uuid = self.serialized.tabs[tab_index];
+
+                // The tab records are loaded lazily, so the tabs which aren't loaded are treated as not being in the subtree
+                let parent = if db.tabs().is_loaded(uuid) {
+                    db.tabs().get(uuid).and_then(|tab| tab.opener)
+
+                } else {
+                    None
+                };
+
+                match parent {
+                    Some(parent) if subtree.contains(&parent) => {
+                        subtree.push(uuid);
+                        tab_index += 1;
+                    },
+                    _ => break,
+                }
+            }
+        }
 
         self.tabs.insert(new_index, tab_uuid);
 
@@ -250,7 +307,7 @@ impl BrowserWindow {
     }
 
     fn serialize(&self, db: &Database) {
-        db.set(&SerializedWindow::key(self.serialized.uuid), &self.serialized);
+        db.windows().set(self.serialized.uuid, &self.serialized);
     }
 }
 
@@ -278,15 +335,80 @@ struct State {
 
     focused_window: Option<Uuid>,
 
-    reloading_tabs: HashSet<Uuid>,
+    // This is shared with the Futures from load_tab, so they can remove the tab if it fails to be created
+    reloading_tabs: Rc<RefCell<HashSet<Uuid>>>,
+
+    // The cookie store ids of the containers, it is None until they have been fetched
+    containers: Option<HashSet<String>>,
+
+    // The number of `LoadAll` which currently exist
+    full_loads: usize,
 
     // TODO maybe use usize ?
     // TODO replace this with a dedicated Counter struct ?
     pending: Mutable<u32>,
 
     options_ports: Vec<Rc<Port<options::ServerMessage, options::ClientMessage>>>,
+
+    sync: sync::SyncState,
+}
+
+
+/// Keeps every tab loaded while it exists, afterwards the tabs which aren't in an open window are unloaded again.
+struct LoadAll {
+    state: Rc<RefCell<State>>,
 }
 
+impl LoadAll {
+    fn new(state: &Rc<RefCell<State>>) -> impl Future<Output = Result<Self, JsValue>> {
+        let fut = {
+            let mut lock = state.borrow_mut();
+            lock.full_loads += 1;
+            lock.db.load_all()
+        };
+
+        // This is created before loading, so that it is still counted correctly if the loading fails
+        let this = Self { state: state.clone() };
+
+        async move {
+            fut.await?;
+            Ok(this)
+        }
+    }
+}
+
+impl Drop for LoadAll {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+
+        // This is spawned because the State might be borrowed while this is dropped
+        spawn(async move {
+            let fut = {
+                let mut state = state.borrow_mut();
+
+                state.full_loads -= 1;
+
+                if state.full_loads != 0 {
+                    return Ok(());
+                }
+
+                let open: HashSet<Uuid> = state.window_ids.values()
+                    .flat_map(|window| window.serialized.tabs.iter().cloned())
+                    .collect();
+
+                let uuids: Vec<Uuid> = state.db.tabs().uuids().into_iter()
+                    .filter(|uuid| !open.contains(uuid))
+                    .collect();
+
+                state.db.tabs().unload(uuids)
+            };
+
+            fut.await
+        });
+    }
+}
+
+
 impl State {
     fn new(db: Database, browser: Browser, timestamp_created: f64, windows: Vec<browser::WindowState>) -> impl Future<Output = Result<Rc<RefCell<Self>>, JsValue>> {
         let state = Rc::new(RefCell::new(Self {
@@ -299,14 +421,26 @@ impl State {
 
             focused_window: None,
 
-            reloading_tabs: HashSet::new(),
+            reloading_tabs: Rc::new(RefCell::new(HashSet::new())),
+            containers: None,
+            full_loads: 0,
             pending: Mutable::new(0),
 
             options_ports: vec![],
+
+            sync: sync::SyncState::default(),
         }));
 
         async move {
             let windows = Self::windows(&state, windows).await?;
+
+            // Only the tabs which are open in the browser are loaded, the unloaded tabs are loaded when the sidebar is opened
+            let fut = state.borrow().db.tabs().load(windows.iter()
+                .filter_map(|window| window.as_ref())
+                .flat_map(|(_, _, _, tabs)| tabs.iter().map(|(uuid, _)| *uuid)));
+
+            fut.await?;
+
             state.borrow_mut().initialize(timestamp_created, windows);
             Ok(state)
         }
@@ -441,19 +575,25 @@ impl State {
     }
 
     fn new_tab(&mut self, transfer_tags: bool, timestamp_created: f64, uuid: Uuid, tab: &browser::TabState) -> TabCreated {
-        let key = SerializedTab::key(uuid);
-
         let mut is_new = false;
 
-        let mut serialized = self.db.get_or_insert(&key, || {
+        let mut serialized = self.db.tabs().get_or_insert(uuid, || {
             is_new = true;
             SerializedTab::new(uuid, timestamp_created)
         });
 
         // TODO send ServerMessage::TabFocused ?
-        let changed = serialized.initialize(&tab, timestamp_created);
+        let mut changed = serialized.initialize(&tab, timestamp_created);
         let mut changes = serialized.update(&tab);
 
+        // The browser forgets the opener when the tab is unloaded, so it is only set when the tab is created
+        if is_new {
+            if let Some(opener) = tab.opener_id.and_then(|id| self.tab_ids.get(&id)) {
+                serialized.opener = Some(opener.serialized.uuid);
+                changed = true;
+            }
+        }
+
         if is_new && transfer_tags {
             // Transfer labels from the opener tab
             if let Some(opener) = tab.opener_id.map(|id| self.tab_ids.get_mut(&id).unwrap()) {
@@ -475,7 +615,7 @@ impl State {
         }
 
         if changed || !changes.is_empty() {
-            self.db.set(&key, &serialized);
+            self.db.tabs().set(uuid, &serialized);
         }
 
         let mut browser_tab = BrowserTab::new(serialized, tab.id);
@@ -507,14 +647,12 @@ impl State {
             info.uuid
         }).collect();
 
-        let key = SerializedWindow::key(uuid);
-
-        let mut serialized = self.db.get_or_insert(&key, || SerializedWindow::new(uuid, timestamp_created));
+        let mut serialized = self.db.windows().get_or_insert(uuid, || SerializedWindow::new(uuid, timestamp_created));
 
         let changed = merge_ids(&mut serialized.tabs, &tabs);
 
         if changed {
-            self.db.set(&key, &serialized);
+            self.db.windows().set(uuid, &serialized);
         }
 
         // TODO is this needed ?
@@ -527,6 +665,7 @@ impl State {
             focused_tab,
             is_unloading: false,
             ports: vec![],
+            history: undo::History::default(),
         }).unwrap_none();
 
         if focused {
@@ -554,12 +693,12 @@ impl State {
             })
             .collect();
 
-        let mut window_ids: Vec<Uuid> = self.db.get_or_insert(intern("windows"), || vec![]);
+        let mut window_ids = keyspace::WINDOWS.get_or_insert(&self.db, || vec![]);
 
         let changed = merge_ids(&mut window_ids, &new_windows);
 
         if changed {
-            self.db.set(intern("windows"), &window_ids);
+            keyspace::WINDOWS.set(&self.db, &window_ids);
         }
     }
 
@@ -585,11 +724,9 @@ impl State {
 
                 // Tab is unloaded
                 None => {
-                    let key = SerializedTab::key(uuid);
-
-                    if let Some(mut tab) = self.db.get::<SerializedTab>(&key) {
+                    if let Some(mut tab) = self.db.tabs().get(uuid) {
                         if let Some(changes) = update(&mut tab) {
-                            self.db.set(&key, &tab);
+                            self.db.tabs().set(uuid, &tab);
                             unloaded.push((uuid, changes));
                         }
                     }
@@ -607,18 +744,27 @@ impl State {
         unloaded
     }
 
+    // This is used when the tab might not be in the sidebar's window
+    fn send_tab_changes(&self, changes: Vec<(Uuid, Vec<sidebar::TabChange>)>) {
+        for (uuid, changes) in changes {
+            if let Some(window) = self.window_ids.values().find(|window| window.serialized.tab_index(uuid).is_some()) {
+                let tab_index = window.serialized.tab_index(uuid).unwrap();
+
+                window.send_message(&sidebar::ServerMessage::TabChanged { tab_index, changes });
+            }
+        }
+    }
+
     fn update_tabs_serialized<U>(&mut self, uuids: &[Uuid], mut update: U) -> Vec<(Uuid, Vec<sidebar::TabChange>)>
         where U: FnMut(&mut SerializedTab) -> Option<Vec<sidebar::TabChange>> {
 
         uuids.into_iter().filter_map(|&uuid| {
-            let key = SerializedTab::key(uuid);
-
             match self.ids.get(&uuid) {
                 Some(id) => {
                     let tab = self.tab_ids.get_mut(&id).unwrap();
 
                     if let Some(changes) = update(&mut tab.serialized) {
-                        self.db.set(&key, &tab.serialized);
+                        self.db.tabs().set(uuid, &tab.serialized);
                         Some((uuid, changes))
 
                     } else {
@@ -628,9 +774,9 @@ impl State {
 
                 // Tab is unloaded
                 None => {
-                    if let Some(mut tab) = self.db.get::<SerializedTab>(&key) {
+                    if let Some(mut tab) = self.db.tabs().get(uuid) {
                         if let Some(changes) = update(&mut tab) {
-                            self.db.set(&key, &tab);
+                            self.db.tabs().set(uuid, &tab);
                             Some((uuid, changes))
 
                         } else {
@@ -644,6 +790,467 @@ impl State {
             }
         }).collect()
     }
+
+    fn serialized_tab(&self, uuid: Uuid) -> Option<SerializedTab> {
+        match self.ids.get(&uuid) {
+            Some(id) => Some(self.tab_ids.get(id)?.serialized.clone()),
+
+            // Tab is unloaded
+            None => self.db.tabs().get(uuid),
+        }
+    }
+
+    /// Creates a browser tab for an unloaded tab, it returns true if the tab is being loaded.
+    ///
+    /// If `discarded` is true then the tab is added to the browser without loading its page.
+    fn load_tab(&mut self, window_id: Id, uuid: Uuid, active: bool, discarded: bool) -> bool {
+        let State {
+            ref window_ids,
+            ref reloading_tabs,
+            ref containers,
+            ref ids,
+            ref db,
+            ref browser,
+            ref pending,
+            ..
+        } = self;
+
+        if ids.contains_key(&uuid) {
+            return false;
+        }
+
+        let browser_window = match window_ids.get(&window_id) {
+            Some(browser_window) => browser_window,
+            None => return false,
+        };
+
+        browser.get_window(browser_window.window_id, move |window| {
+            let window = match window {
+                Some(window) => window,
+                None => return false,
+            };
+
+            let index = match browser_window.serialized.tab_index(uuid) {
+                Some(index) => index,
+                None => return false,
+            };
+
+            // The tab's record might not be loaded, e.g. if the window was never opened in the sidebar
+            let serialized = match db.tabs().get(uuid) {
+                Some(serialized) => serialized,
+                None => {
+                    warn!("Could not load tab {} because its record is not loaded", uuid);
+                    return false;
+                },
+            };
+
+            if !serialized.has_good_url() || !reloading_tabs.borrow_mut().insert(uuid) {
+                return false;
+            }
+
+            struct OnPanic {
+                pending: Mutable<u32>,
+            }
+
+            impl OnPanic {
+                fn new(pending: Mutable<u32>) -> Self {
+                    pending.replace_with(|pending| *pending + 1);
+                    Self { pending }
+                }
+            }
+
+            impl Drop for OnPanic {
+                fn drop(&mut self) {
+                    self.pending.replace_with(|pending| *pending - 1);
+                }
+            }
+
+            let on_panic = OnPanic::new(pending.clone());
+
+
+            let index = browser_window.serialized.tabs[(index + 1)..]
+                .into_iter()
+                // Look for the first tab which exists in the browser
+                .find(|uuid| ids.contains_key(uuid));
+
+            let index = match index {
+                Some(uuid) => {
+                    // TODO look this up in the real browser window ?
+                    // TODO this conversion is a bit hacky
+                    JsValue::from(browser_window.tabs.iter().position(|x| x == uuid).unwrap() as u32)
+                },
+                None => {
+                    JsValue::UNDEFINED
+                },
+            };
+
+            // Firefox doesn't allow creating pinned tabs which are discarded, so they are loaded normally
+            let discarded = discarded && !serialized.pinned;
+
+            // Discarded tabs can't be active, and they need a title because the page isn't loaded
+            let title = if discarded {
+                serialized.title.map(JsValue::from).unwrap_or(JsValue::UNDEFINED)
+
+            } else {
+                JsValue::UNDEFINED
+            };
+
+            // A container which was removed (or the private container in a normal window) makes tabs.create fail,
+            // so those tabs are put into the default container instead
+            let cookie_store_id = serialized.cookie_store_id.filter(|id| {
+                containers.as_ref().map(|containers| containers.contains(id)).unwrap_or(true)
+            });
+
+            // TODO set openInReaderMode ?
+            let fut = browser.create_tab(&object! {
+                "windowId": window.real_id(),
+                "active": active && !discarded,
+                "discarded": discarded,
+                "title": title,
+                "pinned": serialized.pinned,
+                "cookieStoreId": cookie_store_id.map(JsValue::from).unwrap_or(JsValue::UNDEFINED),
+                //"openerTabId": ,
+                // TODO handle privileged URLs (e.g. chrome: and about:)
+                "url": serialized.url.map(JsValue::from).unwrap_or(JsValue::UNDEFINED),
+                "index": index,
+            }, move |tab| tab.set_uuid(uuid));
+
+            let reloading_tabs = reloading_tabs.clone();
+
+            spawn(async move {
+                let result = match fut.await {
+                    Ok(fut) => fut.await,
+                    Err(e) => Err(e),
+                };
+
+                drop(on_panic);
+
+                // TabCreated won't happen, so the tab must be removed here, otherwise it can never be loaded again
+                if result.is_err() {
+                    reloading_tabs.borrow_mut().remove(&uuid);
+                }
+
+                result
+            });
+
+            true
+        })
+    }
+
+    fn load_tabs(&mut self, window_id: Id, uuids: &[Uuid]) -> Vec<Uuid> {
+        let discarded = match self.window_ids.get(&window_id) {
+            Some(window) => window.serialized.options.restore_lazily,
+            None => false,
+        };
+
+        uuids.into_iter().cloned().filter(|uuid| self.load_tab(window_id, *uuid, false, discarded)).collect()
+    }
+
+    /// Closes the tabs, it returns the index and record of every tab which was in the window, so that they can be reopened
+    fn close_tabs(&mut self, window_id: Id, uuids: &[Uuid]) -> Vec<(usize, SerializedTab)> {
+        let mut closed: Vec<(usize, SerializedTab)> = match self.window_ids.get(&window_id) {
+            Some(window) => uuids.into_iter().filter_map(|uuid| {
+                let tab_index = window.serialized.tab_index(*uuid)?;
+                Some((tab_index, self.serialized_tab(*uuid)?))
+            }).collect(),
+            None => vec![],
+        };
+
+        closed.sort_by_key(|(tab_index, _)| *tab_index);
+
+        let mut close_unloaded = vec![];
+
+        let ids = uuids.into_iter().filter_map(|uuid| {
+            match self.ids.get(uuid) {
+                Some(id) => {
+                    self.tab_ids.get_mut(&id).unwrap().is_closing = true;
+
+                    // TODO can this be made faster ?
+                    self.browser.get_tab_real_id(*id).map(JsValue::from)
+                },
+
+                // Tab is unloaded
+                None => {
+                    close_unloaded.push(*uuid);
+                    None
+                },
+            }
+        }).collect::<js_sys::Array>();
+
+        remove_tabs(ids);
+
+        if !close_unloaded.is_empty() {
+            // TODO what if the window is unloaded ?
+            if let Some(window) = self.window_ids.get_mut(&window_id) {
+                // This either removes all of the tabs or none of them
+                let result = self.db.transaction(|db| {
+                    let mut serialized = window.serialized.clone();
+
+                    let tab_indexes = close_unloaded.into_iter().map(|uuid| {
+                        let tab_index = serialized.tab_index(uuid).ok_or(uuid)?;
+
+                        serialized.tabs.remove(tab_index);
+
+                        // TODO verify that the key already existed
+                        db.tabs().remove(uuid);
+
+                        Ok(tab_index)
+                    }).collect::<Result<Vec<usize>, Uuid>>()?;
+
+                    db.windows().set(serialized.uuid, &serialized);
+
+                    Ok((serialized, tab_indexes))
+                });
+
+                match result {
+                    Ok((serialized, tab_indexes)) => {
+                        window.serialized = serialized;
+
+                        for tab_index in tab_indexes {
+                            window.send_message(&sidebar::ServerMessage::TabRemoved { tab_index });
+                        }
+                    },
+                    Err(uuid) => {
+                        warn!("Could not close tabs because tab {} is not in the window", uuid);
+                    },
+                }
+            }
+        }
+
+        closed
+    }
+
+    /// It returns the tabs which are being unloaded
+    fn unload_tabs(&mut self, uuids: &[Uuid]) -> Vec<Uuid> {
+        let mut unloading = vec![];
+
+        let ids = uuids.into_iter().filter_map(|uuid| {
+            match self.ids.get(uuid) {
+                Some(id) => {
+                    let tab = self.tab_ids.get_mut(&id).unwrap();
+
+                    if tab.is_unloading {
+                        None
+
+                    } else {
+                        tab.is_unloading = true;
+                        unloading.push(*uuid);
+
+                        // TODO can this be made faster ?
+                        self.browser.get_tab_real_id(*id).map(JsValue::from)
+                    }
+                },
+
+                // Tab is unloaded
+                None => None,
+            }
+        }).collect::<js_sys::Array>();
+
+        remove_tabs(ids);
+
+        unloading
+    }
+
+    /// It returns the tabs which are being discarded
+    fn discard_tabs(&self, uuids: &[Uuid]) -> Vec<Uuid> {
+        let mut discarding = vec![];
+
+        let ids = uuids.into_iter().filter_map(|uuid| {
+            let id = self.ids.get(uuid)?;
+            let tab = self.tab_ids.get(id)?;
+
+            if tab.status == TabStatus::Discarded || tab.is_unloading {
+                None
+
+            } else {
+                discarding.push(*uuid);
+
+                // TODO can this be made faster ?
+                self.browser.get_tab_real_id(*id).map(JsValue::from)
+            }
+        }).collect::<js_sys::Array>();
+
+        if ids.length() > 0 {
+            // The status is updated by TabUpdated, the focused tab can't be discarded so it fails silently
+            let fut = web_extension::browser.tabs().discard(&ids);
+
+            spawn(async {
+                let _ = fallible_promise(fut).await;
+                Ok(())
+            });
+        }
+
+        discarding
+    }
+
+    /// It returns the tabs which were changed
+    fn pin_tabs(&mut self, uuids: &[Uuid], pinned: bool) -> Vec<Uuid> {
+        let uuids: Vec<Uuid> = uuids.into_iter().cloned().filter(|uuid| {
+            match self.serialized_tab(*uuid) {
+                Some(tab) => tab.pinned != pinned,
+                None => false,
+            }
+        }).collect();
+
+        let unloaded = self.update_tabs(
+            &uuids,
+            move |id| {
+                web_extension::browser.tabs().update(Some(id), &object! {
+                    "pinned": pinned,
+                })
+            },
+            move |tab| {
+                // TODO also move its position ?
+                if tab.pinned != pinned {
+                    tab.pinned = pinned;
+
+                    Some(vec![
+                        sidebar::TabChange::Pinned { pinned },
+                    ])
+
+                } else {
+                    None
+                }
+            },
+        );
+
+        self.send_tab_changes(unloaded);
+
+        uuids
+    }
+
+    /// Adds the label to the tabs, each tab has its own `timestamp_added`. It returns the tabs which were changed.
+    fn add_label(&mut self, label_name: &str, tabs: &[(Uuid, f64)]) -> Vec<Uuid> {
+        let uuids: Vec<Uuid> = tabs.into_iter().map(|(uuid, _)| *uuid).collect();
+
+        let changes = self.update_tabs_serialized(&uuids, move |tab| {
+            if tab.has_label(label_name) {
+                None
+
+            } else {
+                let (_, timestamp_added) = tabs.into_iter().find(|(uuid, _)| *uuid == tab.uuid).unwrap();
+
+                let label = Label {
+                    name: label_name.to_string(),
+                    timestamp_added: *timestamp_added,
+                };
+
+                tab.add_label(label.clone());
+
+                Some(vec![
+                    sidebar::TabChange::AddedToLabel { label },
+                ])
+            }
+        });
+
+        let changed = changes.iter().map(|(uuid, _)| *uuid).collect();
+
+        self.send_tab_changes(changes);
+
+        changed
+    }
+
+    /// It returns the tabs which had the label, and when the label was added to them
+    fn remove_label(&mut self, label_name: &str, uuids: &[Uuid]) -> Vec<(Uuid, f64)> {
+        let mut removed = vec![];
+
+        let changes = self.update_tabs_serialized(&uuids, |tab| {
+            let timestamp_added = tab.labels.iter().find(|label| label.name == label_name)?.timestamp_added;
+
+            tab.remove_label(label_name);
+            removed.push((tab.uuid, timestamp_added));
+
+            Some(vec![
+                sidebar::TabChange::RemovedFromLabel { label_name: label_name.to_string() },
+            ])
+        });
+
+        self.send_tab_changes(changes);
+
+        removed
+    }
+
+    /// Returns the index of each tab in the browser window
+    fn tab_positions(&self, window_id: Id, uuids: &[Uuid]) -> Vec<(Uuid, usize)> {
+        match self.window_ids.get(&window_id) {
+            Some(window) => uuids.into_iter().filter_map(|uuid| {
+                Some((*uuid, window.tabs.iter().position(|x| x == uuid)?))
+            }).collect(),
+            None => vec![],
+        }
+    }
+
+    /// It returns the old positions of the tabs
+    fn move_tabs(&mut self, window_id: Id, uuids: &[Uuid], index: usize) -> Vec<(Uuid, usize)> {
+        let positions = self.tab_positions(window_id, uuids);
+
+        let mut unloaded = vec![];
+
+        let ids = uuids.iter().filter_map(|uuid| {
+            match self.ids.get(uuid) {
+                Some(id) => {
+                    // TODO can this be made faster ?
+                    self.browser.get_tab_real_id(*id).map(JsValue::from)
+                },
+
+                // Tab is unloaded
+                None => {
+                    unloaded.push(uuid);
+                    None
+                },
+            }
+        }).collect::<js_sys::Array>();
+
+        log!("Moving {}", index);
+
+        if ids.length() > 0 {
+            // TODO immediately send out a message to the sidebar ?
+            let fut = web_extension::browser.tabs().move_(&ids, &object! {
+                "index": index as u32,
+            });
+
+            // TODO should this spawn ?
+            spawn(async {
+                let _ = fallible_promise(fut).await;
+                Ok(())
+            });
+        }
+
+        // TODO handle unloaded
+        if !unloaded.is_empty() {
+
+        }
+
+        positions
+    }
+
+    /// Moves each tab to its index in the browser window, it returns the old positions of the tabs
+    fn move_tabs_to(&mut self, window_id: Id, mut positions: Vec<(Uuid, usize)>) -> Vec<(Uuid, usize)> {
+        let uuids: Vec<Uuid> = positions.iter().map(|(uuid, _)| *uuid).collect();
+
+        let old_positions = self.tab_positions(window_id, &uuids);
+
+        // The tabs are moved from left to right, so that moving a tab doesn't change the position of the tabs which were already moved
+        positions.sort_by_key(|(_, index)| *index);
+
+        let futures: Vec<js_sys::Promise> = positions.into_iter().filter_map(|(uuid, index)| {
+            let id = self.browser.get_tab_real_id(*self.ids.get(&uuid)?)?;
+
+            Some(web_extension::browser.tabs().move_(&js_sys::Array::of1(&JsValue::from(id)), &object! {
+                "index": index as u32,
+            }))
+        }).collect();
+
+        spawn(async move {
+            for fut in futures {
+                let _ = fallible_promise(fut).await;
+            }
+
+            Ok(())
+        });
+
+        old_positions
+    }
 }
 
 
@@ -675,8 +1282,20 @@ pub async fn main_js() -> Result<(), JsValue> {
 
         //db.clear();
 
+        if migrate::is_outdated(&db) {
+            db.load_all().await?;
+        }
+
         migrate::migrate(&db);
 
+        // None of the windows are loaded yet, so everything can be repaired.
+        // The tabs are loaded lazily, so this doesn't check whether the tab records are valid.
+        let report = fsck::check(&db, true, &HashSet::new());
+
+        if !report.problems.is_empty() {
+            log!("Repaired {} of {} database problems: {:#?}", report.repaired, report.problems.len(), report.problems);
+        }
+
         Ok(db)
     }
 
@@ -708,20 +1327,6 @@ pub async fn main_js() -> Result<(), JsValue> {
             message: sidebar::ClientMessage,
         ) -> Result<(), JsValue> {
 
-            fn remove_tabs(ids: js_sys::Array) {
-                if ids.length() > 0 {
-                    // TODO immediately send out a message to the sidebar ?
-                    let fut = web_extension::browser.tabs().remove(&ids);
-
-                    // TODO should this spawn ?
-                    spawn(async {
-                        // TODO maybe remove each tab individually, so a single error doesn't break everything
-                        let _ = fallible_promise(fut).await;
-                        Ok(())
-                    });
-                }
-            }
-
             fn get_window<'a>(window_ids: &'a mut HashMap<Id, BrowserWindow>, port_id: &Cell<Option<Id>>) -> Option<&'a mut BrowserWindow> {
                 port_id.get().and_then(move |window_id| window_ids.get_mut(&window_id))
             }
@@ -739,10 +1344,34 @@ pub async fn main_js() -> Result<(), JsValue> {
                 }
             }
 
+            fn perform(state: &RefCell<State>, port_id: &Cell<Option<Id>>, operation: undo::Operation) {
+                // TODO what if the window is unloaded ?
+                if let Some(window_id) = port_id.get() {
+                    undo::perform(&mut state.borrow_mut(), window_id, operation);
+                }
+            }
+
             match message {
                 sidebar::ClientMessage::Initialize { id } => {
                     let id: Id = deserialize_str(&id);
 
+                    // The unloaded tabs are loaded lazily when the sidebar is opened
+                    let fut = {
+                        let state = state.borrow();
+
+                        match state.window_ids.get(&id) {
+                            Some(window) => state.db.tabs().load(window.serialized.tabs.clone()),
+                            None => state.db.load(vec![]),
+                        }
+                    };
+
+                    // The containers are sent with the tabs, so that the sidebar doesn't need to regroup the tabs afterwards
+                    let containers = containers::containers();
+
+                    fut.await?;
+
+                    let containers = containers.await?;
+
                     let state: &mut State = &mut state.borrow_mut();
 
                     if let Some(window) = state.window_ids.get_mut(&id) {
@@ -764,8 +1393,15 @@ pub async fn main_js() -> Result<(), JsValue> {
 
                                     // Tab is unloaded
                                     None => {
-                                        let serialized = db.get::<SerializedTab>(&SerializedTab::key(*uuid)).unwrap();
-                                        Tab::unloaded(serialized)
+                                        match db.tabs().get(*uuid) {
+                                            Some(serialized) => Tab::unloaded(serialized),
+
+                                            // The tab can't be skipped, because the sidebar uses the same tab indexes as the window
+                                            None => {
+                                                warn!("Tab {} does not have a record", uuid);
+                                                Tab::unloaded(SerializedTab::new(*uuid, window.serialized.timestamp_created))
+                                            },
+                                        }
                                     },
                                 }
                             })
@@ -773,7 +1409,16 @@ pub async fn main_js() -> Result<(), JsValue> {
 
                         let options = window.serialized.options.clone();
 
-                        port.send_message(&sidebar::ServerMessage::Initial { tabs, options });
+                        port.send_message(&sidebar::ServerMessage::Initial { tabs, options, containers });
+                        port.send_message(&window.history.message());
+
+                        let fut = sessions::recently_closed();
+
+                        spawn(async move {
+                            let sessions = fut.await?;
+                            port.send_message(&sidebar::ServerMessage::RecentlyClosed { sessions });
+                            Ok(())
+                        });
                     }
                 },
 
@@ -800,164 +1445,24 @@ pub async fn main_js() -> Result<(), JsValue> {
 
                         // Tab is unloaded
                         None => {
-                            let State {
-                                ref mut window_ids,
-                                ref mut reloading_tabs,
-                                ref ids,
-                                ref db,
-                                ref browser,
-                                ref pending,
-                                ..
-                            } = state;
-
                             // TODO what if the window is unloaded ?
-                            if let Some(browser_window) = port_id.get().and_then(|window_id| window_ids.get_mut(&window_id)) {
-                                browser.get_window(browser_window.window_id, move |window| {
-                                    if let Some(window) = window {
-                                        if let Some(index) = browser_window.serialized.tab_index(uuid) {
-                                            let serialized = db.get::<SerializedTab>(&SerializedTab::key(uuid)).unwrap();
-
-                                            if serialized.has_good_url() {
-                                                if reloading_tabs.insert(uuid) {
-                                                    struct OnPanic {
-                                                        pending: Mutable<u32>,
-                                                    }
-
-                                                    impl OnPanic {
-                                                        fn new(pending: Mutable<u32>) -> Self {
-                                                            pending.replace_with(|pending| *pending + 1);
-                                                            Self { pending }
-                                                        }
-                                                    }
-
-                                                    impl Drop for OnPanic {
-                                                        fn drop(&mut self) {
-                                                            self.pending.replace_with(|pending| *pending - 1);
-                                                        }
-                                                    }
-
-                                                    let on_panic = OnPanic::new(pending.clone());
-
-
-                                                    let index = browser_window.serialized.tabs[(index + 1)..]
-                                                        .into_iter()
-                                                        // Look for the first tab which exists in the browser
-                                                        .find(|uuid| ids.contains_key(uuid));
-
-                                                    let index = match index {
-                                                        Some(uuid) => {
-                                                            // TODO look this up in the real browser window ?
-                                                            // TODO this conversion is a bit hacky
-                                                            JsValue::from(browser_window.tabs.iter().position(|x| x == uuid).unwrap() as u32)
-                                                        },
-                                                        None => {
-                                                            JsValue::UNDEFINED
-                                                        },
-                                                    };
-
-                                                    // TODO set active ?
-                                                    // TODO set openInReaderMode ?
-                                                    let fut = browser.create_tab(&object! {
-                                                        "windowId": window.real_id(),
-                                                        "pinned": serialized.pinned,
-                                                        //"cookieStoreId": serialized.cookie_store_id.map(JsValue::from).unwrap_or(JsValue::UNDEFINED),
-                                                        //"openerTabId": ,
-                                                        // TODO handle privileged URLs (e.g. chrome: and about:)
-                                                        "url": serialized.url.map(JsValue::from).unwrap_or(JsValue::UNDEFINED),
-                                                        "index": index,
-                                                    }, move |tab| tab.set_uuid(uuid));
-
-                                                    spawn(async move {
-                                                        fut.await?.await?;
-
-                                                        drop(on_panic);
-
-                                                        Ok(())
-                                                    });
-                                                }
-                                            }
-                                        }
-                                    }
-                                });
+                            if let Some(window_id) = port_id.get() {
+                                state.load_tab(window_id, uuid, true, false);
                             }
                         },
                     }
                 },
 
                 sidebar::ClientMessage::CloseTabs { uuids } => {
-                    let state: &mut State = &mut state.borrow_mut();
-
-                    let mut close_unloaded = vec![];
-
-                    let ids = uuids.into_iter().filter_map(|uuid| {
-                        match state.ids.get(&uuid) {
-                            Some(id) => {
-                                // TODO can this be made faster ?
-                                state.browser.get_tab_real_id(*id).map(JsValue::from)
-                            },
-
-                            // Tab is unloaded
-                            None => {
-                                close_unloaded.push(uuid);
-                                None
-                            },
-                        }
-                    }).collect::<js_sys::Array>();
-
-                    remove_tabs(ids);
-
-                    if !close_unloaded.is_empty() {
-                        let window_ids = &mut state.window_ids;
-
-                        // TODO what if the window is unloaded ?
-                        if let Some(window) = port_id.get().and_then(|window_id| window_ids.get_mut(&window_id)) {
-                            let db = &state.db;
-
-                            let tab_indexes = close_unloaded.into_iter().map(|uuid| {
-                                let tab_index = window.serialized.tab_index(uuid).unwrap();
-
-                                window.serialized.tabs.remove(tab_index);
-
-                                // TODO verify that the key already existed
-                                db.remove(&SerializedTab::key(uuid));
-
-                                tab_index
-                            }).collect::<Vec<usize>>();
-
-                            window.serialize(&state.db);
-
-                            for tab_index in tab_indexes {
-                                window.send_message(&sidebar::ServerMessage::TabRemoved { tab_index });
-                            }
-                        }
-                    }
+                    perform(&state, &port_id, undo::Operation::CloseTabs { uuids });
                 },
 
                 sidebar::ClientMessage::UnloadTabs { uuids } => {
-                    let state: &mut State = &mut state.borrow_mut();
-
-                    let ids = uuids.into_iter().filter_map(|uuid| {
-                        match state.ids.get(&uuid) {
-                            Some(id) => {
-                                let tab = state.tab_ids.get_mut(&id).unwrap();
-
-                                if tab.is_unloading {
-                                    None
-
-                                } else {
-                                    tab.is_unloading = true;
-
-                                    // TODO can this be made faster ?
-                                    state.browser.get_tab_real_id(*id).map(JsValue::from)
-                                }
-                            },
-
-                            // Tab is unloaded
-                            None => None,
-                        }
-                    }).collect::<js_sys::Array>();
+                    perform(&state, &port_id, undo::Operation::UnloadTabs { uuids });
+                },
 
-                    remove_tabs(ids);
+                sidebar::ClientMessage::DiscardTabs { uuids } => {
+                    state.borrow().discard_tabs(&uuids);
                 },
 
                 sidebar::ClientMessage::MuteTabs { uuids, muted } => {
@@ -988,108 +1493,75 @@ pub async fn main_js() -> Result<(), JsValue> {
                 },
 
                 sidebar::ClientMessage::PinTabs { uuids, pinned } => {
-                    let mut state = state.borrow_mut();
+                    perform(&state, &port_id, undo::Operation::PinTabs { uuids, pinned });
+                },
 
-                    let unloaded = state.update_tabs(
-                        &uuids,
-                        move |id| {
-                            web_extension::browser.tabs().update(Some(id), &object! {
-                                "pinned": pinned,
-                            })
-                        },
-                        move |tab| {
-                            // TODO also move its position ?
-                            if tab.pinned != pinned {
-                                tab.pinned = pinned;
+                sidebar::ClientMessage::CollapseTab { uuid, collapsed } => {
+                    let mut state = state.borrow_mut();
 
-                                Some(vec![
-                                    sidebar::TabChange::Pinned { pinned },
-                                ])
+                    let changes = state.update_tabs_serialized(&[uuid], |tab| {
+                        if tab.collapsed == collapsed {
+                            None
 
-                            } else {
-                                None
-                            }
-                        },
-                    );
+                        } else {
+                            tab.collapsed = collapsed;
+                            Some(vec![sidebar::TabChange::Collapsed { collapsed }])
+                        }
+                    });
 
-                    send_messages(&mut state, &port_id, unloaded);
+                    // The other sidebars in the same window need to be updated
+                    state.send_tab_changes(changes);
                 },
 
                 sidebar::ClientMessage::MoveTabs { uuids, index } => {
-                    let state: &mut State = &mut state.borrow_mut();
-
-                    let mut unloaded = vec![];
+                    perform(&state, &port_id, undo::Operation::MoveTabs { uuids, index });
+                },
 
-                    let ids = uuids.iter().filter_map(|uuid| {
-                        match state.ids.get(&uuid) {
-                            Some(id) => {
-                                // TODO can this be made faster ?
-                                state.browser.get_tab_real_id(*id).map(JsValue::from)
-                            },
+                sidebar::ClientMessage::AddLabelToTabs { uuids, label } => {
+                    let tabs = uuids.into_iter().map(|uuid| (uuid, label.timestamp_added)).collect();
 
-                            // Tab is unloaded
-                            None => {
-                                unloaded.push(uuid);
-                                None
-                            },
-                        }
-                    }).collect::<js_sys::Array>();
+                    perform(&state, &port_id, undo::Operation::AddLabelToTabs { label_name: label.name, tabs });
+                },
 
-                    log!("Moving {}", index);
+                sidebar::ClientMessage::RemoveLabelFromTabs { uuids, label_name } => {
+                    perform(&state, &port_id, undo::Operation::RemoveLabelFromTabs { uuids, label_name });
+                },
 
-                    if ids.length() > 0 {
-                        // TODO immediately send out a message to the sidebar ?
-                        let fut = web_extension::browser.tabs().move_(&ids, &object! {
-                            "index": index as u32,
-                        });
+                sidebar::ClientMessage::RestoreTabs { uuids } => {
+                    perform(&state, &port_id, undo::Operation::LoadTabs { uuids });
+                },
 
-                        // TODO should this spawn ?
-                        spawn(async {
-                            let _ = fallible_promise(fut).await;
-                            Ok(())
-                        });
+                sidebar::ClientMessage::Undo => {
+                    if let Some(window_id) = port_id.get() {
+                        undo::undo(&mut state.borrow_mut(), window_id);
                     }
+                },
 
-                    // TODO handle unloaded
-                    if !unloaded.is_empty() {
-
+                sidebar::ClientMessage::Redo => {
+                    if let Some(window_id) = port_id.get() {
+                        undo::redo(&mut state.borrow_mut(), window_id);
                     }
                 },
 
-                sidebar::ClientMessage::AddLabelToTabs { uuids, label } => {
-                    let state: &mut State = &mut state.borrow_mut();
+                sidebar::ClientMessage::ExportTabs { uuids, format } => {
+                    // The tabs can be in any window, including unloaded windows
+                    let _loaded = LoadAll::new(&state).await?;
 
-                    let messages = state.update_tabs_serialized(&uuids, move |tab| {
-                        if tab.has_label(&label.name) {
-                            None
+                    let fut = {
+                        let windows = export::read_windows(&state.borrow().db);
+                        export::download(&export::select_tabs(windows, &uuids), format)
+                    };
 
-                        } else {
-                            tab.add_label(label.clone());
-
-                            Some(vec![
-                                sidebar::TabChange::AddedToLabel { label: label.clone() },
-                            ])
-                        }
-                    });
-
-                    send_messages(state, &port_id, messages);
+                    spawn(fut);
                 },
 
-                sidebar::ClientMessage::RemoveLabelFromTabs { uuids, label_name } => {
-                    let state: &mut State = &mut state.borrow_mut();
-
-                    let messages = state.update_tabs_serialized(&uuids, move |tab| {
-                        if tab.remove_label(&label_name) {
-                            Some(vec![
-                                sidebar::TabChange::RemovedFromLabel { label_name: label_name.clone() },
-                            ])
-
-                        } else {
-                            None
-                        }
-                    });
+                // The sidebars are updated by sessions::listen
+                sidebar::ClientMessage::RestoreClosed { session_id } => {
+                    spawn(sessions::restore(&session_id));
+                },
 
-                    send_messages(state, &port_id, messages);
+                sidebar::ClientMessage::ForgetClosed { session } => {
+                    spawn(sessions::forget(&session));
                 },
             }
 
@@ -1129,17 +1601,47 @@ pub async fn main_js() -> Result<(), JsValue> {
 
 
     fn listen_to_options(state: Rc<RefCell<State>>, options_messages: impl Stream<Item = Port<options::ServerMessage, options::ClientMessage>> + 'static) {
+        fn send_summary(state: &State, port: &Port<options::ServerMessage, options::ClientMessage>) {
+            let (windows, labels) = export::summary(&export::read_windows(&state.db));
+            port.send_message(&options::ServerMessage::Summary { windows, labels });
+        }
+
+        fn send_migration_failures(state: &State, port: &Port<options::ServerMessage, options::ClientMessage>) {
+            let failures = keyspace::MIGRATION_FAILURES.get(&state.db).unwrap_or_else(|| vec![]);
+            port.send_message(&options::ServerMessage::MigrationFailures { failures });
+        }
+
+        fn send_sync_status(state: &State, port: &Port<options::ServerMessage, options::ClientMessage>) {
+            port.send_message(&options::ServerMessage::SyncStatus { status: sync::status(state) });
+        }
+
+        async fn send_usage(state: &Rc<RefCell<State>>, port: &Port<options::ServerMessage, options::ClientMessage>) -> Result<(), JsValue> {
+            let fut = quota::usage(&state.borrow().db);
+            port.send_message(&options::ServerMessage::StorageUsage { usage: fut.await? });
+            Ok(())
+        }
+
         async fn on_message(state: Rc<RefCell<State>>, port: Rc<Port<options::ServerMessage, options::ClientMessage>>, message: options::ClientMessage) -> Result<(), JsValue> {
+            // Everything on the options page works with every window, so all of the tabs need to be loaded
+            let _loaded = LoadAll::new(&state).await?;
+
             match message {
                 options::ClientMessage::Initialize => {
                     port.send_message(&options::ServerMessage::Initial);
+                    send_summary(&state.borrow(), &port);
+                    send_sync_status(&state.borrow(), &port);
+                    send_migration_failures(&state.borrow(), &port);
+                    port.send_message(&options::ServerMessage::TombstoneDuration { duration: tombstone::duration(&state.borrow().db) });
+                    port.send_message(&options::ServerMessage::AutoUnload { settings: auto_unload::settings(&state.borrow().db) });
+                    send_usage(&state, &port).await?;
                 },
 
                 // TODO don't allow multiple exports at the same time
-                options::ClientMessage::Export => {
-                    let json = state.borrow().db.to_json();
-
-                    let fut = download(&format!("Tab Organizer ({}).json", pretty_date()), &json);
+                options::ClientMessage::Export { format, filter } => {
+                    let fut = {
+                        let windows = export::read_windows(&state.borrow().db);
+                        export::download(&export::filter(windows, &filter), format)
+                    };
 
                     spawn(async move {
                         fut.await?;
@@ -1150,28 +1652,116 @@ pub async fn main_js() -> Result<(), JsValue> {
                     });
                 },
 
-                options::ClientMessage::Import { data } => {
-                    let json = js_sys::JSON::parse(&data)
-                        .unwrap()
-                        .dyn_into::<js_sys::Object>()
-                        .unwrap();
+                options::ClientMessage::PreviewImport { data } => {
+                    match import::parse(&data, Date::now()) {
+                        Ok((format, windows)) => {
+                            let (windows, labels) = import::preview(&windows);
+                            port.send_message(&options::ServerMessage::ImportPreview { format, windows, labels });
+                        },
+                        Err(errors) => {
+                            port.send_message(&options::ServerMessage::ImportFailed { errors });
+                        },
+                    }
+                },
+
+                options::ClientMessage::Import { data, mode } => {
+                    match import::parse(&data, Date::now()) {
+                        Ok((_, windows)) => {
+                            let window_count = windows.len();
+
+                            let tabs = import::import(&mut state.borrow_mut(), windows, mode);
+
+                            port.send_message(&options::ServerMessage::Imported { windows: window_count, tabs });
+                            send_summary(&state.borrow(), &port);
+                        },
+                        Err(errors) => {
+                            port.send_message(&options::ServerMessage::ImportFailed { errors });
+                        },
+                    }
+                },
 
-                    let db = Database::new_from_object(json);
+                options::ClientMessage::CheckDatabase { repair } => {
+                    let report = {
+                        let state = state.borrow();
 
-                    let mut tabs = vec![];
+                        let loaded: HashSet<Uuid> = state.window_ids.values().map(|window| window.serialized.uuid).collect();
 
-                    let windows = db.get::<Vec<Uuid>>("windows").unwrap();
+                        fsck::check(&state.db, repair, &loaded)
+                    };
 
-                    for id in windows {
-                        let window = db.get::<SerializedWindow>(&SerializedWindow::key(id)).unwrap();
+                    port.send_message(&options::ServerMessage::DatabaseChecked { problems: report.problems, repaired: report.repaired });
 
-                        for id in window.tabs {
-                            let tab = db.get::<SerializedTab>(&SerializedTab::key(id)).unwrap();
-                            tabs.push(tab);
-                        }
+                    if repair {
+                        send_summary(&state.borrow(), &port);
                     }
+                },
 
-                    port.send_message(&options::ServerMessage::Imported { tabs });
+                options::ClientMessage::CreateBackup => {
+                    backup::create(&state.borrow().db, options::BackupReason::Regular);
+                },
+
+                options::ClientMessage::RestoreBackup { uuid, mode } => {
+                    let parsed = backup::read(&state.borrow().db, uuid)
+                        .map_err(|error| vec![error])
+                        .and_then(|data| import::parse(&data, Date::now()));
+
+                    match parsed {
+                        Ok((_, windows)) => {
+                            let window_count = windows.len();
+
+                            let tabs = {
+                                let state: &mut State = &mut state.borrow_mut();
+
+                                // The current state is backed up first, so that the restore can be undone
+                                backup::create(&state.db, options::BackupReason::BeforeRestore);
+
+                                import::import(state, windows, mode)
+                            };
+
+                            port.send_message(&options::ServerMessage::Imported { windows: window_count, tabs });
+                            send_summary(&state.borrow(), &port);
+                        },
+                        Err(errors) => {
+                            port.send_message(&options::ServerMessage::ImportFailed { errors });
+                        },
+                    }
+                },
+
+                options::ClientMessage::GetStorageUsage => {
+                    send_usage(&state, &port).await?;
+                },
+
+                options::ClientMessage::SetCompact { compact } => {
+                    if let Err(message) = quota::set_compact(&state.borrow().db, compact) {
+                        port.send_message(&options::ServerMessage::CompactFailed { message });
+                    }
+
+                    send_usage(&state, &port).await?;
+                },
+
+                options::ClientMessage::SetSync { enabled } => {
+                    sync::set_enabled(&state, enabled);
+                    send_sync_status(&state.borrow(), &port);
+                },
+
+                // The status is sent to every options page when the sync is finished
+                options::ClientMessage::SyncNow => {
+                    sync::start(&state);
+                },
+
+                options::ClientMessage::SetTombstoneDuration { duration } => {
+                    tombstone::set_duration(&state.borrow().db, duration);
+                    port.send_message(&options::ServerMessage::TombstoneDuration { duration });
+                },
+
+                options::ClientMessage::SetAutoUnload { settings } => {
+                    auto_unload::set_settings(&mut state.borrow_mut(), &settings);
+                    port.send_message(&options::ServerMessage::AutoUnload { settings });
+                },
+
+                options::ClientMessage::DismissMigrationFailures => {
+                    keyspace::MIGRATION_FAILURES.remove(&state.borrow().db);
+                    send_migration_failures(&state.borrow(), &port);
                 },
             }
 
@@ -1219,16 +1809,24 @@ pub async fn main_js() -> Result<(), JsValue> {
                     let uuid = State::window_uuid(&state, window.id, window.tabs);
 
                     if let Some((uuid, tabs)) = uuid.await? {
+                        let fut = state.borrow().db.tabs().load(tabs.iter().map(|(uuid, _)| *uuid));
+                        fut.await?;
+
                         let mut state = state.borrow_mut();
 
+                        // If the window was closed recently then its tabs get their labels back
+                        for (tab_uuid, _) in tabs.iter() {
+                            tombstone::unbury(&state.db, *tab_uuid);
+                        }
+
                         let uuid = state.new_window(true, timestamp, uuid, window.id, window.focused, &tabs);
 
-                        let mut window_ids: Vec<Uuid> = state.db.get_or_insert(intern("windows"), || vec![]);
+                        let mut window_ids = keyspace::WINDOWS.get_or_insert(&state.db, || vec![]);
 
                         // TODO insert at the proper index ?
                         window_ids.push(uuid);
 
-                        state.db.set(intern("windows"), &window_ids);
+                        keyspace::WINDOWS.set(&state.db, &window_ids);
                     }
                 },
 
@@ -1255,16 +1853,16 @@ pub async fn main_js() -> Result<(), JsValue> {
                             // These are only unloaded tabs
                             for uuid in browser_window.serialized.tabs {
                                 // TODO verify that the key already existed
-                                state.db.remove(&SerializedTab::key(uuid));
+                                state.db.tabs().remove(uuid);
                             }
 
-                            state.db.remove(&SerializedWindow::key(uuid));
+                            state.db.windows().remove(uuid);
 
-                            let mut window_ids: Vec<Uuid> = state.db.get_or_insert(intern("windows"), || vec![]);
+                            let mut window_ids = keyspace::WINDOWS.get_or_insert(&state.db, || vec![]);
 
                             window_ids.remove_item(&uuid).unwrap();
 
-                            state.db.set(intern("windows"), &window_ids);
+                            keyspace::WINDOWS.set(&state.db, &window_ids);
                         }
                     }
                 },
@@ -1299,10 +1897,22 @@ pub async fn main_js() -> Result<(), JsValue> {
                                 None => uuid,
                             };
 
+                            // The tab might already exist, e.g. if it was restored with undo close tab
+                            let fut = state.borrow().db.tabs().load(vec![uuid]);
+                            fut.await?;
+
                             let state: &mut State = &mut state.borrow_mut();
 
                             // TODO is this correct ?
                             if state.window_ids.contains_key(&window_id) {
+                                // If the tab was closed recently then it gets its labels back
+                                tombstone::restore(state, uuid, window_id);
+
+                                // The tab can already be unloaded in the window, e.g. if it was reopened with undo and then restored by the browser
+                                if state.window_ids[&window_id].serialized.tab_index(uuid).is_some() {
+                                    state.reloading_tabs.borrow_mut().insert(uuid);
+                                }
+
                                 state.insert_tab_uuid(uuid, tab.id);
 
                                 let info = state.new_tab(true, timestamp, uuid, &tab);
@@ -1311,9 +1921,9 @@ pub async fn main_js() -> Result<(), JsValue> {
                                 let browser_tab = state.tab_ids.get(&tab.id).unwrap();
 
                                 if info.is_new {
-                                    assert!(!state.reloading_tabs.contains(&info.uuid));
+                                    assert!(!state.reloading_tabs.borrow().contains(&info.uuid));
 
-                                    let tab_index = browser_window.insert_tab(info.uuid, index);
+                                    let tab_index = browser_window.insert_tab(&state.db, info.uuid, browser_tab.serialized.opener, index);
 
                                     browser_window.serialized.tabs.insert(tab_index, info.uuid);
                                     browser_window.serialize(&state.db);
@@ -1325,7 +1935,7 @@ pub async fn main_js() -> Result<(), JsValue> {
 
                                 // Tab was unloaded
                                 } else {
-                                    assert!(state.reloading_tabs.remove(&info.uuid));
+                                    assert!(state.reloading_tabs.borrow_mut().remove(&info.uuid));
 
                                     let old_tab_index = browser_window.serialized.tab_index(info.uuid).unwrap();
 
@@ -1350,7 +1960,7 @@ pub async fn main_js() -> Result<(), JsValue> {
                                     } else {
                                         assert_eq!(browser_window.serialized.tabs.remove(old_tab_index), info.uuid);
 
-                                        let new_tab_index = browser_window.insert_tab(info.uuid, index);
+                                        let new_tab_index = browser_window.insert_tab(&state.db, info.uuid, browser_tab.serialized.opener, index);
 
                                         browser_window.serialized.tabs.insert(new_tab_index, info.uuid);
 
@@ -1374,7 +1984,7 @@ pub async fn main_js() -> Result<(), JsValue> {
                         let mut serialized_changes = browser_tab.serialized.update(&tab);
 
                         if !serialized_changes.is_empty() {
-                            state.db.set(&SerializedTab::key(tab_uuid), &browser_tab.serialized);
+                            state.db.tabs().set(tab_uuid, &browser_tab.serialized);
                         }
 
                         serialized_changes.append(&mut browser_tab.update(&tab));
@@ -1400,7 +2010,7 @@ pub async fn main_js() -> Result<(), JsValue> {
                         if let Some(old_tab_uuid) = browser_window.set_focused(uuid) {
                             browser_tab.serialized.timestamp_focused = Some(timestamp);
 
-                            state.db.set(&SerializedTab::key(uuid), &browser_tab.serialized);
+                            state.db.tabs().set(uuid, &browser_tab.serialized);
 
                             if let Some(old_tab_uuid) = old_tab_uuid {
                                 let old_tab_index = browser_window.serialized.tab_index(old_tab_uuid).unwrap();
@@ -1452,7 +2062,8 @@ pub async fn main_js() -> Result<(), JsValue> {
                         // TODO what if this is None ?
                         // TODO verify this works correctly if the tab is focused
                         if let Some(new_window) = state.window_ids.get_mut(&new_window_id) {
-                            let tab_index = new_window.insert_tab(tab_uuid, new_index);
+                            // The tab was moved by the user, so it isn't put next to its opener
+                            let tab_index = new_window.insert_tab(&state.db, tab_uuid, None, new_index);
 
                             new_window.serialized.tabs.insert(tab_index, tab_uuid);
                             new_window.serialize(&state.db);
@@ -1493,7 +2104,7 @@ pub async fn main_js() -> Result<(), JsValue> {
                 },
 
                 // TODO verify this works correctly if the tab is focused
-                BrowserChange::TabRemoved { timestamp: _, tab_id, window_id, is_window_closing } => {
+                BrowserChange::TabRemoved { timestamp, tab_id, window_id, is_window_closing } => {
                     let state: &mut State = &mut state.borrow_mut();
 
                     if let Some(mut browser_tab) = state.tab_ids.remove(&tab_id) {
@@ -1536,7 +2147,7 @@ pub async fn main_js() -> Result<(), JsValue> {
                             browser_window.serialized.tabs.remove(tab_index);
 
                             // TODO verify that the key existed before ?
-                            state.db.remove(&SerializedTab::key(tab_uuid));
+                            tombstone::bury(&state.db, browser_tab.serialized, browser_window.serialized.uuid, tab_index, timestamp);
                             browser_window.serialize(&state.db);
 
                             browser_window.send_message(&sidebar::ServerMessage::TabRemoved { tab_index });
@@ -1556,6 +2167,33 @@ pub async fn main_js() -> Result<(), JsValue> {
     }
 
 
+    fn create_backup_if_due(state: &Rc<RefCell<State>>) {
+        if backup::is_due(&state.borrow().db) {
+            // The backup contains every tab, so they all need to be loaded
+            let fut = LoadAll::new(state);
+
+            spawn(clone!(state => async move {
+                let _loaded = fut.await?;
+                backup::create(&state.borrow().db, options::BackupReason::Regular);
+                Ok(())
+            }));
+        }
+    }
+
+    create_backup_if_due(&state);
+    tombstone::remove_expired(&state.borrow().db);
+    sync::start(&state);
+    sync::listen(&state);
+    sessions::listen(&state);
+    containers::listen(&state);
+    auto_unload::start(state.clone());
+
+    every_hour(clone!(state => move || {
+        create_backup_if_due(&state);
+        tombstone::remove_expired(&state.borrow().db);
+        sync::start(&state);
+    }));
+
     listen_to_sidebar(state.clone(), sidebar_messages);
     listen_to_options(state.clone(), options_messages);
     listen_to_changes(state, browser_changes);
@@ -1,48 +1,77 @@
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use uuid::Uuid;
 
-use tab_organizer::{time, Database};
-use tab_organizer::state as latest;
-use wasm_bindgen::intern;
+use tab_organizer::{time, warn, Database};
+use tab_organizer::{keyspace, compact};
+use tab_organizer::keyspace::Record;
+use tab_organizer::state::SerializedTab;
+use tab_organizer::state::options::MigrationFailure;
 
+use super::backup;
 
-fn each_window<F>(db: &Database, mut f: F) where F: FnMut(&Database, String) {
-    if let Some(window_ids) = db.get::<Vec<Uuid>>(intern("windows")) {
-        for window_id in window_ids {
-            f(db, latest::SerializedWindow::key(window_id));
-        }
-    }
+
+/// A record which could not be migrated, it is removed from the `Database`
+#[derive(Debug)]
+pub(crate) struct InvalidRecord {
+    key: String,
+    message: String,
 }
 
-fn migrate_tabs<Window, Old, New, F>(db: &Database, mut f: F)
-    where Window: DeserializeOwned,
-          Old: DeserializeOwned,
-          New: Serialize + From<Old>,
-          F: FnMut(&Window) -> &[Uuid] {
-    each_window(db, move |db, key| {
-        let window = db.get::<Window>(&key).unwrap();
-
-        for tab_id in f(&window) {
-            let key = latest::SerializedTab::key(*tab_id);
-            let tab = db.get::<Old>(&key).unwrap();
-            db.set::<New>(&key, &tab.into());
+
+/// A single change to the schema of the `Database`.
+///
+/// The migrations only receive a `Database`, so they can also be run on an in-memory `Database`.
+pub(crate) trait Migration {
+    fn from_version(&self) -> u32;
+    fn to_version(&self) -> u32;
+    fn migrate(&self, db: &Database) -> Vec<InvalidRecord>;
+}
+
+
+// This goes through every key rather than the window list, so the orphan records are also migrated
+fn migrate_records<Old, New>(db: &Database, prefix: &str) -> Vec<InvalidRecord>
+    where Old: DeserializeOwned,
+          New: Serialize + From<Old> {
+
+    let mut invalid = vec![];
+
+    for key in db.keys_with_prefix(prefix) {
+        if let Some(old) = db.try_get::<Old>(&key) {
+            match old {
+                Ok(old) => db.set::<New>(&key, &old.into()),
+                Err(error) => invalid.push(InvalidRecord { key, message: error.to_string() }),
+            }
         }
-    });
+    }
+
+    invalid
 }
 
-fn migrate_windows<Old, New>(db: &Database)
+// The compact tab records are arrays, the new fields are optional elements at the end so they don't need to be migrated
+fn migrate_tab_records<Old, New>(db: &Database) -> Vec<InvalidRecord>
     where Old: DeserializeOwned,
           New: Serialize + From<Old> {
-    each_window(db, move |db, key| {
-        let window = db.get::<Old>(&key).unwrap();
-        db.set::<New>(&key, &window.into());
-    });
+
+    let mut invalid = vec![];
+
+    for key in db.keys_with_prefix(SerializedTab::PREFIX) {
+        if let Some(value) = db.get_raw(&key) {
+            if !compact::is_compact(&value) {
+                match serde_json::from_str::<Old>(&value) {
+                    Ok(old) => db.set::<New>(&key, &old.into()),
+                    Err(error) => invalid.push(InvalidRecord { key, message: error.to_string() }),
+                }
+            }
+        }
+    }
+
+    invalid
 }
 
 
 mod v1 {
     use super::v2;
+    use tab_organizer::keyspace::Record;
     use serde_derive::Deserialize;
     use uuid::Uuid;
 
@@ -106,13 +135,22 @@ mod v1 {
         }
     }
 
-    pub(crate) fn migrate(db: &tab_organizer::Database) {
-        super::migrate_tabs::<SerializedWindow, SerializedTab, v2::SerializedTab, _>(db, |window| &window.tabs);
+    pub(crate) struct Migration;
+
+    impl super::Migration for Migration {
+        fn from_version(&self) -> u32 { 1 }
+        fn to_version(&self) -> u32 { 2 }
+
+        fn migrate(&self, db: &tab_organizer::Database) -> Vec<super::InvalidRecord> {
+            // The old tab records don't implement Record, but the prefix is the same in every version
+            super::migrate_records::<SerializedTab, v2::SerializedTab>(db, tab_organizer::state::SerializedTab::PREFIX)
+        }
     }
 }
 
 mod v2 {
     use super::v3;
+    use tab_organizer::keyspace::Record;
 
     pub(crate) use v3::{SerializedTab, Label};
     pub(crate) use super::v1::SerializedWindow;
@@ -136,34 +174,491 @@ mod v2 {
         }
     }
 
-    pub(crate) fn migrate(db: &tab_organizer::Database) {
-        super::migrate_windows::<SerializedWindow, v3::SerializedWindow>(db);
+    pub(crate) struct Migration;
+
+    impl super::Migration for Migration {
+        fn from_version(&self) -> u32 { 2 }
+        fn to_version(&self) -> u32 { 3 }
+
+        fn migrate(&self, db: &tab_organizer::Database) -> Vec<super::InvalidRecord> {
+            super::migrate_records::<SerializedWindow, v3::SerializedWindow>(db, v3::SerializedWindow::PREFIX)
+        }
     }
 }
 
 mod v3 {
-    pub(crate) use tab_organizer::state::{SerializedTab, SerializedWindow, Label, WindowOptions};
+    use super::v4;
+    use tab_organizer::keyspace::Record;
+    use serde_derive::{Serialize, Deserialize};
+    use uuid::Uuid;
+
+    pub(crate) use v4::{SerializedWindow, Label, WindowOptions};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct SerializedTab {
+        pub uuid: Uuid,
+        pub labels: Vec<Label>,
+        pub timestamp_created: f64,
+        pub timestamp_focused: Option<f64>,
+        pub pinned: bool,
+        pub favicon_url: Option<String>,
+        pub url: Option<String>,
+        pub title: Option<String>,
+        pub muted: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SerializedTombstone {
+        pub tab: SerializedTab,
+        pub window_uuid: Uuid,
+        pub tab_index: usize,
+        pub timestamp_removed: f64,
+    }
+
+    impl From<SerializedTab> for v4::SerializedTab {
+        fn from(input: SerializedTab) -> Self {
+            let SerializedTab {
+                uuid,
+                labels,
+                timestamp_created,
+                timestamp_focused,
+                pinned,
+                favicon_url,
+                url,
+                title,
+                muted,
+            } = input;
+
+            Self {
+                uuid,
+                labels,
+                timestamp_created,
+                timestamp_focused,
+                pinned,
+                favicon_url,
+                url,
+                title,
+                muted,
+                // It is set when the tab is loaded
+                cookie_store_id: None,
+            }
+        }
+    }
+
+    impl From<SerializedTombstone> for v4::SerializedTombstone {
+        fn from(input: SerializedTombstone) -> Self {
+            let SerializedTombstone {
+                tab,
+                window_uuid,
+                tab_index,
+                timestamp_removed,
+            } = input;
+
+            Self {
+                tab: tab.into(),
+                window_uuid,
+                tab_index,
+                timestamp_removed,
+            }
+        }
+    }
+
+    pub(crate) struct Migration;
+
+    impl super::Migration for Migration {
+        fn from_version(&self) -> u32 { 3 }
+        fn to_version(&self) -> u32 { 4 }
+
+        fn migrate(&self, db: &tab_organizer::Database) -> Vec<super::InvalidRecord> {
+            let mut invalid = super::migrate_tab_records::<SerializedTab, v4::SerializedTab>(db);
+            invalid.extend(super::migrate_records::<SerializedTombstone, v4::SerializedTombstone>(db, tab_organizer::state::SerializedTombstone::PREFIX));
+            invalid
+        }
+    }
 }
 
+mod v4 {
+    use super::v5;
+    use tab_organizer::keyspace::Record;
+    use serde_derive::{Serialize, Deserialize};
+    use uuid::Uuid;
 
-pub(crate) fn migrate(db: &Database) {
-    const LATEST_VERSION: u32 = 3;
+    pub(crate) use v5::{SerializedWindow, Label, WindowOptions};
 
-    let mut version = db.get_or_insert::<u32, _>(intern("version"), || LATEST_VERSION);
+    #[derive(Serialize, Deserialize)]
+    pub struct SerializedTab {
+        pub uuid: Uuid,
+        pub labels: Vec<Label>,
+        pub timestamp_created: f64,
+        pub timestamp_focused: Option<f64>,
+        pub pinned: bool,
+        pub favicon_url: Option<String>,
+        pub url: Option<String>,
+        pub title: Option<String>,
+        pub muted: bool,
+        pub cookie_store_id: Option<String>,
+    }
 
-    if version != LATEST_VERSION {
-        time!("Migrating", {
-            while version < LATEST_VERSION {
-                match version {
-                    1 => v1::migrate(db),
-                    2 => v2::migrate(db),
-                    _ => unreachable!(),
-                }
+    #[derive(Serialize, Deserialize)]
+    pub struct SerializedTombstone {
+        pub tab: SerializedTab,
+        pub window_uuid: Uuid,
+        pub tab_index: usize,
+        pub timestamp_removed: f64,
+    }
 
-                version += 1;
+    impl From<SerializedTab> for v5::SerializedTab {
+        fn from(input: SerializedTab) -> Self {
+            let SerializedTab {
+                uuid,
+                labels,
+                timestamp_created,
+                timestamp_focused,
+                pinned,
+                favicon_url,
+                url,
+                title,
+                muted,
+                cookie_store_id,
+            } = input;
+
+            Self {
+                uuid,
+                labels,
+                timestamp_created,
+                timestamp_focused,
+                pinned,
+                favicon_url,
+                url,
+                title,
+                muted,
+                cookie_store_id,
+                // The tabs which were opened before this version don't have an opener
+                opener: None,
+                collapsed: false,
+            }
+        }
+    }
+
+    impl From<SerializedTombstone> for v5::SerializedTombstone {
+        fn from(input: SerializedTombstone) -> Self {
+            let SerializedTombstone {
+                tab,
+                window_uuid,
+                tab_index,
+                timestamp_removed,
+            } = input;
+
+            Self {
+                tab: tab.into(),
+                window_uuid,
+                tab_index,
+                timestamp_removed,
             }
+        }
+    }
+
+    pub(crate) struct Migration;
+
+    impl super::Migration for Migration {
+        fn from_version(&self) -> u32 { 4 }
+        fn to_version(&self) -> u32 { 5 }
+
+        fn migrate(&self, db: &tab_organizer::Database) -> Vec<super::InvalidRecord> {
+            let mut invalid = super::migrate_tab_records::<SerializedTab, v5::SerializedTab>(db);
+            invalid.extend(super::migrate_records::<SerializedTombstone, v5::SerializedTombstone>(db, v5::SerializedTombstone::PREFIX));
+            invalid
+        }
+    }
+}
+
+mod v5 {
+    pub(crate) use tab_organizer::state::{SerializedTab, SerializedTombstone, SerializedWindow, Label, WindowOptions};
+}
+
+
+pub(crate) const LATEST_VERSION: u32 = 5;
+
+// When adding a new version, add its migration to the end of this list
+const MIGRATIONS: &[&dyn Migration] = &[
+    &v1::Migration,
+    &v2::Migration,
+    &v3::Migration,
+    &v4::Migration,
+];
+
+
+/// Runs every migration starting at `version`, it stops if there isn't a migration for a version.
+///
+/// If `snapshot` is true then a snapshot is created before each migration, so the records can be restored
+/// from the version before the migration which broke them.
+///
+/// The records which could not be migrated are removed, so `fsck` should be run afterwards.
+pub(crate) fn migrate_from(db: &Database, mut version: u32, snapshot: bool) -> Vec<MigrationFailure> {
+    let mut failures = vec![];
+
+    while version < LATEST_VERSION {
+        let migration = match MIGRATIONS.iter().find(|migration| migration.from_version() == version) {
+            Some(migration) => migration,
+            None => {
+                failures.push(MigrationFailure {
+                    from_version: version,
+                    to_version: LATEST_VERSION,
+                    key: keyspace::VERSION.name().to_string(),
+                    message: format!("There is no migration from version {}", version),
+                });
+
+                break;
+            },
+        };
+
+        let to_version = migration.to_version();
+
+        if snapshot {
+            backup::create_snapshot(db);
+        }
+
+        for InvalidRecord { key, message } in migration.migrate(db) {
+            db.remove(&key);
+            failures.push(MigrationFailure { from_version: version, to_version, key, message });
+        }
+
+        version = to_version;
+        keyspace::VERSION.set(db, &version);
+    }
+
+    failures
+}
+
+
+/// The migrations need every record, so the `Database` must be fully loaded if this returns true
+pub(crate) fn is_outdated(db: &Database) -> bool {
+    keyspace::VERSION.get(db).map(|version| version != LATEST_VERSION).unwrap_or(false)
+}
+
+
+/// Migrates the live `Database`, the failures are stored so that they can be shown on the options page
+pub(crate) fn migrate(db: &Database) {
+    let version = keyspace::VERSION.get_or_insert(db, || LATEST_VERSION);
+
+    if version != LATEST_VERSION {
+        // If a migration has a bug then the old records can be restored from the snapshots
+        let failures = time!(format!("Migrating from version {} to {}", version, LATEST_VERSION), {
+            migrate_from(db, version, true)
         });
 
-        db.set(intern("version"), &LATEST_VERSION);
+        if !failures.is_empty() {
+            warn!("Failed to migrate {} records, they can be restored from the backups which were made before updating: {:#?}", failures.len(), failures);
+
+            let mut all_failures = keyspace::MIGRATION_FAILURES.get(db).unwrap_or_else(|| vec![]);
+            all_failures.extend(failures);
+            keyspace::MIGRATION_FAILURES.set(db, &all_failures);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use uuid::Uuid;
+    use tab_organizer::{keyspace, Database};
+    use tab_organizer::state::SortTabs;
+    use tab_organizer::storage::MemoryStorage;
+    use super::{migrate_from, LATEST_VERSION};
+
+    const TAB: &str = "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c";
+    const WINDOW: &str = "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11";
+
+    // The fixtures are JSON objects (like `Database::to_json`), except the values are JSON rather than strings
+    fn database(fixture: &str) -> Database {
+        let values: HashMap<String, serde_json::Value> = serde_json::from_str(fixture).unwrap();
+
+        Database::new_in_memory(MemoryStorage::from_values(values.into_iter().map(|(key, value)| {
+            (key, value.to_string())
+        }).collect()))
+    }
+
+    fn uuid(value: &str) -> Uuid {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn v1_to_v2() {
+        let db = database(r#"{
+            "version": 1,
+            "tab-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                "tags": [{ "name": "work", "timestamp_added": 5 }],
+                "timestamp_created": 1,
+                "timestamp_focused": 2,
+                "pinned": true,
+                "favicon_url": null,
+                "url": "https://example.com/",
+                "title": "Example",
+                "muted": false
+            }
+        }"#);
+
+        let failures = migrate_from(&db, 1, false);
+        assert!(failures.is_empty(), "{:?}", failures);
+        assert_eq!(keyspace::VERSION.get(&db), Some(LATEST_VERSION));
+
+        let tab = db.tabs().get(uuid(TAB)).unwrap();
+        assert_eq!(tab.labels.len(), 1);
+        assert_eq!(tab.labels[0].name, "work");
+        assert_eq!(tab.labels[0].timestamp_added, 5.0);
+        assert_eq!(tab.timestamp_focused, Some(2.0));
+        assert!(tab.pinned);
+        assert_eq!(tab.url.as_ref().map(|x| x.as_str()), Some("https://example.com/"));
+    }
+
+    #[test]
+    fn v2_to_v3() {
+        let db = database(r#"{
+            "version": 2,
+            "window-ids.0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11": {
+                "uuid": "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11",
+                "name": "Research",
+                "timestamp_created": 1,
+                "tabs": ["c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c"]
+            }
+        }"#);
+
+        let failures = migrate_from(&db, 2, false);
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        let window = db.windows().get(uuid(WINDOW)).unwrap();
+        assert_eq!(window.name.as_ref().map(|x| x.as_str()), Some("Research"));
+        assert_eq!(window.tabs, vec![uuid(TAB)]);
+        assert_eq!(window.options.sort_tabs, SortTabs::Label);
+    }
+
+    #[test]
+    fn v3_to_v4() {
+        let db = database(r#"{
+            "version": 3,
+            "tab-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                "labels": [],
+                "timestamp_created": 1,
+                "timestamp_focused": null,
+                "pinned": false,
+                "favicon_url": null,
+                "url": null,
+                "title": null,
+                "muted": true
+            },
+            "tab-ids.9d2cbb35-7d1a-4c55-8f0f-3f3b8f0d7e42": ["9d2cbb35-7d1a-4c55-8f0f-3f3b8f0d7e42", [], 1, null, 0, null, null, null],
+            "tombstone-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "tab": {
+                    "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                    "labels": [],
+                    "timestamp_created": 1,
+                    "timestamp_focused": null,
+                    "pinned": false,
+                    "favicon_url": null,
+                    "url": null,
+                    "title": null,
+                    "muted": false
+                },
+                "window_uuid": "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11",
+                "tab_index": 3,
+                "timestamp_removed": 10
+            }
+        }"#);
+
+        let compact = db.get_raw("tab-ids.9d2cbb35-7d1a-4c55-8f0f-3f3b8f0d7e42");
+
+        let failures = migrate_from(&db, 3, false);
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        let tab = db.tabs().get(uuid(TAB)).unwrap();
+        assert!(tab.muted);
+        assert_eq!(tab.cookie_store_id, None);
+
+        // The compact records don't need to be migrated
+        assert_eq!(db.get_raw("tab-ids.9d2cbb35-7d1a-4c55-8f0f-3f3b8f0d7e42"), compact);
+
+        let tombstone = db.tombstones().get(uuid(TAB)).unwrap();
+        assert_eq!(tombstone.tab.cookie_store_id, None);
+        assert_eq!(tombstone.window_uuid, uuid(WINDOW));
+        assert_eq!(tombstone.tab_index, 3);
+    }
+
+    #[test]
+    fn v4_to_v5() {
+        let db = database(r#"{
+            "version": 4,
+            "tab-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                "labels": [],
+                "timestamp_created": 1,
+                "timestamp_focused": null,
+                "pinned": false,
+                "favicon_url": null,
+                "url": null,
+                "title": null,
+                "muted": false,
+                "cookie_store_id": "firefox-container-1"
+            },
+            "tombstone-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "tab": {
+                    "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                    "labels": [],
+                    "timestamp_created": 1,
+                    "timestamp_focused": null,
+                    "pinned": false,
+                    "favicon_url": null,
+                    "url": null,
+                    "title": null,
+                    "muted": false,
+                    "cookie_store_id": null
+                },
+                "window_uuid": "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11",
+                "tab_index": 0,
+                "timestamp_removed": 10
+            }
+        }"#);
+
+        let failures = migrate_from(&db, 4, false);
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        let tab = db.tabs().get(uuid(TAB)).unwrap();
+        assert_eq!(tab.cookie_store_id.as_ref().map(|x| x.as_str()), Some("firefox-container-1"));
+        assert_eq!(tab.opener, None);
+
+        let tombstone = db.tombstones().get(uuid(TAB)).unwrap();
+        assert_eq!(tombstone.tab.opener, None);
+    }
+
+    #[test]
+    fn invalid_record() {
+        let db = database(r#"{
+            "version": 1,
+            "tab-ids.c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c": {
+                "uuid": "c5fba7e6-1b0e-4b83-9ee1-2a0e7aa46f1c",
+                "tags": "work",
+                "timestamp_created": 1
+            },
+            "window-ids.0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11": {
+                "uuid": "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11",
+                "name": null,
+                "timestamp_created": 1,
+                "tabs": []
+            }
+        }"#);
+
+        let failures = migrate_from(&db, 1, false);
+
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].from_version, 1);
+        assert_eq!(failures[0].to_version, 2);
+        assert_eq!(failures[0].key, format!("tab-ids.{}", TAB));
+
+        // The invalid record is removed, and the other records are still migrated
+        assert!(!db.tabs().contains(uuid(TAB)));
+        assert!(db.windows().get(uuid(WINDOW)).is_some());
+        assert_eq!(keyspace::VERSION.get(&db), Some(LATEST_VERSION));
     }
 }
@@ -0,0 +1,92 @@
+use std::future::Future;
+use uuid::Uuid;
+use wasm_bindgen::JsValue;
+use tab_organizer::{time, Database};
+use tab_organizer::keyspace::{self, Record};
+use tab_organizer::compact::FAVICON_PREFIX;
+use tab_organizer::state::{SerializedWindow, SerializedTab, SerializedTombstone};
+use tab_organizer::state::options::{RecordUsage, StorageUsage};
+
+use super::backup;
+
+
+// Everything else is counted as "Other"
+const RECORD_TYPES: [(&str, &str); 5] = [
+    ("Tabs", SerializedTab::PREFIX),
+    ("Windows", SerializedWindow::PREFIX),
+    ("Closed tabs", SerializedTombstone::PREFIX),
+    ("Favicons", FAVICON_PREFIX),
+    ("Backups", backup::PREFIX),
+];
+
+
+fn record_usage(db: &Database) -> Vec<RecordUsage> {
+    let mut usage: Vec<RecordUsage> = RECORD_TYPES.iter().chain(&[("Other", "")]).map(|(name, _)| {
+        RecordUsage {
+            name: name.to_string(),
+            count: 0,
+            bytes: 0,
+        }
+    }).collect();
+
+    for key in db.keys_with_prefix("") {
+        let index = RECORD_TYPES.iter().position(|(_, prefix)| key.starts_with(prefix)).unwrap_or(RECORD_TYPES.len());
+
+        // This is only an estimate, because the browser might store the values differently
+        let bytes = key.len() + db.get_raw(&key).map(|value| value.len()).unwrap_or(0);
+
+        let usage = &mut usage[index];
+        usage.count += 1;
+        usage.bytes += bytes;
+    }
+
+    usage
+}
+
+
+pub(crate) fn usage(db: &Database) -> impl Future<Output = Result<StorageUsage, JsValue>> {
+    let records = record_usage(db);
+    let compact = keyspace::COMPACT.get(db).unwrap_or(false);
+    let fut = db.bytes_in_use();
+
+    async move {
+        Ok(StorageUsage {
+            records,
+            bytes_in_use: fut.await?,
+            compact,
+        })
+    }
+}
+
+
+/// Rewrites every tab record, so that they all use the compact format (or the normal format).
+///
+/// If a tab record cannot be read then nothing is changed, because removing the favicons would lose its favicon.
+pub(crate) fn set_compact(db: &Database, compact: bool) -> Result<(), String> {
+    time!("Compacting database", {
+        db.transaction(|db| {
+            let mut tabs: Vec<(Uuid, SerializedTab)> = vec![];
+
+            for uuid in db.tabs().uuids() {
+                match db.tabs().try_get(uuid) {
+                    Some(Ok(tab)) => tabs.push((uuid, tab)),
+                    Some(Err(e)) => return Err(format!("Could not read tab {}: {}", uuid, e)),
+                    None => {},
+                }
+            }
+
+            // The favicons are added again when the tabs are rewritten, so this also removes the unused favicons
+            for key in db.keys_with_prefix(FAVICON_PREFIX) {
+                db.remove(&key);
+            }
+
+            keyspace::COMPACT.set(db, &compact);
+
+            for (uuid, tab) in tabs {
+                db.tabs().set(uuid, &tab);
+            }
+
+            Ok(())
+        })
+    })
+}
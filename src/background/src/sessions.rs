@@ -0,0 +1,99 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::future::Future;
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_extension::{browser, Session};
+use tab_organizer::{spawn, Listener};
+use tab_organizer::state::sidebar::{ClosedSession, ServerMessage};
+
+use super::State;
+
+
+/// Returns the recently closed tabs and windows, the most recently closed are first
+pub(crate) fn recently_closed() -> impl Future<Output = Result<Vec<ClosedSession>, JsValue>> {
+    let fut = JsFuture::from(browser.sessions().get_recently_closed(None));
+
+    async move {
+        let sessions: Array = fut.await?.unchecked_into();
+
+        Ok(sessions.iter().filter_map(|session| {
+            let session: Session = session.unchecked_into();
+
+            let timestamp_closed = session.last_modified();
+
+            if let Some(tab) = session.tab() {
+                Some(ClosedSession::Tab {
+                    session_id: tab.session_id()?,
+                    window_id: tab.window_id(),
+                    timestamp_closed,
+                    title: tab.title(),
+                    url: tab.url(),
+                    favicon_url: tab.fav_icon_url(),
+                })
+
+            } else if let Some(window) = session.window() {
+                Some(ClosedSession::Window {
+                    session_id: window.session_id()?,
+                    timestamp_closed,
+                    title: window.title(),
+                    tabs: window.tabs().map(|tabs| tabs.length() as usize).unwrap_or(0),
+                })
+
+            } else {
+                None
+            }
+        }).collect())
+    }
+}
+
+
+// The labels are restored by the tombstones, because the tabs keep their UUID
+pub(crate) fn restore(session_id: &str) -> impl Future<Output = Result<(), JsValue>> {
+    let fut = JsFuture::from(browser.sessions().restore(session_id));
+
+    async move {
+        let _ = fut.await?;
+        Ok(())
+    }
+}
+
+
+pub(crate) fn forget(session: &ClosedSession) -> impl Future<Output = Result<(), JsValue>> {
+    let fut = JsFuture::from(match session {
+        ClosedSession::Tab { session_id, window_id, .. } => browser.sessions().forget_closed_tab(*window_id, session_id),
+        ClosedSession::Window { session_id, .. } => browser.sessions().forget_closed_window(session_id),
+    });
+
+    async move {
+        let _ = fut.await?;
+        Ok(())
+    }
+}
+
+
+/// Sends the recently closed tabs and windows to every sidebar whenever they change
+pub(crate) fn listen(state: &Rc<RefCell<State>>) {
+    let state = state.clone();
+
+    let listener: Listener<dyn FnMut()> = Listener::new(browser.sessions().on_changed(), Closure::new(move || {
+        let fut = recently_closed();
+        let state = state.clone();
+
+        spawn(async move {
+            let sessions = fut.await?;
+
+            let message = ServerMessage::RecentlyClosed { sessions };
+
+            for window in state.borrow().window_ids.values() {
+                window.send_message(&message);
+            }
+
+            Ok(())
+        });
+    }));
+
+    listener.forget();
+}
@@ -0,0 +1,740 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use uuid::Uuid;
+use js_sys::{Array, Date, Object, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use serde_derive::{Serialize, Deserialize};
+use web_extension::browser;
+use tab_organizer::{spawn, time, warn, serialize_str, Database, Listener};
+use tab_organizer::keyspace;
+use tab_organizer::compress::{compress_str, decompress_str};
+use tab_organizer::state::{Label, sidebar, options};
+
+use super::{State, LoadAll};
+
+
+// storage.sync only allows 8192 bytes per item (including the key and the JSON quotes), and 102400 bytes in total
+const CHUNK_SIZE: usize = 8000;
+const MAX_CHUNKS: usize = 12;
+
+const COUNT_KEY: &str = "chunks";
+
+// Only some of the URLs are synced for each window, that is enough to find the same window on the other devices
+const WINDOW_URLS: usize = 50;
+
+fn chunk_key(index: usize) -> String {
+    format!("chunk.{}", index)
+}
+
+
+// The labels for each URL
+type Urls = BTreeMap<String, BTreeSet<String>>;
+
+// Some of the URLs for each window name
+type Windows = BTreeMap<String, BTreeSet<String>>;
+
+
+/// Everything which is stored in `storage.sync`, it is shared between every device which is signed into the browser
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SyncData {
+    /// The timestamp when each label was first added to a tab
+    labels: BTreeMap<String, f64>,
+    /// The labels for each URL, they are added to the tabs on the other devices which have the same URL
+    urls: Urls,
+    /// Some of the URLs were dropped so that it fits into `storage.sync`, so a missing URL doesn't mean that its labels were removed
+    #[serde(default)]
+    truncated: bool,
+    /// The window UUIDs are different on every device, so the windows are matched by their URLs instead
+    #[serde(default)]
+    windows: Windows,
+}
+
+impl SyncData {
+    fn add_label(&mut self, label: &Label) {
+        let timestamp = self.labels.entry(label.name.clone()).or_insert(label.timestamp_added);
+
+        if label.timestamp_added < *timestamp {
+            *timestamp = label.timestamp_added;
+        }
+    }
+
+    /// Reads the labels from every tab, the `Database` must be fully loaded
+    fn labels(db: &Database) -> Self {
+        let mut data = Self::default();
+
+        for (_, tab) in db.tabs().iter() {
+            for label in tab.labels.iter() {
+                data.add_label(label);
+            }
+        }
+
+        data
+    }
+
+    fn merge_timestamps(&mut self, other: &SyncData) {
+        for (name, timestamp_added) in other.labels.iter() {
+            self.add_label(&Label {
+                name: name.clone(),
+                timestamp_added: *timestamp_added,
+            });
+        }
+    }
+
+    /// Compresses the data and splits it into chunks which fit into `storage.sync`
+    fn encode(&mut self) -> Vec<String> {
+        loop {
+            let compressed = compress_str(&serialize_str(self));
+
+            if compressed.len() <= CHUNK_SIZE * MAX_CHUNKS || self.urls.is_empty() {
+                // This is safe because the compressed string is base64
+                return compressed.as_bytes().chunks(CHUNK_SIZE).map(|chunk| {
+                    std::str::from_utf8(chunk).unwrap().to_string()
+                }).collect();
+            }
+
+            // TODO drop the URLs which haven't been used for the longest time
+            let drop = std::cmp::max(self.urls.len() / 10, 1);
+
+            self.truncated = true;
+
+            for _ in 0..drop {
+                let url = self.urls.keys().next_back().unwrap().clone();
+                self.urls.remove(&url);
+            }
+        }
+    }
+
+    fn decode(chunks: &[String]) -> Result<Self, String> {
+        let data = decompress_str(&chunks.concat()).map_err(|error| error.to_string())?;
+        serde_json::from_str(&data).map_err(|error| error.to_string())
+    }
+}
+
+
+enum Remote {
+    Empty,
+    /// Some of the chunks haven't been synced yet, so it will be retried when they arrive
+    Incomplete,
+    Invalid(Vec<String>, String),
+    Valid(Vec<String>, SyncData),
+}
+
+fn read_remote() -> impl Future<Output = Result<Remote, JsValue>> {
+    let fut = JsFuture::from(browser.storage().sync().get(&JsValue::null()));
+
+    async move {
+        let values: Object = fut.await?.unchecked_into();
+
+        let count = match Reflect::get(&values, &JsValue::from(COUNT_KEY))?.as_f64() {
+            Some(count) => count as usize,
+            None => return Ok(Remote::Empty),
+        };
+
+        let chunks: Option<Vec<String>> = (0..count).map(|index| {
+            Reflect::get(&values, &JsValue::from(chunk_key(index))).ok()?.as_string()
+        }).collect();
+
+        Ok(match chunks {
+            Some(chunks) => match SyncData::decode(&chunks) {
+                Ok(data) => Remote::Valid(chunks, data),
+                Err(message) => Remote::Invalid(chunks, message),
+            },
+            None => Remote::Incomplete,
+        })
+    }
+}
+
+fn write_remote(old_count: usize, chunks: Vec<String>) -> impl Future<Output = Result<(), JsValue>> {
+    let values = Object::new();
+
+    Reflect::set(&values, &JsValue::from(COUNT_KEY), &JsValue::from(chunks.len() as f64)).unwrap();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        Reflect::set(&values, &JsValue::from(chunk_key(index)), &JsValue::from(chunk.as_str())).unwrap();
+    }
+
+    let removed: Array = (chunks.len()..old_count).map(|index| JsValue::from(chunk_key(index))).collect();
+
+    let set = JsFuture::from(browser.storage().sync().set(&values));
+    let remove = JsFuture::from(browser.storage().sync().remove(&removed));
+
+    async move {
+        let _ = set.await?;
+        let _ = remove.await?;
+        Ok(())
+    }
+}
+
+
+/// Returns the labels of every URL on this device, including the URLs which don't have any labels
+fn local_urls(db: &Database) -> Urls {
+    let mut urls = Urls::new();
+
+    for (_, tab) in db.tabs().iter() {
+        if let Some(url) = tab.url {
+            urls.entry(url).or_insert_with(BTreeSet::new).extend(tab.labels.into_iter().map(|label| label.name));
+        }
+    }
+
+    urls
+}
+
+/// Returns the new labels for each URL, a label is only taken from `remote` if it wasn't added or removed on this device since the last sync.
+///
+/// URLs which don't exist on this device keep their labels, so that the other devices don't lose them.
+///
+/// If `truncated` is true then the URLs which are missing from `remote` keep the labels from this device,
+/// so removing every label from a URL on another device isn't synced until the data fits into `storage.sync` again.
+fn merge_urls(local: &Urls, base: &Urls, remote: &Urls, truncated: bool) -> Urls {
+    let mut urls = remote.clone();
+    let empty = BTreeSet::new();
+
+    for (url, labels) in local.iter() {
+        if truncated && !remote.contains_key(url) {
+            urls.insert(url.clone(), labels.clone());
+            continue;
+        }
+
+        let base = base.get(url).unwrap_or(&empty);
+        let merged = urls.entry(url.clone()).or_insert_with(BTreeSet::new);
+
+        for name in labels.difference(base) {
+            merged.insert(name.clone());
+        }
+
+        for name in base.difference(labels) {
+            merged.remove(name);
+        }
+    }
+
+    urls.retain(|_, labels| !labels.is_empty());
+    urls
+}
+
+
+struct LocalWindow {
+    uuid: Uuid,
+    name: Option<String>,
+    urls: BTreeSet<String>,
+}
+
+/// Returns every window on this device, the `Database` must be fully loaded
+fn local_windows(db: &Database) -> Vec<LocalWindow> {
+    db.windows().iter().map(|(uuid, window)| {
+        let urls = window.tabs.iter().filter_map(|uuid| db.tabs().get(*uuid)?.url).collect();
+
+        LocalWindow {
+            uuid,
+            name: window.name,
+            urls,
+        }
+    }).collect()
+}
+
+fn sample_urls(urls: &BTreeSet<String>) -> BTreeSet<String> {
+    urls.iter().take(WINDOW_URLS).cloned().collect()
+}
+
+// More than half of the synced URLs must be in the window
+fn window_score(window: &LocalWindow, urls: &BTreeSet<String>) -> Option<usize> {
+    let score = urls.iter().filter(|url| window.urls.contains(*url)).count();
+
+    if score * 2 > urls.len() {
+        Some(score)
+
+    } else {
+        None
+    }
+}
+
+/// Returns the new windows for `storage.sync` and the new name of every window on this device.
+///
+/// A name is only taken from `remote` if the window wasn't renamed on this device since the last sync. The windows which
+/// don't have a name (or which were renamed on another device) get the name of the remote window which has the same URLs.
+///
+/// Windows which don't exist on this device keep their name, so that the other devices don't lose it.
+fn merge_windows(local: &[LocalWindow], base: &BTreeMap<Uuid, String>, remote: &Windows) -> (Windows, BTreeMap<Uuid, Option<String>>) {
+    let mut windows = remote.clone();
+    let mut names = BTreeMap::new();
+    let mut unmatched = vec![];
+
+    for window in local.iter() {
+        let base = base.get(&window.uuid);
+
+        if window.name.as_ref() != base {
+            if let Some(name) = base {
+                windows.remove(name);
+            }
+
+            if let Some(name) = &window.name {
+                windows.insert(name.clone(), sample_urls(&window.urls));
+            }
+
+            names.insert(window.uuid, window.name.clone());
+
+        } else {
+            match &window.name {
+                Some(name) if remote.contains_key(name) => {
+                    windows.insert(name.clone(), sample_urls(&window.urls));
+                    names.insert(window.uuid, Some(name.clone()));
+                },
+                _ => {
+                    unmatched.push(window);
+                },
+            }
+        }
+    }
+
+    for window in unmatched {
+        let name = windows.iter()
+            // Every name is only used for one window
+            .filter(|(name, _)| !names.values().any(|x| x.as_ref() == Some(*name)))
+            .filter_map(|(name, urls)| Some((window_score(window, urls)?, name)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, name)| name.clone());
+
+        names.insert(window.uuid, name);
+    }
+
+    (windows, names)
+}
+
+
+/// Changes the labels of the tabs so they match `urls`, the labels which aren't in `urls` were removed on another device
+fn apply_labels(state: &mut State, urls: &Urls, timestamps: &BTreeMap<String, f64>) {
+    let empty = BTreeSet::new();
+
+    let uuids: Vec<Uuid> = state.db.tabs().iter().filter_map(|(uuid, tab)| {
+        let labels = urls.get(tab.url.as_ref()?).unwrap_or(&empty);
+
+        if tab.labels.len() != labels.len() || tab.labels.iter().any(|label| !labels.contains(&label.name)) {
+            Some(uuid)
+
+        } else {
+            None
+        }
+    }).collect();
+
+    let changes = state.update_tabs_serialized(&uuids, |tab| {
+        let labels = urls.get(tab.url.as_ref().unwrap()).unwrap_or(&empty);
+
+        let mut changes = vec![];
+
+        let removed: Vec<String> = tab.labels.iter()
+            .filter(|label| !labels.contains(&label.name))
+            .map(|label| label.name.clone())
+            .collect();
+
+        for label_name in removed {
+            tab.remove_label(&label_name);
+            changes.push(sidebar::TabChange::RemovedFromLabel { label_name });
+        }
+
+        for name in labels.iter() {
+            if !tab.has_label(name) {
+                let label = Label {
+                    name: name.clone(),
+                    timestamp_added: timestamps.get(name).cloned().unwrap_or_else(Date::now),
+                };
+
+                tab.add_label(label.clone());
+                changes.push(sidebar::TabChange::AddedToLabel { label });
+            }
+        }
+
+        if changes.is_empty() {
+            None
+
+        } else {
+            Some(changes)
+        }
+    });
+
+    state.send_tab_changes(changes);
+}
+
+
+fn apply_names(state: &mut State, local: &[LocalWindow], names: &BTreeMap<Uuid, Option<String>>) {
+    for window in local.iter() {
+        let new_name = &names[&window.uuid];
+
+        if window.name != *new_name {
+            // Windows which are open in the browser are owned by State
+            match state.window_ids.values_mut().find(|x| x.serialized.uuid == window.uuid) {
+                Some(browser_window) => {
+                    browser_window.serialized.name = new_name.clone();
+                    browser_window.serialize(&state.db);
+                },
+                None => {
+                    if let Some(mut serialized) = state.db.windows().get(window.uuid) {
+                        serialized.name = new_name.clone();
+                        state.db.windows().set(window.uuid, &serialized);
+                    }
+                },
+            }
+        }
+    }
+}
+
+
+fn run(state: &Rc<RefCell<State>>) -> impl Future<Output = Result<(), JsValue>> {
+    let load = LoadAll::new(state);
+    let remote = read_remote();
+
+    let state = state.clone();
+
+    async move {
+        let _loaded = load.await?;
+
+        let (old_chunks, remote, is_valid) = match remote.await? {
+            Remote::Empty => (vec![], SyncData::default(), false),
+            Remote::Incomplete => return Ok(()),
+            Remote::Invalid(chunks, message) => {
+                warn!("Replacing invalid sync data: {}", message);
+                (chunks, SyncData::default(), false)
+            },
+            Remote::Valid(chunks, data) => (chunks, data, true),
+        };
+
+        let fut = time!("Syncing labels and window names", {
+            let mut state = state.borrow_mut();
+
+            // If the sync data is missing then nothing was removed on the other devices, so it is merged like the first sync
+            let (base_urls, base_names) = if is_valid {
+                (
+                    keyspace::SYNCED_URLS.get(&state.db).unwrap_or_else(BTreeMap::new),
+                    keyspace::SYNCED_WINDOW_NAMES.get(&state.db).unwrap_or_else(BTreeMap::new),
+                )
+
+            } else {
+                (BTreeMap::new(), BTreeMap::new())
+            };
+
+            let urls = merge_urls(&local_urls(&state.db), &base_urls, &remote.urls, remote.truncated);
+
+            let local_windows = local_windows(&state.db);
+            let (windows, names) = merge_windows(&local_windows, &base_names, &remote.windows);
+
+            apply_labels(&mut state, &urls, &remote.labels);
+            apply_names(&mut state, &local_windows, &names);
+
+            let mut merged = SyncData::labels(&state.db);
+            merged.merge_timestamps(&remote);
+            merged.urls = urls;
+            merged.windows = windows;
+
+            let chunks = merged.encode();
+
+            // This is after encoding, because it might drop some of the URLs
+            keyspace::SYNCED_URLS.set(&state.db, &merged.urls);
+            keyspace::SYNCED_WINDOW_NAMES.set(&state.db, &names.into_iter().filter_map(|(uuid, name)| Some((uuid, name?))).collect());
+            keyspace::SYNC_TIMESTAMP.set(&state.db, &Date::now());
+
+            // If nothing changed then it must not write, otherwise it would trigger storage.onChanged on every device
+            if chunks != old_chunks {
+                Some(write_remote(old_chunks.len(), chunks))
+
+            } else {
+                None
+            }
+        });
+
+        if let Some(fut) = fut {
+            fut.await?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub(crate) struct SyncState {
+    running: bool,
+    // Another sync was requested while it was running
+    queued: bool,
+    error: Option<String>,
+}
+
+
+pub(crate) fn is_enabled(db: &Database) -> bool {
+    keyspace::SYNC.get(db).unwrap_or(false)
+}
+
+pub(crate) fn status(state: &State) -> options::SyncStatus {
+    options::SyncStatus {
+        enabled: is_enabled(&state.db),
+        timestamp_synced: keyspace::SYNC_TIMESTAMP.get(&state.db),
+        error: state.sync.error.clone(),
+    }
+}
+
+pub(crate) fn set_enabled(state: &Rc<RefCell<State>>, enabled: bool) {
+    {
+        let mut state = state.borrow_mut();
+
+        keyspace::SYNC.set(&state.db, &enabled);
+        state.sync.error = None;
+    }
+
+    start(state);
+}
+
+/// Syncs whenever another device changes `storage.sync`
+pub(crate) fn listen(state: &Rc<RefCell<State>>) {
+    let state = state.clone();
+
+    let listener: Listener<dyn FnMut(JsValue, String)> = Listener::new(browser.storage().on_changed(), Closure::new(move |_: JsValue, area_name: String| {
+        if area_name == "sync" {
+            start(&state);
+        }
+    }));
+
+    listener.forget();
+}
+
+/// Merges the labels and window names with `storage.sync`, it does nothing if syncing is disabled
+pub(crate) fn start(state: &Rc<RefCell<State>>) {
+    {
+        let mut state = state.borrow_mut();
+
+        if !is_enabled(&state.db) {
+            return;
+        }
+
+        if state.sync.running {
+            state.sync.queued = true;
+            return;
+        }
+
+        state.sync.running = true;
+    }
+
+    let state = state.clone();
+
+    spawn(async move {
+        loop {
+            let result = run(&state).await;
+
+            let mut state = state.borrow_mut();
+
+            state.sync.error = result.err().map(|error| format!("{:?}", error));
+
+            if state.sync.queued {
+                state.sync.queued = false;
+
+            } else {
+                state.sync.running = false;
+
+                let status = status(&state);
+
+                for port in state.options_ports.iter() {
+                    port.send_message(&options::ServerMessage::SyncStatus { status: status.clone() });
+                }
+
+                break;
+            }
+        }
+
+        Ok(())
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use uuid::Uuid;
+    use super::{Urls, Windows, SyncData, LocalWindow, merge_urls, merge_windows, CHUNK_SIZE, MAX_CHUNKS};
+
+    fn urls(values: &[(&str, &[&str])]) -> Urls {
+        values.iter().map(|(url, labels)| {
+            (url.to_string(), labels.iter().map(|label| label.to_string()).collect())
+        }).collect()
+    }
+
+    #[test]
+    fn merge() {
+        let base = urls(&[
+            ("a", &["x", "y"]),
+            ("b", &["x"]),
+        ]);
+
+        // "y" was removed from "a" and "z" was added to "b" on this device, "c" isn't open on this device
+        let local = urls(&[
+            ("a", &["x"]),
+            ("b", &["x", "z"]),
+            ("d", &[]),
+        ]);
+
+        // "x" was removed from "b" and "w" was added to "a" on another device
+        let remote = urls(&[
+            ("a", &["w", "x", "y"]),
+            ("b", &[]),
+            ("c", &["x"]),
+        ]);
+
+        assert_eq!(merge_urls(&local, &base, &remote, false), urls(&[
+            ("a", &["w", "x"]),
+            ("b", &["z"]),
+            ("c", &["x"]),
+        ]));
+    }
+
+    #[test]
+    fn first_sync() {
+        let local = urls(&[("a", &["x"])]);
+        let remote = urls(&[("a", &["y"]), ("b", &["x"])]);
+
+        assert_eq!(merge_urls(&local, &Urls::new(), &remote, false), urls(&[
+            ("a", &["x", "y"]),
+            ("b", &["x"]),
+        ]));
+    }
+
+    const WINDOW_1: &str = "0f6a8a0e-51b4-4a3e-9d1c-7c0d2a5c6b11";
+    const WINDOW_2: &str = "5d2c4e1a-9b7f-4c3d-8e6a-1f0b2c3d4e5f";
+
+    fn uuid(value: &str) -> Uuid {
+        value.parse().unwrap()
+    }
+
+    fn set(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn window(uuid_: &str, name: Option<&str>, urls: &[&str]) -> LocalWindow {
+        LocalWindow {
+            uuid: uuid(uuid_),
+            name: name.map(|name| name.to_string()),
+            urls: set(urls),
+        }
+    }
+
+    fn windows(values: &[(&str, &[&str])]) -> Windows {
+        values.iter().map(|(name, urls)| (name.to_string(), set(urls))).collect()
+    }
+
+    fn names(values: &[(&str, Option<&str>)]) -> BTreeMap<Uuid, Option<String>> {
+        values.iter().map(|(uuid_, name)| (uuid(uuid_), name.map(|name| name.to_string()))).collect()
+    }
+
+    #[test]
+    fn window_names_first_sync() {
+        let local = vec![
+            window(WINDOW_1, None, &["a", "b", "c", "d"]),
+            window(WINDOW_2, Some("Local"), &["x"]),
+        ];
+
+        let remote = windows(&[
+            ("Research", &["a", "b", "c"]),
+            ("Other", &["y", "z"]),
+        ]);
+
+        let (merged, new_names) = merge_windows(&local, &BTreeMap::new(), &remote);
+
+        // The unnamed window has the same URLs as "Research", and "Other" doesn't exist on this device
+        assert_eq!(new_names, names(&[(WINDOW_1, Some("Research")), (WINDOW_2, Some("Local"))]));
+
+        assert_eq!(merged, windows(&[
+            ("Local", &["x"]),
+            ("Other", &["y", "z"]),
+            ("Research", &["a", "b", "c"]),
+        ]));
+    }
+
+    #[test]
+    fn window_names_renamed() {
+        let base: BTreeMap<Uuid, String> = vec![
+            (uuid(WINDOW_1), "Research".to_string()),
+            (uuid(WINDOW_2), "Work".to_string()),
+        ].into_iter().collect();
+
+        // "Research" was renamed on this device, "Work" was renamed on another device
+        let local = vec![
+            window(WINDOW_1, Some("Study"), &["a", "b"]),
+            window(WINDOW_2, Some("Work"), &["x", "y", "z"]),
+        ];
+
+        let remote = windows(&[
+            ("Research", &["a", "b"]),
+            ("Projects", &["x", "y"]),
+        ]);
+
+        let (merged, new_names) = merge_windows(&local, &base, &remote);
+
+        assert_eq!(new_names, names(&[(WINDOW_1, Some("Study")), (WINDOW_2, Some("Projects"))]));
+
+        assert_eq!(merged, windows(&[
+            ("Projects", &["x", "y"]),
+            ("Study", &["a", "b"]),
+        ]));
+    }
+
+    #[test]
+    fn window_names_removed() {
+        let base: BTreeMap<Uuid, String> = vec![
+            (uuid(WINDOW_1), "Research".to_string()),
+        ].into_iter().collect();
+
+        // The name was removed on another device, and the other window doesn't have enough of the same URLs
+        let local = vec![
+            window(WINDOW_1, Some("Research"), &["a", "b"]),
+            window(WINDOW_2, None, &["x"]),
+        ];
+
+        let remote = windows(&[("Other", &["x", "y", "z"])]);
+
+        let (merged, new_names) = merge_windows(&local, &base, &remote);
+
+        assert_eq!(new_names, names(&[(WINDOW_1, None), (WINDOW_2, None)]));
+        assert_eq!(merged, remote);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut seed: u64 = 1;
+
+        // Random URLs don't compress well, so they don't fit into storage.sync
+        let base: Urls = (0..5000).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+
+            let labels: BTreeSet<String> = vec!["x".to_string()].into_iter().collect();
+
+            (format!("https://example.com/{:016x}", seed), labels)
+        }).collect();
+
+        let mut data = SyncData::default();
+        data.urls = base.clone();
+
+        let chunks = data.encode();
+
+        assert!(chunks.len() <= MAX_CHUNKS);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+
+        let remote = SyncData::decode(&chunks).unwrap();
+
+        assert!(remote.truncated);
+        assert!(remote.urls.len() < base.len());
+
+        // Nothing changed on the other device, so it must keep every label, even for the URLs which were dropped
+        assert_eq!(merge_urls(&base, &base, &remote.urls, remote.truncated), base);
+
+        // Removals are still synced for the URLs which weren't dropped
+        let first = base.keys().next().unwrap().clone();
+
+        let mut local = base.clone();
+        local.get_mut(&first).unwrap().insert("y".to_string());
+
+        let merged = merge_urls(&local, &local, &remote.urls, remote.truncated);
+
+        assert_eq!(merged[&first], base[&first]);
+        assert_eq!(merged.len(), base.len());
+    }
+}
@@ -0,0 +1,99 @@
+use uuid::Uuid;
+use js_sys::Date;
+use tab_organizer::{Database, TimeDifference};
+use tab_organizer::keyspace;
+use tab_organizer::state::{Tab, SerializedTab, SerializedTombstone, sidebar};
+use tab_organizer::browser::Id;
+
+use super::State;
+
+
+const DEFAULT_DURATION: f64 = TimeDifference::DAY;
+
+
+pub(crate) fn duration(db: &Database) -> f64 {
+    keyspace::TOMBSTONE_DURATION.get(db).unwrap_or(DEFAULT_DURATION)
+}
+
+pub(crate) fn set_duration(db: &Database, duration: f64) {
+    keyspace::TOMBSTONE_DURATION.set(db, &duration);
+    remove_expired(db);
+}
+
+
+/// Removes the tab record, but keeps a copy of it so that it can be restored if the tab is reopened
+pub(crate) fn bury(db: &Database, tab: SerializedTab, window_uuid: Uuid, tab_index: usize, timestamp_removed: f64) {
+    let uuid = tab.uuid;
+
+    db.tabs().remove(uuid);
+
+    if duration(db) > 0.0 {
+        db.tombstones().set(uuid, &SerializedTombstone {
+            tab,
+            window_uuid,
+            tab_index,
+            timestamp_removed,
+        });
+    }
+}
+
+
+pub(crate) fn remove_expired(db: &Database) {
+    let oldest = Date::now() - duration(db);
+
+    for (uuid, tombstone) in db.tombstones().iter() {
+        if tombstone.timestamp_removed < oldest {
+            db.tombstones().remove(uuid);
+        }
+    }
+}
+
+
+/// If the tab was closed recently then this puts its record back, but it doesn't add it to a window
+pub(crate) fn unbury(db: &Database, tab_uuid: Uuid) -> Option<SerializedTombstone> {
+    if db.tabs().contains(tab_uuid) {
+        return None;
+    }
+
+    let tombstone = db.tombstones().get(tab_uuid)?;
+
+    db.tombstones().remove(tab_uuid);
+    db.tabs().set(tab_uuid, &tombstone.tab);
+
+    Some(tombstone)
+}
+
+
+/// If the tab was closed recently then this puts it back into the window, it returns true if it was restored.
+///
+/// The tab is restored as an unloaded tab, so afterwards it should be handled the same as an unloaded tab which is being loaded.
+pub(crate) fn restore(state: &mut State, tab_uuid: Uuid, window_id: Id) -> bool {
+    let tombstone = match unbury(&state.db, tab_uuid) {
+        Some(tombstone) => tombstone,
+        None => return false,
+    };
+
+    let browser_window = state.window_ids.get_mut(&window_id).unwrap();
+
+    let len = browser_window.serialized.tabs.len();
+
+    // If it was reopened in a different window then it's added to the end, and TabCreated moves it to the correct position
+    let tab_index = if browser_window.serialized.uuid == tombstone.window_uuid {
+        std::cmp::min(tombstone.tab_index, len)
+
+    } else {
+        len
+    };
+
+    browser_window.serialized.tabs.insert(tab_index, tab_uuid);
+    browser_window.serialize(&state.db);
+
+    browser_window.send_message(&sidebar::ServerMessage::TabInserted {
+        tab_index,
+        tab: Tab::unloaded(tombstone.tab),
+    });
+
+    state.reloading_tabs.borrow_mut().insert(tab_uuid);
+
+    true
+}
@@ -0,0 +1,260 @@
+use uuid::Uuid;
+use tab_organizer::state::{Tab, SerializedTab, sidebar};
+use tab_organizer::browser::Id;
+
+use super::State;
+
+
+// The oldest actions are forgotten, so that the history doesn't grow forever
+const MAX_ACTIONS: usize = 50;
+
+
+#[derive(Debug)]
+pub(crate) enum Operation {
+    CloseTabs {
+        uuids: Vec<Uuid>,
+    },
+    /// Puts closed tabs back into the window as unloaded tabs
+    ReopenTabs {
+        tabs: Vec<(usize, SerializedTab)>,
+    },
+    UnloadTabs {
+        uuids: Vec<Uuid>,
+    },
+    LoadTabs {
+        uuids: Vec<Uuid>,
+    },
+    PinTabs {
+        uuids: Vec<Uuid>,
+        pinned: bool,
+    },
+    MoveTabs {
+        uuids: Vec<Uuid>,
+        index: usize,
+    },
+    /// Moves each tab to its index in the browser window
+    RestorePositions {
+        positions: Vec<(Uuid, usize)>,
+    },
+    /// Each tab has its own `timestamp_added`, so that undoing a removed label puts back the original label
+    AddLabelToTabs {
+        label_name: String,
+        tabs: Vec<(Uuid, f64)>,
+    },
+    RemoveLabelFromTabs {
+        uuids: Vec<Uuid>,
+        label_name: String,
+    },
+}
+
+impl Operation {
+    fn is_empty(&self) -> bool {
+        match self {
+            Operation::CloseTabs { uuids } => uuids.is_empty(),
+            Operation::ReopenTabs { tabs } => tabs.is_empty(),
+            Operation::UnloadTabs { uuids } => uuids.is_empty(),
+            Operation::LoadTabs { uuids } => uuids.is_empty(),
+            Operation::PinTabs { uuids, .. } => uuids.is_empty(),
+            Operation::MoveTabs { uuids, .. } => uuids.is_empty(),
+            Operation::RestorePositions { positions } => positions.is_empty(),
+            Operation::AddLabelToTabs { tabs, .. } => tabs.is_empty(),
+            Operation::RemoveLabelFromTabs { uuids, .. } => uuids.is_empty(),
+        }
+    }
+}
+
+
+fn count_tabs(len: usize) -> String {
+    if len == 1 {
+        "1 tab".to_string()
+
+    } else {
+        format!("{} tabs", len)
+    }
+}
+
+
+fn reopen_tabs(state: &mut State, window_id: Id, tabs: Vec<(usize, SerializedTab)>) -> Vec<Uuid> {
+    let window = match state.window_ids.get_mut(&window_id) {
+        Some(window) => window,
+        None => return vec![],
+    };
+
+    let db = &state.db;
+    let ids = &state.ids;
+    let tab_ids = &mut state.tab_ids;
+
+    // The tabs are sorted by their index, so inserting them from left to right puts them back in their old position
+    let uuids = tabs.into_iter().filter_map(|(tab_index, tab)| {
+        let uuid = tab.uuid;
+
+        // The browser hasn't removed the tab yet, so it is still in the window. When it is removed it becomes unloaded instead.
+        if let Some(browser_tab) = ids.get(&uuid).and_then(|id| tab_ids.get_mut(id)) {
+            if browser_tab.is_closing {
+                browser_tab.is_closing = false;
+                browser_tab.is_unloading = true;
+                return Some(uuid);
+
+            } else {
+                return None;
+            }
+        }
+
+        // The tab was already reopened, e.g. with browser.sessions.restore
+        if db.tabs().contains(uuid) {
+            return None;
+        }
+
+        db.tombstones().remove(uuid);
+        db.tabs().set(uuid, &tab);
+
+        let tab_index = std::cmp::min(tab_index, window.serialized.tabs.len());
+
+        window.serialized.tabs.insert(tab_index, uuid);
+
+        window.send_message(&sidebar::ServerMessage::TabInserted {
+            tab_index,
+            tab: Tab::unloaded(tab),
+        });
+
+        Some(uuid)
+    }).collect();
+
+    window.serialize(db);
+
+    uuids
+}
+
+
+/// Runs the operation, it returns a description of what it did, and the operation which reverts it
+fn apply(state: &mut State, window_id: Id, operation: Operation) -> Option<(String, Operation)> {
+    let (description, inverse) = match operation {
+        Operation::CloseTabs { uuids } => {
+            let tabs = state.close_tabs(window_id, &uuids);
+            (format!("Closed {}", count_tabs(tabs.len())), Operation::ReopenTabs { tabs })
+        },
+        Operation::ReopenTabs { tabs } => {
+            let uuids = reopen_tabs(state, window_id, tabs);
+            (format!("Reopened {}", count_tabs(uuids.len())), Operation::CloseTabs { uuids })
+        },
+        Operation::UnloadTabs { uuids } => {
+            let uuids = state.unload_tabs(&uuids);
+            (format!("Unloaded {}", count_tabs(uuids.len())), Operation::LoadTabs { uuids })
+        },
+        Operation::LoadTabs { uuids } => {
+            let uuids = state.load_tabs(window_id, &uuids);
+            (format!("Restored {}", count_tabs(uuids.len())), Operation::UnloadTabs { uuids })
+        },
+        Operation::PinTabs { uuids, pinned } => {
+            let uuids = state.pin_tabs(&uuids, pinned);
+            let verb = if pinned { "Pinned" } else { "Unpinned" };
+            (format!("{} {}", verb, count_tabs(uuids.len())), Operation::PinTabs { uuids, pinned: !pinned })
+        },
+        Operation::MoveTabs { uuids, index } => {
+            let positions = state.move_tabs(window_id, &uuids, index);
+            (format!("Moved {}", count_tabs(positions.len())), Operation::RestorePositions { positions })
+        },
+        Operation::RestorePositions { positions } => {
+            let positions = state.move_tabs_to(window_id, positions);
+            (format!("Moved {}", count_tabs(positions.len())), Operation::RestorePositions { positions })
+        },
+        Operation::AddLabelToTabs { label_name, tabs } => {
+            let uuids = state.add_label(&label_name, &tabs);
+            (format!("Added {} to \"{}\"", count_tabs(uuids.len()), label_name), Operation::RemoveLabelFromTabs { uuids, label_name })
+        },
+        Operation::RemoveLabelFromTabs { uuids, label_name } => {
+            let tabs = state.remove_label(&label_name, &uuids);
+            (format!("Removed {} from \"{}\"", count_tabs(tabs.len()), label_name), Operation::AddLabelToTabs { label_name, tabs })
+        },
+    };
+
+    if inverse.is_empty() {
+        None
+
+    } else {
+        Some((description, inverse))
+    }
+}
+
+
+#[derive(Debug)]
+struct Action {
+    description: String,
+    inverse: Operation,
+}
+
+
+/// The actions which were done in a window, they are forgotten when the window is closed
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    undo: Vec<Action>,
+    redo: Vec<Action>,
+}
+
+impl History {
+    pub(crate) fn message(&self) -> sidebar::ServerMessage {
+        sidebar::ServerMessage::History {
+            undo: self.undo.last().map(|action| action.description.clone()),
+            redo: self.redo.last().map(|action| action.description.clone()),
+        }
+    }
+}
+
+
+fn history(state: &mut State, window_id: Id) -> Option<&mut History> {
+    state.window_ids.get_mut(&window_id).map(|window| &mut window.history)
+}
+
+fn send_history(state: &State, window_id: Id) {
+    if let Some(window) = state.window_ids.get(&window_id) {
+        window.send_message(&window.history.message());
+    }
+}
+
+
+/// Runs an action from the sidebar, so that it can be undone later
+pub(crate) fn perform(state: &mut State, window_id: Id, operation: Operation) {
+    if let Some((description, inverse)) = apply(state, window_id, operation) {
+        if let Some(history) = history(state, window_id) {
+            history.redo.clear();
+
+            history.undo.push(Action { description: description.clone(), inverse });
+
+            if history.undo.len() > MAX_ACTIONS {
+                history.undo.remove(0);
+            }
+        }
+
+        send_history(state, window_id);
+
+        if let Some(window) = state.window_ids.get(&window_id) {
+            window.send_message(&sidebar::ServerMessage::Performed { description });
+        }
+    }
+}
+
+// Pops actions from the first stack until one of them changes something, and then pushes its inverse onto the second stack.
+//
+// The actions which do nothing are dropped, e.g. if the tabs were already reopened from the recently closed menu,
+// otherwise they would stay on top of the stack forever and hide the older actions.
+fn replay<F>(state: &mut State, window_id: Id, mut stacks: F) where F: FnMut(&mut History) -> (&mut Vec<Action>, &mut Vec<Action>) {
+    while let Some(action) = history(state, window_id).and_then(|history| stacks(history).0.pop()) {
+        if let Some((_, inverse)) = apply(state, window_id, action.inverse) {
+            if let Some(history) = history(state, window_id) {
+                stacks(history).1.push(Action { description: action.description, inverse });
+            }
+
+            break;
+        }
+    }
+
+    send_history(state, window_id);
+}
+
+pub(crate) fn undo(state: &mut State, window_id: Id) {
+    replay(state, window_id, |history| (&mut history.undo, &mut history.redo));
+}
+
+pub(crate) fn redo(state: &mut State, window_id: Id) {
+    replay(state, window_id, |history| (&mut history.redo, &mut history.undo));
+}
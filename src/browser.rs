@@ -147,10 +147,16 @@ pub struct TabState {
     pub favicon_url: Option<String>,
     pub title: Option<String>,
     pub url: Option<String>,
+    pub cookie_store_id: Option<String>,
 }
 
 impl TabState {
     fn status(browser_tab: &web_extension::Tab) -> TabStatus {
+        // Tabs which were created with `discarded: true` have the "complete" status
+        if browser_tab.discarded().unwrap_or(false) {
+            return TabStatus::Discarded;
+        }
+
         match browser_tab.status().as_deref() {
             None => TabStatus::New,
             Some("loading") => TabStatus::Loading,
@@ -171,6 +177,7 @@ impl TabState {
             favicon_url: browser_tab.fav_icon_url(),
             title: browser_tab.title(),
             url: browser_tab.url(),
+            cookie_store_id: browser_tab.cookie_store_id(),
         }
     }
 }
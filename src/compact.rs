@@ -0,0 +1,130 @@
+use serde_derive::{Serialize, Deserialize};
+use uuid::Uuid;
+use crate::{Database, serialize_str};
+use crate::state::{SerializedTab, Label};
+
+
+// A lot of tabs share the same favicon (and they are often large data: URLs), so each favicon is only stored once
+pub const FAVICON_PREFIX: &str = "favicon-ids.";
+
+const PINNED: u8 = 1 << 0;
+const MUTED: u8 = 1 << 1;
+const COLLAPSED: u8 = 1 << 2;
+
+
+// This is stored as an array rather than an object, so it doesn't need to store the field names.
+// The timestamps are integers, because they are always whole milliseconds anyways.
+#[derive(Serialize, Deserialize)]
+struct CompactTab(
+    Uuid,
+    Vec<(String, i64)>,
+    i64,
+    Option<i64>,
+    u8,
+    // The id of the favicon
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    // The cookie store id and the opener were added later, so the older records don't have them
+    #[serde(default)]
+    Option<String>,
+    #[serde(default)]
+    Option<Uuid>,
+);
+
+
+pub fn favicon_key(id: &str) -> String {
+    format!("{}{}", FAVICON_PREFIX, id)
+}
+
+// https://en.wikipedia.org/wiki/Fowler%E2%80%93Noll%E2%80%93Vo_hash_function
+fn hash(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ (byte as u64)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns the id for the favicon, it adds it to the `Database` if it doesn't exist yet
+fn favicon_id(db: &Database, url: &str) -> String {
+    let mut hash = hash(url);
+
+    loop {
+        let id = format!("{:016x}", hash);
+        let key = favicon_key(&id);
+
+        match db.get_raw(&key) {
+            Some(existing) => {
+                if existing == url {
+                    return id;
+                }
+            },
+            None => {
+                db.set_raw(&key, url.to_string());
+                return id;
+            },
+        }
+
+        // Hash collision, so it tries the next id
+        hash = hash.wrapping_add(1);
+    }
+}
+
+
+#[inline]
+pub fn is_compact(value: &str) -> bool {
+    value.starts_with('[')
+}
+
+pub fn encode_tab(db: &Database, tab: &SerializedTab) -> String {
+    let mut flags = 0;
+
+    if tab.pinned {
+        flags |= PINNED;
+    }
+
+    if tab.muted {
+        flags |= MUTED;
+    }
+
+    if tab.collapsed {
+        flags |= COLLAPSED;
+    }
+
+    serialize_str(&CompactTab(
+        tab.uuid,
+        tab.labels.iter().map(|label| (label.name.clone(), label.timestamp_added as i64)).collect(),
+        tab.timestamp_created as i64,
+        tab.timestamp_focused.map(|x| x as i64),
+        flags,
+        tab.favicon_url.as_ref().map(|url| favicon_id(db, url)),
+        tab.url.clone(),
+        tab.title.clone(),
+        tab.cookie_store_id.clone(),
+        tab.opener,
+    ))
+}
+
+pub fn decode_tab(value: &str, get_raw: &dyn Fn(&str) -> Option<String>) -> Result<SerializedTab, serde_json::Error> {
+    let CompactTab(uuid, labels, timestamp_created, timestamp_focused, flags, favicon, url, title, cookie_store_id, opener) = serde_json::from_str(value)?;
+
+    Ok(SerializedTab {
+        uuid,
+        labels: labels.into_iter().map(|(name, timestamp_added)| {
+            Label {
+                name,
+                timestamp_added: timestamp_added as f64,
+            }
+        }).collect(),
+        timestamp_created: timestamp_created as f64,
+        timestamp_focused: timestamp_focused.map(|x| x as f64),
+        pinned: flags & PINNED != 0,
+        // TODO should this be reported by fsck if the favicon is missing ?
+        favicon_url: favicon.and_then(|id| get_raw(&favicon_key(&id))),
+        url,
+        title,
+        muted: flags & MUTED != 0,
+        cookie_store_id,
+        opener,
+        collapsed: flags & COLLAPSED != 0,
+    })
+}
@@ -0,0 +1,307 @@
+// https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+const MIN_MATCH: usize = 4;
+
+// The last 5 bytes are always literals, and the last match must start at least 12 bytes before the end
+const LAST_LITERALS: usize = 5;
+const MATCH_FIND_LIMIT: usize = 12;
+
+const MAX_OFFSET: usize = 65535;
+
+const HASH_BITS: u32 = 12;
+
+// Each byte of input can output at most 255 bytes, because the match lengths are stored 255 at a time
+const MAX_RATIO: usize = 255;
+
+
+#[derive(Debug)]
+pub enum DecompressError {
+    Truncated,
+    InvalidOffset,
+    InvalidBase64,
+    InvalidUtf8,
+    TooLarge {
+        size: usize,
+    },
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "The data ended unexpectedly"),
+            Self::InvalidOffset => write!(f, "The data contains an invalid match offset"),
+            Self::InvalidBase64 => write!(f, "The data is not valid base64"),
+            Self::InvalidUtf8 => write!(f, "The data is not valid UTF-8"),
+            Self::TooLarge { size } => write!(f, "The data cannot decompress to {} bytes", size),
+            Self::SizeMismatch { expected, actual } => write!(f, "Expected {} bytes but got {} bytes", expected, actual),
+        }
+    }
+}
+
+
+fn read_u32(input: &[u8], index: usize) -> u32 {
+    (input[index] as u32) |
+    ((input[index + 1] as u32) << 8) |
+    ((input[index + 2] as u32) << 16) |
+    ((input[index + 3] as u32) << 24)
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+
+    output.push(length as u8);
+}
+
+fn write_literals(output: &mut Vec<u8>, literals: &[u8], match_token: u8) {
+    let len = literals.len();
+
+    output.push(((len.min(15) as u8) << 4) | match_token);
+
+    if len >= 15 {
+        write_length(output, len - 15);
+    }
+
+    output.extend_from_slice(literals);
+}
+
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], offset: usize, length: usize) {
+    let length = length - MIN_MATCH;
+
+    write_literals(output, literals, length.min(15) as u8);
+
+    output.push(offset as u8);
+    output.push((offset >> 8) as u8);
+
+    if length >= 15 {
+        write_length(output, length - 15);
+    }
+}
+
+/// Compresses the input into a single LZ4 block, it uses a simple greedy matcher
+pub fn compress_block(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+
+    // The position of the last sequence with the same hash, plus 1
+    let mut table = vec![0; 1 << HASH_BITS];
+
+    let mut anchor = 0;
+    let mut index = 0;
+
+    if input.len() >= MATCH_FIND_LIMIT {
+        let limit = input.len() - MATCH_FIND_LIMIT;
+        let match_limit = input.len() - LAST_LITERALS;
+
+        while index <= limit {
+            let sequence = read_u32(input, index);
+            let hash = hash(sequence);
+            let candidate = table[hash];
+
+            table[hash] = index + 1;
+
+            if candidate != 0 {
+                let candidate = candidate - 1;
+
+                if index - candidate <= MAX_OFFSET && read_u32(input, candidate) == sequence {
+                    let mut length = MIN_MATCH;
+
+                    while index + length < match_limit && input[candidate + length] == input[index + length] {
+                        length += 1;
+                    }
+
+                    write_sequence(&mut output, &input[anchor..index], index - candidate, length);
+
+                    index += length;
+                    anchor = index;
+                    continue;
+                }
+            }
+
+            index += 1;
+        }
+    }
+
+    write_literals(&mut output, &input[anchor..], 0);
+
+    output
+}
+
+
+fn read_length(input: &[u8], index: &mut usize, mut length: usize) -> Result<usize, DecompressError> {
+    if length == 15 {
+        loop {
+            let byte = *input.get(*index).ok_or(DecompressError::Truncated)?;
+            *index += 1;
+
+            length += byte as usize;
+
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+
+    Ok(length)
+}
+
+/// Decompresses a single LZ4 block, `size` is the expected size of the output
+pub fn decompress_block(input: &[u8], size: usize) -> Result<Vec<u8>, DecompressError> {
+    // The size comes from the header, so it is checked before allocating, otherwise corrupt data could allocate gigabytes
+    if size > input.len().saturating_mul(MAX_RATIO) {
+        return Err(DecompressError::TooLarge { size });
+    }
+
+    let mut output = Vec::with_capacity(size);
+
+    let mut index = 0;
+
+    while index < input.len() {
+        let token = input[index];
+        index += 1;
+
+        let literals = read_length(input, &mut index, (token >> 4) as usize)?;
+
+        let end = index + literals;
+
+        output.extend_from_slice(input.get(index..end).ok_or(DecompressError::Truncated)?);
+
+        index = end;
+
+        // The last sequence only contains literals
+        if index == input.len() {
+            break;
+        }
+
+        let offset = input.get(index..(index + 2)).ok_or(DecompressError::Truncated)?;
+        let offset = (offset[0] as usize) | ((offset[1] as usize) << 8);
+        index += 2;
+
+        if offset == 0 || offset > output.len() {
+            return Err(DecompressError::InvalidOffset);
+        }
+
+        let length = read_length(input, &mut index, (token & 15) as usize)? + MIN_MATCH;
+
+        // The match can overlap with the bytes which are being copied, so it has to copy one byte at a time
+        let start = output.len() - offset;
+
+        for i in start..(start + length) {
+            let byte = output[i];
+            output.push(byte);
+        }
+    }
+
+    if output.len() == size {
+        Ok(output)
+
+    } else {
+        Err(DecompressError::SizeMismatch { expected: size, actual: output.len() })
+    }
+}
+
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(input: &[u8]) -> String {
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+
+        let n = ((bytes[0] as u32) << 16) | ((bytes[1] as u32) << 8) | (bytes[2] as u32);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64[((n >> (18 - i * 6)) & 63) as usize] as char);
+
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+fn from_base64(input: &str) -> Result<Vec<u8>, DecompressError> {
+    let input = input.trim_end_matches('=').as_bytes();
+
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+
+    let mut n: u32 = 0;
+    let mut bits = 0;
+
+    for byte in input {
+        let value = BASE64.iter().position(|x| x == byte).ok_or(DecompressError::InvalidBase64)?;
+
+        n = (n << 6) | (value as u32);
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((n >> bits) as u8);
+        }
+    }
+
+    Ok(output)
+}
+
+
+/// Compresses the string with LZ4, the result is base64 so it can be stored in `browser.storage`
+pub fn compress_str(input: &str) -> String {
+    let input = input.as_bytes();
+
+    let len = input.len() as u32;
+
+    let mut output = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8];
+
+    output.extend(compress_block(input));
+
+    to_base64(&output)
+}
+
+pub fn decompress_str(input: &str) -> Result<String, DecompressError> {
+    let input = from_base64(input)?;
+
+    if input.len() < 4 {
+        return Err(DecompressError::Truncated);
+    }
+
+    let output = decompress_block(&input[4..], read_u32(&input, 0) as usize)?;
+
+    String::from_utf8(output).map_err(|_| DecompressError::InvalidUtf8)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{compress_str, decompress_str, decompress_block, DecompressError};
+
+    #[test]
+    fn round_trip() {
+        let input = "hello hello hello hello hello world, this is a test of the compression";
+        assert_eq!(decompress_str(&compress_str(input)).unwrap(), input);
+        assert_eq!(decompress_str(&compress_str("")).unwrap(), "");
+    }
+
+    #[test]
+    fn too_large() {
+        match decompress_block(&[0x10, b'a'], 0xFFFF_FFFF) {
+            Err(DecompressError::TooLarge { size }) => assert_eq!(size, 0xFFFF_FFFF),
+            result => panic!("Expected TooLarge but got {:?}", result),
+        }
+    }
+}
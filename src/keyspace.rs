@@ -0,0 +1,271 @@
+use std::marker::PhantomData;
+use std::collections::{BTreeMap, BTreeSet};
+use wasm_bindgen::JsValue;
+use futures::future::LocalBoxFuture;
+use futures_signals::signal::{Signal, SignalExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+use crate::{Database, serialize_str};
+use crate::compact;
+use crate::state::{SerializedTab, SerializedWindow, SerializedTombstone};
+use crate::state::options::{BackupSummary, MigrationFailure, AutoUnload};
+
+
+/// A key in the `Database` whose value always has the type `T`.
+#[derive(Debug)]
+pub struct Key<T> {
+    name: &'static str,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            value: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Key<T> where T: Serialize + DeserializeOwned {
+    #[inline]
+    pub fn get(&self, db: &Database) -> Option<T> {
+        db.get(self.name)
+    }
+
+    #[inline]
+    pub fn try_get(&self, db: &Database) -> Option<Result<T, serde_json::Error>> {
+        db.try_get(self.name)
+    }
+
+    #[inline]
+    pub fn contains(&self, db: &Database) -> bool {
+        db.contains(self.name)
+    }
+
+    #[inline]
+    pub fn get_or_insert<F>(&self, db: &Database, f: F) -> T where F: FnOnce() -> T {
+        db.get_or_insert(self.name, f)
+    }
+
+    #[inline]
+    pub fn set(&self, db: &Database, value: &T) {
+        db.set(self.name, value)
+    }
+
+    #[inline]
+    pub fn remove(&self, db: &Database) {
+        db.remove(self.name)
+    }
+
+    #[inline]
+    pub fn watch(&self, db: &Database) -> impl Signal<Item = Option<T>> {
+        db.watch(self.name)
+    }
+}
+
+
+pub const VERSION: Key<u32> = Key::new("version");
+pub const WINDOWS: Key<Vec<Uuid>> = Key::new("windows");
+pub const BACKUPS: Key<Vec<BackupSummary>> = Key::new("backups");
+/// The records which were removed because they couldn't be migrated, until they are dismissed on the options page
+pub const MIGRATION_FAILURES: Key<Vec<MigrationFailure>> = Key::new("migration-failures");
+/// Whether new tab records should be stored with `compact::encode_tab`
+pub const COMPACT: Key<bool> = Key::new("compact");
+/// How many milliseconds the closed tabs are kept for
+pub const TOMBSTONE_DURATION: Key<f64> = Key::new("tombstone-duration");
+/// Whether the labels and window names should be synced with `storage.sync`
+pub const SYNC: Key<bool> = Key::new("sync");
+pub const SYNC_TIMESTAMP: Key<f64> = Key::new("sync-timestamp");
+/// The labels for each URL from the last sync, it is used to check which labels were added or removed since then
+pub const SYNCED_URLS: Key<BTreeMap<String, BTreeSet<String>>> = Key::new("sync-urls");
+/// The name of each window on this device from the last sync, it is used to check which windows were renamed since then
+pub const SYNCED_WINDOW_NAMES: Key<BTreeMap<Uuid, String>> = Key::new("sync-window-names");
+pub const AUTO_UNLOAD: Key<AutoUnload> = Key::new("auto-unload");
+
+
+/// A kind of value which is stored once per UUID, the key is `PREFIX` followed by the UUID.
+pub trait Record: Serialize + DeserializeOwned {
+    const PREFIX: &'static str;
+
+    fn key(uuid: Uuid) -> String {
+        format!("{}{}", Self::PREFIX, uuid)
+    }
+
+    /// Converts the record into the string which is stored in the `Database`
+    #[inline]
+    fn encode(&self, _db: &Database) -> String {
+        serialize_str(self)
+    }
+
+    /// The opposite of `encode`, `get_raw` is used to look up any other keys which the record refers to
+    #[inline]
+    fn decode(value: &str, _get_raw: &dyn Fn(&str) -> Option<String>) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(value)
+    }
+}
+
+impl Record for SerializedTab {
+    const PREFIX: &'static str = "tab-ids.";
+
+    fn encode(&self, db: &Database) -> String {
+        if COMPACT.get(db).unwrap_or(false) {
+            compact::encode_tab(db, self)
+
+        } else {
+            serialize_str(self)
+        }
+    }
+
+    // Both formats are always supported, because changing COMPACT doesn't rewrite the records immediately
+    fn decode(value: &str, get_raw: &dyn Fn(&str) -> Option<String>) -> Result<Self, serde_json::Error> {
+        if compact::is_compact(value) {
+            compact::decode_tab(value, get_raw)
+
+        } else {
+            serde_json::from_str(value)
+        }
+    }
+}
+
+impl Record for SerializedWindow {
+    const PREFIX: &'static str = "window-ids.";
+}
+
+impl Record for SerializedTombstone {
+    const PREFIX: &'static str = "tombstone-ids.";
+}
+
+
+/// Every record of type `T` in the `Database`.
+#[derive(Debug)]
+pub struct Records<'a, T> {
+    db: &'a Database,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> Clone for Records<'a, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db,
+            record: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> Records<'a, T> where T: Record {
+    #[inline]
+    pub fn get(&self, uuid: Uuid) -> Option<T> {
+        self.try_get(uuid).map(|value| value.unwrap())
+    }
+
+    pub fn try_get(&self, uuid: Uuid) -> Option<Result<T, serde_json::Error>> {
+        let db = self.db;
+        db.get_raw(&T::key(uuid)).map(|value| T::decode(&value, &|key| db.get_raw(key)))
+    }
+
+    #[inline]
+    pub fn contains(&self, uuid: Uuid) -> bool {
+        self.db.contains(&T::key(uuid))
+    }
+
+    /// See `Database::load`
+    pub fn load<I>(&self, uuids: I) -> LocalBoxFuture<'static, Result<(), JsValue>> where I: IntoIterator<Item = Uuid> {
+        self.db.load(uuids.into_iter().map(T::key))
+    }
+
+    /// See `Database::unload`
+    pub fn unload<I>(&self, uuids: I) -> LocalBoxFuture<'static, Result<(), JsValue>> where I: IntoIterator<Item = Uuid> {
+        self.db.unload(uuids.into_iter().map(T::key))
+    }
+
+    #[inline]
+    pub fn is_loaded(&self, uuid: Uuid) -> bool {
+        self.db.is_key_loaded(&T::key(uuid))
+    }
+
+    pub fn get_or_insert<F>(&self, uuid: Uuid, f: F) -> T where F: FnOnce() -> T {
+        match self.get(uuid) {
+            Some(value) => value,
+            None => {
+                let value = f();
+                self.set(uuid, &value);
+                value
+            },
+        }
+    }
+
+    #[inline]
+    pub fn set(&self, uuid: Uuid, value: &T) {
+        self.db.set_raw(&T::key(uuid), value.encode(self.db))
+    }
+
+    #[inline]
+    pub fn remove(&self, uuid: Uuid) {
+        self.db.remove(&T::key(uuid))
+    }
+
+    pub fn watch(&self, uuid: Uuid) -> impl Signal<Item = Option<T>> {
+        let get_raw = self.db.committed_reader();
+
+        self.db.watch_raw(&T::key(uuid)).map(move |value| {
+            value.map(|value| T::decode(&value, &get_raw).unwrap())
+        })
+    }
+
+    /// Returns the UUID of every record in sorted order, keys which aren't valid UUIDs are skipped
+    pub fn uuids(&self) -> Vec<Uuid> {
+        let mut uuids: Vec<Uuid> = self.db.keys_with_prefix(T::PREFIX)
+            .into_iter()
+            .filter_map(|key| key[T::PREFIX.len()..].parse().ok())
+            .collect();
+
+        uuids.sort();
+        uuids
+    }
+
+    /// Iterates over every record, records which cannot be deserialized are skipped
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, T)> + 'a {
+        let records = self.clone();
+
+        self.uuids().into_iter().filter_map(move |uuid| {
+            match records.try_get(uuid)? {
+                Ok(value) => Some((uuid, value)),
+                Err(_) => None,
+            }
+        })
+    }
+}
+
+
+impl Database {
+    #[inline]
+    pub fn records<T>(&self) -> Records<T> where T: Record {
+        Records {
+            db: self,
+            record: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn tabs(&self) -> Records<SerializedTab> {
+        self.records()
+    }
+
+    #[inline]
+    pub fn windows(&self) -> Records<SerializedWindow> {
+        self.records()
+    }
+
+    #[inline]
+    pub fn tombstones(&self) -> Records<SerializedTombstone> {
+        self.records()
+    }
+}
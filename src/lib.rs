@@ -5,16 +5,18 @@ use lazy_static::lazy_static;
 use std::borrow::Borrow;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
-use futures_signals::signal::{Signal, SignalExt};
+use futures_signals::signal::{Mutable, Signal, SignalExt};
 use futures::channel::mpsc;
 use futures::stream::Stream;
-use futures::try_join;
+use futures::{try_join, FutureExt};
+use futures::future::LocalBoxFuture;
 use std::future::Future;
 use dominator::{clone, RefFn};
 use dominator::animation::{easing, Percentage};
 use uuid::Uuid;
-use js_sys::{Date, Object, Reflect, Array, Set};
+use js_sys::Date;
 use web_sys::{window, Performance, Storage, Blob, Url, BlobPropertyBag};
 use wasm_bindgen_futures::{JsFuture, spawn_local};
 use wasm_bindgen::JsCast;
@@ -22,6 +24,9 @@ use wasm_bindgen::prelude::*;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use web_extension::browser;
+use storage::{StorageBackend, StorageListener, LocalStorage, MemoryStorage, IndexedDbStorage};
+use keyspace::Record;
+use state::SerializedTab;
 
 
 // The logging is written in JS so it will keep working even if Rust/Wasm fails
@@ -66,11 +71,11 @@ extern "C" {
 
 
 // TODO use a better way of downloading which doesn't need the "downloads" permission
-pub fn download(filename: &str, value: &str) -> impl Future<Output = Result<(), JsValue>> {
+pub fn download(filename: &str, mime_type: &str, value: &str) -> impl Future<Output = Result<(), JsValue>> {
     let blob = Blob::new_with_str_sequence_and_options(
         array![ value ].as_ref(),
         BlobPropertyBag::new()
-            .type_("application/json"),
+            .type_(mime_type),
     ).unwrap();
 
     let url = Url::create_object_url_with_blob(&blob).unwrap();
@@ -112,6 +117,10 @@ pub fn fallible_promise(promise: js_sys::Promise) -> impl Future<Output = Option
 
 pub mod state;
 pub mod browser;
+pub mod storage;
+pub mod compress;
+pub mod keyspace;
+pub mod compact;
 
 pub mod styles {
     use lazy_static::lazy_static;
@@ -592,6 +601,12 @@ impl<A> Drop for Listener<A> where A: ?Sized {
     }
 }
 
+impl<A> std::fmt::Debug for Listener<A> where A: ?Sized {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Listener").finish()
+    }
+}
+
 
 pub fn serialize_str<A>(value: &A) -> String where A: Serialize {
     serde_json::to_string(value).unwrap()
@@ -616,76 +631,85 @@ pub fn deserialize<A>(value: &JsValue) -> A where A: DeserializeOwned {
 // It also batches changes so it doesn't need to write to the database as often.
 #[derive(Debug)]
 struct DatabaseFlusher {
+    // TODO verify that this doesn't leak
+    backend: Rc<dyn StorageBackend>,
     // TODO realloc the changes occasionally ?
     changes: Vec<Change>,
     waiting: bool,
+    // If this is false then the changes are only written when `Database::flush` is called
+    auto_commit: bool,
+}
+
+// Only the newest change for each key is kept
+fn merge_changes<I>(changes: I) -> (Vec<(String, String)>, Vec<String>) where I: DoubleEndedIterator<Item = Change> {
+    let mut updated = vec![];
+    let mut removed = vec![];
+
+    let mut seen = HashSet::new();
+
+    for change in changes.rev() {
+        match change {
+            Change::Remove(key) => {
+                // If we have not seen the key yet...
+                if !seen.contains(&key) {
+                    seen.insert(key.clone());
+                    removed.push(key);
+                }
+            },
+            Change::Set(key, value) => {
+                // If we have not seen the key yet...
+                if !seen.contains(&key) {
+                    seen.insert(key.clone());
+                    updated.push((key, value));
+                }
+            },
+        }
+    }
+
+    (updated, removed)
 }
 
 impl DatabaseFlusher {
-    fn new() -> Self {
+    fn new(backend: Rc<dyn StorageBackend>, auto_commit: bool) -> Self {
         Self {
+            backend,
             changes: vec![],
             waiting: false,
+            auto_commit,
         }
     }
 
     fn flush(this: Rc<RefCell<Self>>) {
         let start_merge = performance_now();
 
-        let mut updated = None;
-        let mut removed = None;
-
-        let len = {
+        let (len, backend, updated, removed) = {
             let mut lock = this.borrow_mut();
 
             let len = lock.changes.len();
 
-            {
-                let seen = Set::new(&JsValue::null());
-
-                let mut iter = lock.changes.drain(..);
-
-                while let Some(change) = iter.next_back() {
-                    match change {
-                        Change::Remove(key) => {
-                            // If we have not seen the key yet...
-                            if !seen.has(&key) {
-                                seen.add(&key);
-                                removed.get_or_insert_with(|| Array::new()).push(&key);
-                            }
-                        },
-                        Change::Set(key, value) => {
-                            // If we have not seen the key yet...
-                            if !seen.has(&key) {
-                                seen.add(&key);
-                                Reflect::set(updated.get_or_insert_with(|| Object::new()), &key, &value).unwrap();
-                            }
-                        },
-                    }
-                }
-            }
+            let (updated, removed) = merge_changes(lock.changes.drain(..));
 
             assert_eq!(lock.changes.len(), 0);
-            assert!(updated.is_some() || removed.is_some());
+            assert!(!updated.is_empty() || !removed.is_empty());
 
-            len
+            (len, lock.backend.clone(), updated, removed)
         };
 
         let start_flush = performance_now();
 
         spawn(async move {
             try_join!(
-                async move {
-                    if let Some(updated) = updated {
-                        let _ = JsFuture::from(browser.storage().local().set(&updated)).await?;
+                async {
+                    if !updated.is_empty() {
+                        backend.set(updated).await?;
                     }
 
                     Ok(()) as Result<(), JsValue>
                 },
 
-                async move {
-                    if let Some(removed) = removed {
-                        let _ = JsFuture::from(browser.storage().local().remove(&removed)).await?;
+                async {
+                    if !removed.is_empty() {
+                        backend.remove(removed).await?;
                     }
 
                     Ok(()) as Result<(), JsValue>
@@ -746,8 +770,8 @@ impl DatabaseFlusher {
 
 #[derive(Debug)]
 enum Change {
-    Remove(JsValue),
-    Set(JsValue, JsValue),
+    Remove(String),
+    Set(String, String),
 }
 
 #[derive(Debug)]
@@ -790,6 +814,10 @@ impl TransactionState {
     }
 
     fn start_commit(&mut self, state: &Rc<RefCell<Self>>) {
+        if !self.flusher.borrow().auto_commit {
+            return;
+        }
+
         if let None = self.timer {
             let state = state.clone();
 
@@ -806,37 +834,195 @@ impl TransactionState {
 }
 
 
+#[derive(Debug)]
+struct Watchers {
+    // TODO remove the Mutables which don't have any signals
+    signals: HashMap<String, Mutable<Option<String>>>,
+    // The keys which were changed by this Database, but storage.onChanged hasn't caught up with them yet.
+    // It is None if the backend doesn't send changes.
+    unconfirmed: Option<HashSet<String>>,
+}
+
+impl Watchers {
+    fn changed(&mut self, key: &str, value: Option<&String>) {
+        if let Some(unconfirmed) = &mut self.unconfirmed {
+            unconfirmed.insert(key.to_owned());
+        }
+
+        if let Some(signal) = self.signals.get(key) {
+            signal.set(value.cloned());
+        }
+    }
+
+    fn changed_externally(&mut self, db: &mut HashMap<String, String>, key: String, value: Option<String>) {
+        let unconfirmed = self.unconfirmed.as_mut().unwrap();
+
+        // storage.onChanged also fires for our own changes, but they might have already been overwritten
+        // by a newer local change, so the values from storage.onChanged are ignored until they match.
+        if unconfirmed.contains(&key) {
+            if db.get(&key) == value.as_ref() {
+                unconfirmed.remove(&key);
+            }
+
+        } else if db.get(&key) != value.as_ref() {
+            if let Some(signal) = self.signals.get(&key) {
+                signal.set(value.clone());
+            }
+
+            match value {
+                Some(value) => { db.insert(key, value); },
+                None => { db.remove(&key); },
+            }
+        }
+    }
+}
+
+
+const DATABASE_NAME: &str = "tab-organizer";
+
 #[derive(Debug)]
 pub struct Database {
-    db: Object,
+    db: Rc<RefCell<HashMap<String, String>>>,
     // TODO verify that this doesn't leak
     flusher: Rc<RefCell<DatabaseFlusher>>,
     // TODO verify that this doesn't leak
     state: Rc<RefCell<TransactionState>>,
+    watchers: Rc<RefCell<Watchers>>,
+    _listener: Option<StorageListener>,
+    // Keys which exist in the backend, but their values haven't been loaded yet
+    unloaded: Rc<RefCell<HashSet<String>>>,
+    // Keys which are waiting for `unload`, they are removed from here if they are loaded again
+    unloading: Rc<RefCell<HashSet<String>>>,
+    // The changes for each nested transaction, a value of None means that the key was removed.
+    // They aren't visible outside of the transaction until the outermost transaction succeeds.
+    transactions: RefCell<Vec<HashMap<String, Option<String>>>>,
 }
 
 impl Database {
+    /// Uses IndexedDB, the tabs are loaded lazily.
+    ///
+    /// The first time that it runs it moves everything from `browser.storage.local` into IndexedDB.
     pub fn new() -> impl Future<Output = Result<Self, JsValue>> {
+        async {
+            let backend = IndexedDbStorage::open(DATABASE_NAME, SerializedTab::PREFIX).await?;
+
+            // TODO remove this after enough time has passed
+            if backend.is_empty().await? {
+                let local = LocalStorage::new();
+
+                let values = local.get_all().await?;
+
+                if !values.is_empty() {
+                    let keys = values.keys().cloned().collect();
+                    let len = values.len();
+
+                    backend.set(values.into_iter().collect()).await?;
+
+                    // This is only done after the values were copied, so that nothing is lost if it fails
+                    local.remove(keys).await?;
+
+                    info!("Moved {} keys from storage.local into IndexedDB", len);
+                }
+            }
+
+            Self::new_with_backend(Rc::new(backend)).await
+        }
+    }
+
+    /// Opens the same database as `new`, so the other extension pages can read (and `watch`) the values from the background page.
+    ///
+    /// It doesn't move anything from `browser.storage.local`, because the background page does that.
+    pub fn open() -> impl Future<Output = Result<Self, JsValue>> {
+        async {
+            let backend = IndexedDbStorage::open(DATABASE_NAME, SerializedTab::PREFIX).await?;
+            Self::new_with_backend(Rc::new(backend)).await
+        }
+    }
+
+    /// Loads the values from the backend, changes are flushed back into the same backend.
+    ///
+    /// Some backends load keys lazily, those keys must be loaded with `load` before they can be used.
+    pub fn new_with_backend(backend: Rc<dyn StorageBackend>) -> impl Future<Output = Result<Self, JsValue>> {
         // TODO move this inside the async ?
-        let fut = JsFuture::from(browser.storage().local().get(&JsValue::null()));
+        let values = backend.get_all();
+        let lazy_keys = backend.lazy_keys();
 
         async move {
-            let db = fut.await?;
-            let db: Object = db.unchecked_into();
-            Ok(Self::new_from_object(db))
+            let (db, lazy_keys) = try_join!(values, lazy_keys)?;
+            Ok(Self::new_from_values(backend, db, lazy_keys, true))
         }
     }
 
-    pub fn new_from_object(db: Object) -> Self {
-        let flusher = Rc::new(RefCell::new(DatabaseFlusher::new()));
+    fn new_from_values(backend: Rc<dyn StorageBackend>, db: HashMap<String, String>, lazy_keys: Vec<String>, auto_commit: bool) -> Self {
+        let db = Rc::new(RefCell::new(db));
+        let unloaded: Rc<RefCell<HashSet<String>>> = Rc::new(RefCell::new(lazy_keys.into_iter().collect()));
+
+        let watchers = Rc::new(RefCell::new(Watchers {
+            signals: HashMap::new(),
+            unconfirmed: None,
+        }));
+
+        let listener = backend.on_changed(Box::new(clone!(db, unloaded, watchers => move |changes: Vec<(String, Option<String>)>| {
+            let mut db = db.borrow_mut();
+            let mut unloaded = unloaded.borrow_mut();
+            let mut watchers = watchers.borrow_mut();
+
+            for (key, value) in changes {
+                // The new value is known, so it doesn't need to be loaded anymore
+                unloaded.remove(&key);
+                watchers.changed_externally(&mut db, key, value);
+            }
+        })));
+
+        if listener.is_some() {
+            watchers.borrow_mut().unconfirmed = Some(HashSet::new());
+        }
+
+        let flusher = Rc::new(RefCell::new(DatabaseFlusher::new(backend, auto_commit)));
 
         Self {
             db,
             state: TransactionState::new(flusher.clone(), false),
             flusher,
+            watchers,
+            _listener: listener,
+            unloaded,
+            unloading: Rc::new(RefCell::new(HashSet::new())),
+            transactions: RefCell::new(vec![]),
         }
     }
 
+    /// Creates a database which lives entirely in memory, it starts out with the values in `storage`.
+    ///
+    /// It doesn't use any timers, so the changes are only written into `storage` when `flush` is called.
+    pub fn new_in_memory(storage: MemoryStorage) -> Self {
+        let db = storage.values();
+        Self::new_from_values(Rc::new(storage), db, vec![], false)
+    }
+
+    /// Writes the changes into the backend right away, the `Future` resolves after they are written.
+    ///
+    /// This is only used with `new_in_memory`, the other databases write their changes automatically.
+    pub fn flush(&self) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        assert!(!self.flusher.borrow().auto_commit, "Database::flush can only be used with Database::new_in_memory");
+
+        let (updated, removed) = merge_changes(self.state.borrow_mut().changes.drain(..));
+
+        let backend = self.flusher.borrow().backend.clone();
+
+        async move {
+            if !updated.is_empty() {
+                backend.set(updated).await?;
+            }
+
+            if !removed.is_empty() {
+                backend.remove(removed).await?;
+            }
+
+            Ok(())
+        }.boxed_local()
+    }
+
     pub fn delay_commit(&mut self) {
         {
             let mut state = self.state.borrow_mut();
@@ -858,34 +1044,262 @@ impl Database {
         self.state.borrow_mut().start_commit(&self.state);
     }
 
-    pub fn get_raw(&self, key: &str) -> Option<JsValue> {
-        // TODO make this more efficient
-        let key = JsValue::from(key);
+    /// Runs `f`, the changes which it makes are only applied if it returns `Ok`.
+    ///
+    /// Inside of `f` the changes are visible as usual, but they aren't committed (or sent to `watch`) until
+    /// `f` succeeds. If it returns `Err` then all of the changes are discarded.
+    ///
+    /// Panics are not rolled back, because they abort the whole page.
+    ///
+    /// Transactions can be nested, the changes of the inner transaction are merged into the outer transaction.
+    pub fn transaction<A, E, F>(&self, f: F) -> Result<A, E> where F: FnOnce(&Self) -> Result<A, E> {
+        let depth = {
+            let mut transactions = self.transactions.borrow_mut();
+            transactions.push(HashMap::new());
+            transactions.len()
+        };
 
-        if Reflect::has(&self.db, &key).unwrap() {
-            Some(Reflect::get(&self.db, &key).unwrap())
+        let result = f(self);
 
-        } else {
-            None
+        let changes = {
+            let mut transactions = self.transactions.borrow_mut();
+
+            assert_eq!(transactions.len(), depth);
+
+            let changes = transactions.pop().unwrap();
+
+            if result.is_ok() {
+                if let Some(parent) = transactions.last_mut() {
+                    parent.extend(changes);
+                    return result;
+                }
+
+                changes
+
+            } else {
+                return result;
+            }
+        };
+
+        for (key, value) in changes {
+            self.apply(key, value);
         }
+
+        result
     }
 
-    pub fn set_raw(&self, key: &str, value: JsValue) {
-        // TODO make this more efficient
-        let key = JsValue::from(key);
+    fn apply(&self, key: String, value: Option<String>) {
+        self.unloaded.borrow_mut().remove(&key);
 
-        Reflect::set(&self.db, &key, &value).unwrap();
+        self.watchers.borrow_mut().changed(&key, value.as_ref());
 
         let mut state = self.state.borrow_mut();
 
-        state.changes.push(Change::Set(key, value));
+        match value {
+            Some(value) => {
+                self.db.borrow_mut().insert(key.clone(), value.clone());
+                state.changes.push(Change::Set(key, value));
+            },
+            None => {
+                self.db.borrow_mut().remove(&key);
+                state.changes.push(Change::Remove(key));
+            },
+        }
 
         state.start_commit(&self.state);
     }
 
+    fn change(&self, key: String, value: Option<String>) {
+        let mut transactions = self.transactions.borrow_mut();
+
+        match transactions.last_mut() {
+            Some(changes) => {
+                changes.insert(key, value);
+            },
+            None => {
+                drop(transactions);
+                self.apply(key, value);
+            },
+        }
+    }
+
+    /// Loads the values for `keys`, this must be done before the keys can be used.
+    ///
+    /// It does nothing for the keys which are already loaded.
+    // This is boxed so that the Future doesn't depend on the lifetime of `I`
+    pub fn load<I>(&self, keys: I) -> LocalBoxFuture<'static, Result<(), JsValue>> where I: IntoIterator<Item = String> {
+        let keys: Vec<String> = {
+            let unloaded = self.unloaded.borrow();
+            let mut unloading = self.unloading.borrow_mut();
+
+            keys.into_iter().filter(|key| {
+                // The key is needed again, so it must not be unloaded
+                unloading.remove(key);
+                unloaded.contains(key)
+            }).collect()
+        };
+
+        let fut = if keys.is_empty() {
+            None
+
+        } else {
+            Some(self.flusher.borrow().backend.get(keys.clone()))
+        };
+
+        let db = self.db.clone();
+        let unloaded = self.unloaded.clone();
+        let watchers = self.watchers.clone();
+
+        async move {
+            if let Some(fut) = fut {
+                let mut values = fut.await?;
+
+                let mut db = db.borrow_mut();
+                let mut unloaded = unloaded.borrow_mut();
+                let watchers = watchers.borrow();
+
+                for key in keys {
+                    // If the key was changed while it was loading then the new value is used instead
+                    if unloaded.remove(&key) {
+                        if let Some(value) = values.remove(&key) {
+                            if let Some(signal) = watchers.signals.get(&key) {
+                                signal.set(Some(value.clone()));
+                            }
+
+                            db.insert(key, value);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }.boxed_local()
+    }
+
+    /// Removes the values for `keys` from memory, they must be loaded with `load` before they can be used again.
+    ///
+    /// A key is only unloaded if its value has already been written into the backend, and nothing is watching it.
+    /// If the key is loaded again before the `Future` finishes then it stays loaded.
+    pub fn unload<I>(&self, keys: I) -> LocalBoxFuture<'static, Result<(), JsValue>> where I: IntoIterator<Item = String> {
+        let keys: Vec<String> = {
+            let db = self.db.borrow();
+            let mut unloading = self.unloading.borrow_mut();
+
+            keys.into_iter().filter(|key| db.contains_key(key)).inspect(|key| {
+                unloading.insert(key.clone());
+            }).collect()
+        };
+
+        let fut = if keys.is_empty() {
+            None
+
+        } else {
+            Some(self.flusher.borrow().backend.get(keys.clone()))
+        };
+
+        let db = self.db.clone();
+        let unloaded = self.unloaded.clone();
+        let unloading = self.unloading.clone();
+        let watchers = self.watchers.clone();
+
+        async move {
+            if let Some(fut) = fut {
+                let values = fut.await?;
+
+                let mut db = db.borrow_mut();
+                let mut unloaded = unloaded.borrow_mut();
+                let mut unloading = unloading.borrow_mut();
+                let watchers = watchers.borrow();
+
+                for key in keys {
+                    if unloading.remove(&key) {
+                        let is_watched = watchers.signals.contains_key(&key);
+
+                        let is_unconfirmed = watchers.unconfirmed.as_ref()
+                            .map(|unconfirmed| unconfirmed.contains(&key))
+                            .unwrap_or(false);
+
+                        // If the values are different then there is a change which hasn't been written yet
+                        let is_written = match (db.get(&key), values.get(&key)) {
+                            (Some(value), Some(written)) => value == written,
+                            _ => false,
+                        };
+
+                        if is_written && !is_watched && !is_unconfirmed {
+                            db.remove(&key);
+                            unloaded.insert(key);
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }.boxed_local()
+    }
+
+    pub fn load_prefix(&self, prefix: &str) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let keys: Vec<String> = self.unloaded.borrow().iter().filter(|key| key.starts_with(prefix)).cloned().collect();
+        self.load(keys)
+    }
+
+    #[inline]
+    pub fn load_all(&self) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        self.load_prefix("")
+    }
+
+    /// Returns true if every key is loaded
+    pub fn is_loaded(&self) -> bool {
+        self.unloaded.borrow().is_empty()
+    }
+
+    pub fn is_key_loaded(&self, key: &str) -> bool {
+        !self.unloaded.borrow().contains(key)
+    }
+
+    // Reading an unloaded key is a bug, but it behaves as if the key doesn't exist, so that it doesn't break the whole page
+    fn check_loaded(&self, key: &str) -> bool {
+        if self.is_key_loaded(key) {
+            true
+
+        } else {
+            warn!("Key {} must be loaded with Database::load before it can be used", key);
+            false
+        }
+    }
+
+    // This looks inside of the current transactions first, so that the changes are visible inside of the transaction
+    fn with_raw<A, F>(&self, key: &str, f: F) -> A where F: FnOnce(Option<&String>) -> A {
+        let transactions = self.transactions.borrow();
+
+        for changes in transactions.iter().rev() {
+            if let Some(value) = changes.get(key) {
+                return f(value.as_ref());
+            }
+        }
+
+        if self.check_loaded(key) {
+            f(self.db.borrow().get(key))
+
+        } else {
+            f(None)
+        }
+    }
+
+    pub fn get_raw(&self, key: &str) -> Option<String> {
+        self.with_raw(key, |value| value.cloned())
+    }
+
+    pub fn set_raw(&self, key: &str, value: String) {
+        self.change(key.to_owned(), Some(value));
+    }
+
     pub fn get<T>(&self, key: &str) -> Option<T> where T: DeserializeOwned {
-        let value = self.get_raw(key)?;
-        Some(deserialize(&value))
+        self.with_raw(key, |value| value.map(|value| deserialize_str(value)))
+    }
+
+    /// Like `get`, except it returns an error if the value cannot be deserialized
+    pub fn try_get<T>(&self, key: &str) -> Option<Result<T, serde_json::Error>> where T: DeserializeOwned {
+        self.with_raw(key, |value| value.map(|value| serde_json::from_str(value)))
     }
 
     pub fn get_or_insert<T, F>(&self, key: &str, f: F) -> T
@@ -902,37 +1316,90 @@ impl Database {
     }
 
     pub fn set<T>(&self, key: &str, value: &T) where T: Serialize {
-        self.set_raw(key, serialize(value));
+        self.set_raw(key, serialize_str(value));
     }
 
-    fn remove_raw(&self, key: JsValue) {
-        Reflect::delete_property(&self.db, &key).unwrap();
+    pub fn remove(&self, key: &str) {
+        self.change(key.to_owned(), None);
+    }
 
-        let mut state = self.state.borrow_mut();
+    // This doesn't need the key to be loaded
+    pub fn contains(&self, key: &str) -> bool {
+        for changes in self.transactions.borrow().iter().rev() {
+            if let Some(value) = changes.get(key) {
+                return value.is_some();
+            }
+        }
 
-        state.changes.push(Change::Remove(key));
+        self.db.borrow().contains_key(key) || self.unloaded.borrow().contains(key)
+    }
 
-        state.start_commit(&self.state);
+    /// Returns a signal of the value for `key`, it changes whenever the key is set or removed.
+    ///
+    /// That includes changes made by other extension pages, if the backend supports it.
+    ///
+    /// If the key isn't loaded then the signal is `None` until the key is loaded.
+    pub fn watch_raw(&self, key: &str) -> impl Signal<Item = Option<String>> {
+        self.check_loaded(key);
+
+        let mut watchers = self.watchers.borrow_mut();
+
+        watchers.signals.entry(key.to_owned())
+            // This uses the committed value, because the signal doesn't see the changes of the current transaction
+            .or_insert_with(|| Mutable::new(self.db.borrow().get(key).cloned()))
+            .signal_cloned()
     }
 
-    pub fn remove(&self, key: &str) {
-        // TODO make this more efficient
-        let key = JsValue::from(key);
-        self.remove_raw(key);
+    pub fn watch<T>(&self, key: &str) -> impl Signal<Item = Option<T>> where T: DeserializeOwned {
+        self.watch_raw(key).map(|value| value.map(|value| deserialize_str(&value)))
+    }
+
+    // This is used by the signals from `watch`, so it only sees the committed values
+    pub(crate) fn committed_reader(&self) -> impl Fn(&str) -> Option<String> + 'static {
+        let db = self.db.clone();
+        move |key| db.borrow().get(key).cloned()
+    }
+
+    /// Returns the number of bytes which are used by the backend, or `None` if the backend doesn't support it
+    pub fn bytes_in_use(&self) -> impl Future<Output = Result<Option<f64>, JsValue>> {
+        self.flusher.borrow().backend.bytes_in_use()
+    }
+
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut keys: HashSet<String> = self.db.borrow().keys()
+            .chain(self.unloaded.borrow().iter())
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+
+        for changes in self.transactions.borrow().iter() {
+            for (key, value) in changes.iter().filter(|(key, _)| key.starts_with(prefix)) {
+                if value.is_some() {
+                    keys.insert(key.clone());
+
+                } else {
+                    keys.remove(key);
+                }
+            }
+        }
+
+        keys.into_iter().collect()
     }
 
     pub fn clear(&self) {
-        for key in Object::keys(&self.db).iter() {
-            self.remove_raw(key);
+        for key in self.keys_with_prefix("") {
+            self.change(key, None);
         }
     }
 
     pub fn to_json(&self) -> String {
-        js_sys::JSON::stringify_with_replacer_and_space(&self.db, &JsValue::UNDEFINED, &JsValue::from(2)).unwrap().into()
+        // This sorts the keys so that the output is deterministic
+        let db: BTreeMap<&String, &String> = self.db.borrow().iter().collect();
+        serde_json::to_string_pretty(&db).unwrap()
     }
 
     pub fn debug(&self) {
-        web_sys::console::log_1(&self.db);
+        log!("{:#?}", self.db.borrow());
     }
 }
 
@@ -1386,3 +1853,169 @@ pub fn every_hour<F>(mut f: F) where F: FnMut() + 'static {
         every_hour(f);
     }).forget();
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::Database;
+    use crate::storage::MemoryStorage;
+    use std::pin::Pin;
+    use std::task::Poll;
+    use futures::executor::block_on;
+    use futures::future::poll_fn;
+    use futures_signals::signal::Signal;
+
+    // Returns the new value of the signal, or None if it hasn't changed
+    fn poll<S>(signal: &mut Pin<Box<S>>) -> Option<S::Item> where S: Signal {
+        match block_on(poll_fn(|cx| Poll::Ready(signal.as_mut().poll_change(cx)))) {
+            Poll::Ready(Some(value)) => Some(value),
+            Poll::Ready(None) => panic!("Signal ended"),
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let storage = MemoryStorage::new();
+        let db = Database::new_in_memory(storage.clone());
+
+        db.set("foo", &vec![1, 2, 3]);
+        db.set("bar", &"hello");
+        db.set("qux", &true);
+        db.remove("qux");
+
+        assert_eq!(db.get::<Vec<u32>>("foo"), Some(vec![1, 2, 3]));
+        assert_eq!(db.get::<String>("bar"), Some("hello".to_string()));
+        assert_eq!(db.get::<bool>("qux"), None);
+
+        // Nothing is written until it is flushed
+        assert!(storage.values().is_empty());
+
+        block_on(db.flush()).unwrap();
+
+        let values = storage.values();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["foo"], "[1,2,3]");
+        assert_eq!(values["bar"], "\"hello\"");
+
+        // A new database sees the flushed values
+        let db = Database::new_in_memory(storage.clone());
+
+        assert_eq!(db.get::<Vec<u32>>("foo"), Some(vec![1, 2, 3]));
+
+        db.remove("foo");
+        block_on(db.flush()).unwrap();
+
+        assert!(!storage.values().contains_key("foo"));
+        assert!(storage.values().contains_key("bar"));
+    }
+
+    #[test]
+    fn watch() {
+        let storage = MemoryStorage::new();
+        let db = Database::new_in_memory(storage.clone());
+
+        // This is like another extension page, which shares the same storage
+        let other = Database::new_in_memory(storage.clone());
+
+        let mut signal = Box::pin(db.watch::<u32>("foo"));
+
+        assert_eq!(poll(&mut signal), Some(None));
+        assert_eq!(poll(&mut signal), None);
+
+        // Local changes are seen immediately
+        db.set("foo", &1);
+        assert_eq!(poll(&mut signal), Some(Some(1)));
+
+        block_on(db.flush()).unwrap();
+        assert_eq!(poll(&mut signal), None);
+        assert_eq!(other.get::<u32>("foo"), Some(1));
+
+        // External changes are seen after they are flushed
+        other.set("foo", &2);
+        assert_eq!(poll(&mut signal), None);
+
+        block_on(other.flush()).unwrap();
+        assert_eq!(poll(&mut signal), Some(Some(2)));
+        assert_eq!(db.get::<u32>("foo"), Some(2));
+
+        other.remove("foo");
+        block_on(other.flush()).unwrap();
+        assert_eq!(poll(&mut signal), Some(None));
+        assert_eq!(db.get::<u32>("foo"), None);
+    }
+
+    #[test]
+    fn transaction() {
+        let storage = MemoryStorage::new();
+        let db = Database::new_in_memory(storage.clone());
+
+        db.set("foo", &1);
+
+        let result: Result<(), ()> = db.transaction(|db| {
+            db.set("foo", &2);
+            db.set("bar", &3);
+
+            // The changes are visible inside of the transaction
+            assert_eq!(db.get::<u32>("foo"), Some(2));
+            Err(())
+        });
+
+        assert_eq!(result, Err(()));
+        assert_eq!(db.get::<u32>("foo"), Some(1));
+        assert_eq!(db.get::<u32>("bar"), None);
+
+        let result: Result<(), ()> = db.transaction(|db| {
+            db.remove("foo");
+
+            // The inner transaction is merged into the outer transaction
+            db.transaction(|db| {
+                db.set("bar", &3);
+                Ok(())
+            })
+        });
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(db.get::<u32>("foo"), None);
+        assert_eq!(db.get::<u32>("bar"), Some(3));
+
+        block_on(db.flush()).unwrap();
+
+        let values = storage.values();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["bar"], "3");
+    }
+
+    #[test]
+    fn unload() {
+        let storage = MemoryStorage::new();
+        let db = Database::new_in_memory(storage.clone());
+
+        db.set("foo", &1);
+        db.set("bar", &2);
+        block_on(db.flush()).unwrap();
+
+        // This change hasn't been written yet, so it must stay loaded
+        db.set("bar", &3);
+
+        block_on(db.unload(vec!["foo".to_string(), "bar".to_string()])).unwrap();
+
+        assert!(!db.is_key_loaded("foo"));
+        assert!(db.is_key_loaded("bar"));
+        assert!(db.contains("foo"));
+        assert_eq!(db.get::<u32>("bar"), Some(3));
+
+        block_on(db.load(vec!["foo".to_string()])).unwrap();
+
+        assert!(db.is_key_loaded("foo"));
+        assert_eq!(db.get::<u32>("foo"), Some(1));
+
+        // Loading the key before the unload finishes keeps it loaded
+        let fut = db.unload(vec!["foo".to_string()]);
+        block_on(db.load(vec!["foo".to_string()])).unwrap();
+        block_on(fut).unwrap();
+
+        assert!(db.is_key_loaded("foo"));
+        assert_eq!(db.get::<u32>("foo"), Some(1));
+    }
+}
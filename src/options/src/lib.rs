@@ -2,31 +2,156 @@
 
 use std::rc::Rc;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use js_sys::{Date, Uint8Array};
 use dominator::{Dom, clone, html, events, with_node};
-use tab_organizer::{log, info, connect, panic_hook, set_print_logs, Port};
-use tab_organizer::state::{options, SerializedTab};
-use web_sys::HtmlTextAreaElement;
+use tab_organizer::{log, info, connect, panic_hook, set_print_logs, spawn, Port, Database, TimeDifference};
+use tab_organizer::keyspace;
+use tab_organizer::state::options;
+use tab_organizer::state::options::{ImportMode, ImportError, ImportFormat, ExportFormat, ExportFilter, WindowSummary, BackupSummary, BackupReason, DatabaseProblem, StorageUsage, SyncStatus, MigrationFailure, AutoUnload, KEEP_LOADED_LABEL};
+use web_sys::{HtmlTextAreaElement, HtmlSelectElement, HtmlInputElement, File, Response};
 use futures_signals::signal::{Mutable, SignalExt};
 use futures::FutureExt;
 use futures::stream::{StreamExt, TryStreamExt};
 
 
+mod mozlz4;
+
+
+const TOMBSTONE_DURATIONS: [(&str, f64); 5] = [
+    ("Forget closed tabs immediately", 0.0),
+    ("Remember closed tabs for 1 hour", TimeDifference::HOUR),
+    ("Remember closed tabs for 1 day", TimeDifference::DAY),
+    ("Remember closed tabs for 1 week", TimeDifference::WEEK),
+    ("Remember closed tabs for 30 days", 30.0 * TimeDifference::DAY),
+];
+
+const IDLE_DURATIONS: [(&str, f64); 5] = [
+    ("Unload tabs after 15 minutes", 15.0 * TimeDifference::MINUTE),
+    ("Unload tabs after 30 minutes", 30.0 * TimeDifference::MINUTE),
+    ("Unload tabs after 1 hour", TimeDifference::HOUR),
+    ("Unload tabs after 4 hours", 4.0 * TimeDifference::HOUR),
+    ("Unload tabs after 1 day", TimeDifference::DAY),
+];
+
+
+#[derive(Debug)]
+struct Preview {
+    format: ImportFormat,
+    windows: Vec<WindowSummary>,
+    labels: Vec<String>,
+}
+
+
 #[derive(Debug)]
 struct State {
     port: Rc<Port<options::ClientMessage, options::ServerMessage>>,
+    // This is only used for reading, the background page is the only page which writes to the database
+    db: Rc<Database>,
     loading: Mutable<bool>,
-    tabs: Mutable<Vec<SerializedTab>>,
+    export_filters: Mutable<Vec<(String, ExportFilter)>>,
+    export_filter: Mutable<ExportFilter>,
+    import_data: Mutable<String>,
+    import_preview: Mutable<Option<Preview>>,
+    imported: Mutable<Option<(usize, usize)>>,
+    import_errors: Mutable<Vec<ImportError>>,
+    database_check: Mutable<Option<(Vec<DatabaseProblem>, usize)>>,
+    storage_usage: Mutable<Option<StorageUsage>>,
+    compact_error: Mutable<Option<String>>,
+    sync_status: Mutable<Option<SyncStatus>>,
+    migration_failures: Mutable<Vec<MigrationFailure>>,
+    tombstone_duration: Mutable<Option<f64>>,
+    auto_unload: Mutable<Option<AutoUnload>>,
 }
 
 impl State {
-    fn new(port: Rc<Port<options::ClientMessage, options::ServerMessage>>) -> Rc<Self> {
+    fn new(port: Rc<Port<options::ClientMessage, options::ServerMessage>>, db: Rc<Database>) -> Rc<Self> {
         Rc::new(Self {
             port,
+            db,
             loading: Mutable::new(false),
-            tabs: Mutable::new(vec![]),
+            export_filters: Mutable::new(vec![]),
+            export_filter: Mutable::new(ExportFilter::All),
+            import_data: Mutable::new(String::new()),
+            import_preview: Mutable::new(None),
+            imported: Mutable::new(None),
+            import_errors: Mutable::new(vec![]),
+            database_check: Mutable::new(None),
+            storage_usage: Mutable::new(None),
+            compact_error: Mutable::new(None),
+            sync_status: Mutable::new(None),
+            migration_failures: Mutable::new(vec![]),
+            tombstone_duration: Mutable::new(None),
+            auto_unload: Mutable::new(None),
         })
     }
 
+    fn set_summary(&self, windows: Vec<WindowSummary>, labels: Vec<String>) {
+        let mut filters = vec![("All windows".to_string(), ExportFilter::All)];
+
+        filters.extend(windows.into_iter().map(|window| {
+            (format!("Window: {} ({} tabs)", window.name, window.tabs), ExportFilter::Window { uuid: window.uuid })
+        }));
+
+        filters.extend(labels.into_iter().map(|name| {
+            (format!("Label: {}", name), ExportFilter::Label { name })
+        }));
+
+        // The window or label might not exist anymore after an import
+        {
+            let mut filter = self.export_filter.lock_mut();
+
+            if !filters.iter().any(|(_, x)| *x == *filter) {
+                *filter = ExportFilter::All;
+            }
+        }
+
+        self.export_filters.set(filters);
+    }
+
+    fn preview_import(&self, data: String) {
+        self.import_preview.set(None);
+        self.imported.set(None);
+        self.import_errors.set(vec![]);
+
+        if !data.is_empty() {
+            self.port.send_message(&options::ClientMessage::PreviewImport { data: data.clone() });
+        }
+
+        self.import_data.set(data);
+    }
+
+    // Firefox session files are compressed, so they are decompressed before sending them to the background page
+    fn import_file(state: Rc<Self>, file: File) {
+        spawn(async move {
+            let response = Response::new_with_opt_blob(Some(&file))?;
+            let buffer = JsFuture::from(response.array_buffer()?).await?;
+
+            let array = Uint8Array::new(&buffer);
+            let mut bytes = vec![0; array.length() as usize];
+            array.copy_to(&mut bytes);
+
+            if mozlz4::is_mozlz4(&bytes) {
+                let decompressed = mozlz4::decompress(&bytes)
+                    .map_err(|error| error.to_string())
+                    .and_then(|bytes| String::from_utf8(bytes).map_err(|error| error.to_string()));
+
+                match decompressed {
+                    Ok(data) => state.preview_import(data),
+                    Err(message) => {
+                        state.import_preview.set(None);
+                        state.import_errors.set(vec![ImportError::InvalidMozLz4 { message }]);
+                    },
+                }
+
+            } else {
+                state.preview_import(String::from_utf8_lossy(&bytes).into_owned());
+            }
+
+            Ok(())
+        });
+    }
+
     fn button<F>(name: &str, mut f: F) -> Dom where F: FnMut() + 'static {
         let hovered = Mutable::new(false);
         let pressed = Mutable::new(false);
@@ -74,34 +199,453 @@ impl State {
         })
     }
 
-    fn render(state: Rc<Self>) -> Dom {
+    fn export_button(name: &str, format: ExportFormat, state: Rc<Self>) -> Dom {
+        Self::button(name, move || {
+            state.loading.set_neq(true);
+            let filter = state.export_filter.get_cloned();
+            state.port.send_message(&options::ClientMessage::Export { format, filter });
+        })
+    }
+
+    fn import_button(name: &str, mode: ImportMode, state: Rc<Self>) -> Dom {
+        Self::button(name, move || {
+            let data = state.import_data.lock_ref();
+
+            if !data.is_empty() {
+                state.port.send_message(&options::ClientMessage::Import { data: data.clone(), mode });
+            }
+        })
+    }
+
+    fn render_backup(backup: &BackupSummary, state: &Rc<Self>) -> Dom {
+        let date = Date::new(&JsValue::from(backup.timestamp_created));
+        let uuid = backup.uuid;
+
+        let reason = match backup.reason {
+            BackupReason::Regular => "",
+            BackupReason::BeforeRestore => " (before restoring)",
+            BackupReason::BeforeMigration => " (before updating)",
+        };
+
         html!("div", {
+            .text(&format!("{}{}: {} tabs in {} windows ", String::from(date.to_locale_string("default", &JsValue::UNDEFINED)), reason, backup.tabs, backup.windows))
+
             .children(&mut [
-                Self::button("Export", clone!(state => move || {
-                    state.loading.set_neq(true);
-                    state.port.send_message(&options::ClientMessage::Export);
+                Self::button("Restore (replace)", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::RestoreBackup { uuid, mode: ImportMode::Replace });
+                })),
+
+                Self::button("Restore (merge)", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::RestoreBackup { uuid, mode: ImportMode::Merge });
                 })),
+            ])
+        })
+    }
+
+    fn render_usage(usage: &StorageUsage, state: &Rc<Self>) -> Vec<Dom> {
+        fn pretty_bytes(bytes: f64) -> String {
+            if bytes >= 1_048_576.0 {
+                format!("{:.1} MB", bytes / 1_048_576.0)
+
+            } else {
+                format!("{:.1} KB", bytes / 1024.0)
+            }
+        }
+
+        let mut children: Vec<Dom> = usage.records.iter().map(|record| {
+            html!("div", {
+                .text(&format!("{}: {} records, {}", record.name, record.count, pretty_bytes(record.bytes as f64)))
+            })
+        }).collect();
+
+        let total = usage.records.iter().map(|record| record.bytes).sum::<usize>() as f64;
+
+        children.push(html!("div", {
+            .text(&match usage.bytes_in_use {
+                Some(bytes) => format!("Total: {} (the browser reports {})", pretty_bytes(total), pretty_bytes(bytes)),
+                None => format!("Total: {}", pretty_bytes(total)),
+            })
+        }));
+
+        let compact = usage.compact;
+
+        children.push(Self::button(if compact { "Stop compacting storage" } else { "Compact storage" }, clone!(state => move || {
+            state.compact_error.set(None);
+            state.port.send_message(&options::ClientMessage::SetCompact { compact: !compact });
+        })));
+
+        children.push(html!("div", {
+            .style("color", "red")
+            .text_signal(state.compact_error.signal_cloned().map(|error| error.unwrap_or_else(String::new)))
+        }));
+
+        children
+    }
+
+    fn render_migration_failures(failures: &[MigrationFailure], state: &Rc<Self>) -> Vec<Dom> {
+        if failures.is_empty() {
+            return vec![];
+        }
+
+        let mut children = vec![
+            html!("div", {
+                .text("Some records could not be updated to the new version, they can be restored from the backup which was made before updating:")
+            }),
+        ];
+
+        children.extend(failures.into_iter().map(|failure| {
+            html!("div", {
+                .text(&failure.to_string())
+            })
+        }));
+
+        children.push(Self::button("Dismiss", clone!(state => move || {
+            state.port.send_message(&options::ClientMessage::DismissMigrationFailures);
+        })));
+
+        children
+    }
+
+    fn change_auto_unload<F>(&self, f: F) where F: FnOnce(&mut AutoUnload) {
+        if let Some(mut settings) = self.auto_unload.get_cloned() {
+            f(&mut settings);
+            self.port.send_message(&options::ClientMessage::SetAutoUnload { settings });
+        }
+    }
+
+    fn render_auto_unload(settings: &AutoUnload, state: &Rc<Self>) -> Vec<Dom> {
+        let enabled = settings.enabled;
+
+        let mut children = vec![
+            Self::button(if enabled { "Stop unloading idle tabs" } else { "Automatically unload idle tabs" }, clone!(state => move || {
+                state.change_auto_unload(|settings| settings.enabled = !enabled);
+            })),
+        ];
+
+        if enabled {
+            let discard = settings.discard;
+
+            children.push(Self::button(if discard { "Unload idle tabs instead of discarding them" } else { "Discard idle tabs instead of unloading them" }, clone!(state => move || {
+                state.change_auto_unload(|settings| settings.discard = !discard);
+            })));
+
+            let idle_duration = settings.idle_duration;
+
+            children.push(html!("select" => HtmlSelectElement, {
+                .children(&mut IDLE_DURATIONS.iter().map(|(name, duration)| {
+                    html!("option", {
+                        .property("selected", idle_duration == *duration)
+                        .text(name)
+                    })
+                }).collect::<Vec<Dom>>())
+
+                .with_node!(element => {
+                    .event(clone!(state => move |_: events::Change| {
+                        let index = element.selected_index();
+
+                        if index >= 0 {
+                            let (_, duration) = IDLE_DURATIONS[index as usize];
+                            state.change_auto_unload(|settings| settings.idle_duration = duration);
+                        }
+                    }))
+                })
+            }));
+
+            children.push(html!("input" => HtmlInputElement, {
+                .attribute("type", "number")
+                .attribute("min", "1")
+                .attribute("placeholder", "Maximum loaded tabs per window")
+                .property("value", settings.max_loaded_tabs.map(|max| max.to_string()).unwrap_or_else(String::new))
+
+                .with_node!(element => {
+                    .event(clone!(state => move |_: events::Change| {
+                        // If it's empty then there isn't a limit
+                        let max = element.value().trim().parse::<usize>().ok().filter(|max| *max > 0);
+                        state.change_auto_unload(|settings| settings.max_loaded_tabs = max);
+                    }))
+                })
+            }));
+
+            children.push(html!("textarea" => HtmlTextAreaElement, {
+                .attribute("placeholder", "Domains which are never unloaded, one per line")
+                .property("value", settings.excluded_domains.join("\n"))
+
+                .with_node!(element => {
+                    .event(clone!(state => move |_: events::Change| {
+                        let domains = element.value().lines()
+                            .map(|line| line.trim().to_string())
+                            .filter(|line| !line.is_empty())
+                            .collect();
+
+                        state.change_auto_unload(|settings| settings.excluded_domains = domains);
+                    }))
+                })
+            }));
+
+            children.push(html!("div", {
+                .text(&format!("Pinned tabs, tabs which are playing audio, and tabs with the \"{}\" label are never unloaded", KEEP_LOADED_LABEL))
+            }));
+        }
+
+        children
+    }
+
+    fn render_sync(status: &SyncStatus, state: &Rc<Self>) -> Vec<Dom> {
+        let enabled = status.enabled;
+
+        let mut children = vec![
+            Self::button(if enabled { "Stop syncing labels" } else { "Sync labels with other devices" }, clone!(state => move || {
+                state.port.send_message(&options::ClientMessage::SetSync { enabled: !enabled });
+            })),
+        ];
+
+        if enabled {
+            children.push(Self::button("Sync now", clone!(state => move || {
+                state.port.send_message(&options::ClientMessage::SyncNow);
+            })));
+
+            if let Some(timestamp) = status.timestamp_synced {
+                let date = Date::new(&JsValue::from(timestamp));
+
+                children.push(html!("div", {
+                    .text(&format!("Last synced: {}", String::from(date.to_locale_string("default", &JsValue::UNDEFINED))))
+                }));
+            }
+
+            if let Some(error) = &status.error {
+                children.push(html!("div", {
+                    .style("color", "red")
+                    .text(&format!("Sync failed: {}", error))
+                }));
+            }
+        }
+
+        children
+    }
+
+    fn render(state: Rc<Self>) -> Dom {
+        html!("div", {
+            .children(&mut [
+                html!("div", {
+                    .style("color", "red")
+
+                    .children_signal_vec(state.migration_failures.signal_ref(clone!(state => move |failures| {
+                        Self::render_migration_failures(failures, &state)
+                    })).to_signal_vec())
+                }),
+
+                html!("select" => HtmlSelectElement, {
+                    .children_signal_vec(state.export_filters.signal_ref(clone!(state => move |filters| {
+                        let selected = state.export_filter.lock_ref();
+
+                        filters.into_iter().map(|(name, filter)| {
+                            html!("option", {
+                                .property("selected", *filter == *selected)
+                                .text(name)
+                            })
+                        }).collect()
+                    })).to_signal_vec())
+
+                    .with_node!(element => {
+                        .event(clone!(state => move |_: events::Change| {
+                            let index = element.selected_index();
+
+                            if index >= 0 {
+                                let filters = state.export_filters.lock_ref();
+                                state.export_filter.set(filters[index as usize].1.clone());
+                            }
+                        }))
+                    })
+                }),
+
+                Self::export_button("Export (JSON)", ExportFormat::Json, state.clone()),
+                Self::export_button("Export (bookmarks)", ExportFormat::BookmarksHtml, state.clone()),
+                Self::export_button("Export (Markdown)", ExportFormat::Markdown, state.clone()),
+                Self::export_button("Export (CSV)", ExportFormat::Csv, state.clone()),
+                Self::export_button("Export (URLs)", ExportFormat::Urls, state.clone()),
 
                 html!("textarea" => HtmlTextAreaElement, {
                     .with_node!(element => {
                         .event(clone!(state => move |_: events::Change| {
-                            state.port.send_message(&options::ClientMessage::Import { data: element.value() });
+                            state.preview_import(element.value());
+                        }))
+                    })
+                }),
+
+                html!("input" => HtmlInputElement, {
+                    .attribute("type", "file")
+                    .attribute("accept", ".json,.txt,.jsonlz4,.baklz4")
+
+                    .with_node!(element => {
+                        .event(clone!(state => move |_: events::Change| {
+                            if let Some(file) = element.files().and_then(|files| files.get(0)) {
+                                Self::import_file(state.clone(), file);
+                            }
+                        }))
+                    })
+                }),
+
+                // The user has to check the preview before the data can be imported
+                html!("div", {
+                    .visible_signal(state.import_preview.signal_ref(|preview| preview.is_some()))
+
+                    .children(&mut [
+                        html!("div", {
+                            .children_signal_vec(state.import_preview.signal_ref(|preview| {
+                                match preview {
+                                    Some(preview) => {
+                                        let mut children = vec![
+                                            html!("div", {
+                                                .text(&format!("Detected format: {}", preview.format.name()))
+                                            }),
+                                        ];
+
+                                        children.extend(preview.windows.iter().map(|window| {
+                                            html!("div", {
+                                                .text(&format!("{} ({} tabs)", window.name, window.tabs))
+                                            })
+                                        }));
+
+                                        if !preview.labels.is_empty() {
+                                            children.push(html!("div", {
+                                                .text(&format!("Labels: {}", preview.labels.join(", ")))
+                                            }));
+                                        }
+
+                                        children
+                                    },
+                                    None => vec![],
+                                }
+                            }).to_signal_vec())
+                        }),
+
+                        Self::import_button("Import (replace)", ImportMode::Replace, state.clone()),
+                        Self::import_button("Import (merge)", ImportMode::Merge, state.clone()),
+                        Self::import_button("Import (append)", ImportMode::Append, state.clone()),
+                    ])
+                }),
+
+                html!("div", {
+                    .text_signal(state.imported.signal().map(|imported| {
+                        match imported {
+                            Some((windows, tabs)) => format!("Imported {} tabs from {} windows", tabs, windows),
+                            None => "".to_string(),
+                        }
+                    }))
+                }),
+
+                Self::button("Check database", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::CheckDatabase { repair: false });
+                })),
+
+                Self::button("Repair database", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::CheckDatabase { repair: true });
+                })),
+
+                html!("div", {
+                    .children_signal_vec(state.database_check.signal_ref(|check| {
+                        match check {
+                            Some((problems, repaired)) => {
+                                let mut children = vec![
+                                    html!("div", {
+                                        .text(&if problems.is_empty() {
+                                            "No problems found".to_string()
+
+                                        } else {
+                                            format!("Found {} problems, repaired {} of them", problems.len(), repaired)
+                                        })
+                                    }),
+                                ];
+
+                                children.extend(problems.into_iter().map(|problem| {
+                                    html!("div", {
+                                        .text(&problem.to_string())
+                                    })
+                                }));
+
+                                children
+                            },
+                            None => vec![],
+                        }
+                    }).to_signal_vec())
+                }),
+
+                Self::button("Back up now", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::CreateBackup);
+                })),
+
+                // The newest backups are shown first, the list updates whenever the background page changes it
+                html!("div", {
+                    .children_signal_vec(keyspace::BACKUPS.watch(&state.db).map(clone!(state => move |backups| {
+                        backups.unwrap_or_else(|| vec![]).iter().rev().map(|backup| Self::render_backup(backup, &state)).collect()
+                    })).to_signal_vec())
+                }),
+
+                html!("div", {
+                    .children_signal_vec(state.sync_status.signal_ref(clone!(state => move |status| {
+                        match status {
+                            Some(status) => Self::render_sync(status, &state),
+                            None => vec![],
+                        }
+                    })).to_signal_vec())
+                }),
+
+                // The closed tabs are restored with their labels when they are reopened
+                html!("select" => HtmlSelectElement, {
+                    .children_signal_vec(state.tombstone_duration.signal().map(|selected| {
+                        TOMBSTONE_DURATIONS.iter().map(|(name, duration)| {
+                            html!("option", {
+                                .property("selected", selected == Some(*duration))
+                                .text(name)
+                            })
+                        }).collect()
+                    }).to_signal_vec())
+
+                    .with_node!(element => {
+                        .event(clone!(state => move |_: events::Change| {
+                            let index = element.selected_index();
+
+                            if index >= 0 {
+                                let (_, duration) = TOMBSTONE_DURATIONS[index as usize];
+                                state.port.send_message(&options::ClientMessage::SetTombstoneDuration { duration });
+                            }
                         }))
                     })
                 }),
 
                 html!("div", {
-                    .style("overflow", "auto")
-                    .style("height", "500px")
+                    .children_signal_vec(state.auto_unload.signal_ref(clone!(state => move |settings| {
+                        match settings {
+                            Some(settings) => Self::render_auto_unload(settings, &state),
+                            None => vec![],
+                        }
+                    })).to_signal_vec())
+                }),
+
+                Self::button("Refresh storage usage", clone!(state => move || {
+                    state.port.send_message(&options::ClientMessage::GetStorageUsage);
+                })),
+
+                html!("div", {
+                    .children_signal_vec(state.storage_usage.signal_ref(clone!(state => move |usage| {
+                        match usage {
+                            Some(usage) => Self::render_usage(usage, &state),
+                            None => vec![],
+                        }
+                    })).to_signal_vec())
+                }),
+
+                html!("div", {
+                    .style("color", "red")
 
-                    .children_signal_vec(state.tabs.signal_ref(|tabs| {
-                        tabs.into_iter().map(|tab| {
+                    .children_signal_vec(state.import_errors.signal_ref(|errors| {
+                        errors.into_iter().map(|error| {
                             html!("div", {
-                                .text(&tab.url.as_deref().unwrap_or(""))
+                                .text(&error.to_string())
                             })
                         }).collect()
                     }).to_signal_vec())
-                })
+                }),
             ])
         })
     }
@@ -115,6 +659,8 @@ pub async fn main_js() -> Result<(), JsValue> {
 
     log!("Starting");
 
+    let db = Rc::new(Database::open().await?);
+
     let port = Rc::new(connect::<options::ClientMessage, options::ServerMessage>("options"));
 
     port.send_message(&options::ClientMessage::Initialize);
@@ -123,7 +669,7 @@ pub async fn main_js() -> Result<(), JsValue> {
         .map(|x| -> Result<_, JsValue> { Ok(x) })
         .try_fold(None, move |mut state, message| {
             // TODO remove this boxed
-            clone!(port => async move {
+            clone!(port, db => async move {
                 info!("Received message {:#?}", message);
 
                 match message {
@@ -131,18 +677,66 @@ pub async fn main_js() -> Result<(), JsValue> {
                         log!("Options page started");
 
                         state = Some({
-                            let state = State::new(port);
+                            let state = State::new(port, db);
                             dominator::append_dom(&dominator::body(), State::render(state.clone()));
                             state
                         });
                     },
 
+                    options::ServerMessage::Summary { windows, labels } => {
+                        state.as_ref().unwrap().set_summary(windows, labels);
+                    },
+
+                    options::ServerMessage::DatabaseChecked { problems, repaired } => {
+                        state.as_ref().unwrap().database_check.set(Some((problems, repaired)));
+                    },
+
+                    options::ServerMessage::StorageUsage { usage } => {
+                        state.as_ref().unwrap().storage_usage.set(Some(usage));
+                    },
+
+                    options::ServerMessage::CompactFailed { message } => {
+                        state.as_ref().unwrap().compact_error.set(Some(message));
+                    },
+
+                    options::ServerMessage::TombstoneDuration { duration } => {
+                        state.as_ref().unwrap().tombstone_duration.set(Some(duration));
+                    },
+
+                    options::ServerMessage::AutoUnload { settings } => {
+                        state.as_ref().unwrap().auto_unload.set(Some(settings));
+                    },
+
+                    options::ServerMessage::MigrationFailures { failures } => {
+                        state.as_ref().unwrap().migration_failures.set(failures);
+                    },
+
+                    options::ServerMessage::SyncStatus { status } => {
+                        state.as_ref().unwrap().sync_status.set(Some(status));
+                    },
+
                     options::ServerMessage::ExportFinished => {
                         state.as_ref().unwrap().loading.set_neq(false);
                     },
 
-                    options::ServerMessage::Imported { tabs } => {
-                        state.as_ref().unwrap().tabs.set(tabs);
+                    options::ServerMessage::ImportPreview { format, windows, labels } => {
+                        let state = state.as_ref().unwrap();
+                        state.import_errors.set(vec![]);
+                        state.import_preview.set(Some(Preview { format, windows, labels }));
+                    },
+
+                    options::ServerMessage::Imported { windows, tabs } => {
+                        let state = state.as_ref().unwrap();
+                        state.import_preview.set(None);
+                        state.import_errors.set(vec![]);
+                        state.imported.set(Some((windows, tabs)));
+                    },
+
+                    options::ServerMessage::ImportFailed { errors } => {
+                        let state = state.as_ref().unwrap();
+                        state.import_preview.set(None);
+                        state.imported.set(None);
+                        state.import_errors.set(errors);
                     },
                 }
 
@@ -0,0 +1,25 @@
+use tab_organizer::compress::{decompress_block, DecompressError};
+
+
+// Firefox stores its sessions as a single LZ4 block, with a header containing the magic bytes and the decompressed size
+const MAGIC: &[u8] = b"mozLz40\0";
+
+const HEADER_LEN: usize = 12;
+
+
+pub(crate) fn is_mozlz4(input: &[u8]) -> bool {
+    input.starts_with(MAGIC)
+}
+
+
+/// Decodes a `.jsonlz4` or `.baklz4` file, the input must start with the mozLz4 magic bytes
+pub(crate) fn decompress(input: &[u8]) -> Result<Vec<u8>, DecompressError> {
+    let header = input.get(..HEADER_LEN).ok_or(DecompressError::Truncated)?;
+
+    let size = (header[8] as usize) |
+               ((header[9] as usize) << 8) |
+               ((header[10] as usize) << 16) |
+               ((header[11] as usize) << 24);
+
+    decompress_block(&input[HEADER_LEN..], size)
+}
@@ -8,6 +8,8 @@ pub(crate) const DEFAULT_FAVICON: &'static str = "favicons/default.svg";
 
 pub(crate) const LOADING_MESSAGE_THRESHOLD: u32 = 500;
 
+pub(crate) const TOAST_DURATION: u32 = 8000; // Milliseconds before the undo toast is hidden
+
 pub(crate) const MOUSE_SCROLL_THRESHOLD: f64 = 30.0; // Number of pixels before it starts scrolling
 pub(crate) const MOUSE_SCROLL_SPEED: f64 = 0.5; // Number of pixels to move per millisecond
 
@@ -40,6 +42,8 @@ pub(crate) const TAB_FAVICON_SIZE: f64 = 16.0;
 pub(crate) const TAB_FAVICON_LEFT_MARGIN: f64 = 3.0;
 pub(crate) const TAB_FAVICON_RIGHT_MARGIN: f64 = 2.0;
 
+pub(crate) const TREE_INDENT: f64 = 12.0; // Pixels that each level of the tree is indented
+
 pub(crate) const TAB_CLOSE_BORDER_WIDTH: f64 = 1.0;
 
 
@@ -280,6 +284,12 @@ lazy_static! {
         .style("opacity", "0.75")
     };
 
+    // Discarded tabs are still in the browser, so they are less faded than unloaded tabs
+    pub(crate) static ref TAB_DISCARDED_STYLE: String = class! {
+        .style("opacity", "0.85")
+        .style("font-style", "italic")
+    };
+
     /*pub(crate) static ref TAB_UNLOADED_HOVER_STYLE: String = class! {
         //.style("background-color", "hsla(0, 0%, 0%, 0.4)")
 
@@ -355,6 +365,30 @@ lazy_static! {
         .style("bottom", "-1px")
     };
 
+    pub(crate) static ref TAB_TREE_STYLE: String = class! {
+        .style("display", "flex")
+        .style("justify-content", "flex-end")
+        .style("align-items", "center")
+        .style("flex-shrink", "0")
+    };
+
+    pub(crate) static ref TAB_TREE_TWISTY_STYLE: String = class! {
+        .style("width", "8px")
+        .style("height", "8px")
+        .style("margin-right", "2px")
+        .style("opacity", "0.6")
+    };
+
+    // A colored bar which shows which container the tab is in
+    pub(crate) static ref TAB_CONTAINER_STYLE: String = class! {
+        .style("width", "3px")
+        .style("height", "14px")
+        .style("margin-left", "3px")
+        .style("margin-right", "1px")
+        .style("border-radius", "1px")
+        .style("flex-shrink", "0")
+    };
+
     pub(crate) static ref TAB_AUDIO_STYLE: String = class! {
         .style("width", "16px")
         .style("height", "16px")
@@ -507,4 +541,35 @@ lazy_static! {
     pub(crate) static ref TAB_MENU_STYLE: String = class! {
         .style("position", "fixed")
     };
+
+    pub(crate) static ref TOAST_STYLE: String = class! {
+        .style("position", "fixed")
+        .style("z-index", HIGHEST_ZINDEX)
+
+        .style("left", "5px")
+        .style("right", "5px")
+        .style("bottom", "5px")
+
+        .style("align-items", "center")
+        .style("padding", "4px 4px 4px 8px")
+        .style("border-radius", "5px")
+
+        .style("color", "white")
+        .style("background-color", "hsl(0, 0%, 25%)")
+        .style("box-shadow", "0px 0px 3px dimgray")
+    };
+
+    pub(crate) static ref TOAST_BUTTON_STYLE: String = class! {
+        .style("cursor", "pointer")
+        .style("margin-left", "8px")
+        .style("padding", "2px 8px")
+        .style("border-radius", "3px")
+
+        .style("font-weight", "bold")
+        .style("color", "hsl(211, 95%, 75%)")
+    };
+
+    pub(crate) static ref TOAST_BUTTON_HOVER_STYLE: String = class! {
+        .style("background-color", "hsl(0, 0%, 35%)")
+    };
 }
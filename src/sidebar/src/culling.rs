@@ -149,6 +149,7 @@ struct CulledTab {
     manually_closed: MutableSink<MutableSignal<bool>>,
     insert_animation: MutableSink<MutableAnimationSignal>,
     matches_search: MutableSink<MutableSignal<bool>>,
+    hidden_in_tree: MutableSink<MutableSignal<bool>>,
 }
 
 impl CulledTab {
@@ -159,6 +160,7 @@ impl CulledTab {
             manually_closed: MutableSink::new(state.manually_closed.signal()),
             insert_animation: MutableSink::new(state.insert_animation.signal()),
             matches_search: MutableSink::new(state.matches_search.signal()),
+            hidden_in_tree: MutableSink::new(state.hidden_in_tree.signal()),
             state,
         }
     }
@@ -176,12 +178,14 @@ impl CulledTab {
         let manually_closed = self.manually_closed.is_changed(cx);
         let insert_animation = self.insert_animation.is_changed(cx);
         let matches_search = self.matches_search.is_changed(cx);
+        let hidden_in_tree = self.hidden_in_tree.is_changed(cx);
 
         drag_over ||
         dragging ||
         manually_closed ||
         insert_animation ||
-        matches_search
+        matches_search ||
+        hidden_in_tree
     }
 
     // TODO this must be kept in sync with render.rs
@@ -210,7 +214,7 @@ impl CulledTab {
 
     // TODO this must be kept in sync with render.rs
     fn height(&self) -> Option<(f64, f64)> {
-        if self.matches_search.unwrap() && !self.dragging.unwrap() && !self.manually_closed.unwrap() {
+        if self.matches_search.unwrap() && !self.hidden_in_tree.unwrap() && !self.dragging.unwrap() && !self.manually_closed.unwrap() {
             let percentage = ease(self.insert_animation.unwrap());
 
             let border = percentage.range_inclusive(0.0, TAB_BORDER_WIDTH).round();
@@ -523,7 +527,7 @@ pub(crate) fn cull_groups(state: Arc<State>) -> impl Future<Output = ()> {
             .delay_remove(|group| group.wait_until_removed())
             .map(culled_group)),
         search_parser: MutableSink::new(state.search_parser.signal_cloned()),
-        sort_tabs: MutableSink::new(state.options.signal_ref(|x| x.sort_tabs)),
+        sort_tabs: MutableSink::new(Box::pin(state.sort_tabs_signal())),
         scroll_y: MutableSink::new(state.scrolling.y.signal()),
         window_size: MutableSink::new(state.window_size.signal()),
         state,
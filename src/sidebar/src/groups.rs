@@ -1,11 +1,14 @@
 use crate::types::{State, TabState, Group, Tab};
 use crate::url_bar::UrlBar;
+use crate::public_suffix::registrable_domain;
 use tab_organizer::{str_default, round_to_day, time, TimeDifference, StackVec};
 use tab_organizer::state as shared;
 use tab_organizer::state::{SortTabs, Label};
-use tab_organizer::state::sidebar::TabChange;
+use tab_organizer::state::sidebar::{TabChange, ClientMessage};
 use js_sys::Date;
+use uuid::Uuid;
 use std::ops::Deref;
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use std::cmp::Ordering;
 use futures::Future;
@@ -140,13 +143,14 @@ fn insert_group<A, F>(groups: &mut A, index: Result<usize, usize>, create: F) ->
 }
 
 
-fn sorted_groups<A>(sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, tab: &TabState, should_animate: bool) -> StackVec<Arc<Group>> where A: Insertable<Arc<Group>> {
+fn sorted_groups<A>(state: &State, sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, tab: &TabState, should_animate: bool) -> StackVec<Arc<Group>> where A: Insertable<Arc<Group>> {
     if tab.pinned.get() {
         StackVec::Single(pinned.clone())
 
     } else {
         match sort {
-            SortTabs::Index => StackVec::Single({
+            // The tree uses the same order as the browser, the children are indented by update_tree
+            SortTabs::Index | SortTabs::Tree => StackVec::Single({
                 let index = get_unpinned_index(groups);
                 insert_group(groups, index, || {
                     make_new_group(false, None, 0.0, should_animate)
@@ -219,6 +223,26 @@ fn sorted_groups<A>(sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, tab: &T
                 })
             }),
 
+            SortTabs::Domain => StackVec::Single({
+                let url = tab.url.lock_ref();
+                let url = str_default(&url, "");
+
+                let host = UrlBar::new(url)
+                    .and_then(|url| url.domain)
+                    .map(|domain| domain.to_ascii_lowercase())
+                    .unwrap_or_else(|| "".to_string());
+
+                // IP addresses and hosts like localhost don't have a registrable domain, so they are grouped by the whole host.
+                // The tabs which don't have a host (e.g. about: pages) are put into the last group, which doesn't have a header.
+                let name = registrable_domain(&host).unwrap_or(&host);
+
+                let index = get_group_index_name_empty(groups, name);
+                insert_group(groups, index, || {
+                    let name = if name == "" { None } else { Some(Arc::new(name.to_string())) };
+                    make_new_group(false, name, 0.0, should_animate)
+                })
+            }),
+
             SortTabs::Name => StackVec::Single({
                 let title = tab.title.lock_ref();
                 let title = str_default(&title, "");
@@ -239,6 +263,22 @@ fn sorted_groups<A>(sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, tab: &T
                     make_new_group(false, Some(Arc::new(title)), 0.0, should_animate)
                 })
             }),
+
+            SortTabs::Container => StackVec::Single({
+                let containers = state.containers.lock_ref();
+
+                // The tabs which aren't in a container are put into the last group, which doesn't have a header
+                let name = tab.cookie_store_id.as_ref()
+                    .and_then(|id| containers.get(id.as_str()))
+                    .map(|container| container.name.as_str())
+                    .unwrap_or("");
+
+                let index = get_group_index_name_empty(groups, name);
+                insert_group(groups, index, || {
+                    let name = if name == "" { None } else { Some(Arc::new(name.to_string())) };
+                    make_new_group(false, name, 0.0, should_animate)
+                })
+            }),
         }
     }
 }
@@ -257,7 +297,7 @@ fn sorted_tab_index(sort: SortTabs, tabs: &[Arc<Tab>], tab: &TabState, tab_index
 
     } else {
         match sort {
-            SortTabs::Index | SortTabs::Label => {
+            SortTabs::Index | SortTabs::Label | SortTabs::Container | SortTabs::Tree => {
                 if is_initial {
                     tabs.len()
 
@@ -288,7 +328,8 @@ fn sorted_tab_index(sort: SortTabs, tabs: &[Arc<Tab>], tab: &TabState, tab_index
                 })
             },
 
-            SortTabs::Url => {
+            // The subdomains of a domain are next to each other, because the URLs are sorted
+            SortTabs::Url | SortTabs::Domain => {
                 let url = tab.url.lock_ref();
                 let url = str_default(&url, "");
 
@@ -321,6 +362,48 @@ fn sorted_tab_index(sort: SortTabs, tabs: &[Arc<Tab>], tab: &TabState, tab_index
 }
 
 
+// The tabs are in the same order as the browser, so a tab is only shown as a child of its opener if every tab between them is a descendant of the opener
+fn update_tree(is_tree: bool, tabs: &[Arc<TabState>]) {
+    let mut parents = HashSet::new();
+
+    // Each ancestor of the current tab, and whether its descendants are hidden
+    let mut ancestors: Vec<(Uuid, bool)> = vec![];
+
+    for tab in tabs {
+        if !is_tree || tab.pinned.get() {
+            tab.depth.set_neq(0);
+            tab.hidden_in_tree.set_neq(false);
+            continue;
+        }
+
+        while let Some((uuid, _)) = ancestors.last() {
+            if tab.opener == Some(*uuid) {
+                break;
+            }
+
+            ancestors.pop();
+        }
+
+        let hidden = match ancestors.last() {
+            Some((uuid, hidden)) => {
+                parents.insert(*uuid);
+                *hidden
+            },
+            None => false,
+        };
+
+        tab.depth.set_neq(ancestors.len());
+        tab.hidden_in_tree.set_neq(hidden);
+
+        ancestors.push((tab.id, hidden || tab.collapsed.get()));
+    }
+
+    for tab in tabs {
+        tab.has_children.set_neq(parents.contains(&tab.id));
+    }
+}
+
+
 fn initialize(state: &State, sort: SortTabs, pinned: &Arc<Group>, tabs: &[Arc<TabState>], changing_sort: bool, should_animate: bool) -> Vec<Arc<Group>> {
     let mut groups = vec![];
 
@@ -365,7 +448,7 @@ fn insert_tab_into_group(state: &State, sort: SortTabs, group: &Group, tab: Arc<
 }
 
 fn tab_inserted<A>(state: &State, sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, tab: Arc<TabState>, tab_index: usize, should_animate: bool, is_initial: bool) where A: Insertable<Arc<Group>> {
-    sorted_groups(sort, pinned, groups, &tab, should_animate).each(|group| {
+    sorted_groups(state, sort, pinned, groups, &tab, should_animate).each(|group| {
         // TODO if the tab doesn't match the search, and the group is already matching, then do nothing
         insert_tab_into_group(state, sort, &group, tab.clone(), tab_index, should_animate, is_initial);
     });
@@ -423,15 +506,15 @@ fn remove_tab_from_group<A>(groups: &mut A, group: &Group, tab: &TabState, shoul
     }
 }
 
-fn tab_removed(sort: SortTabs, pinned: &Arc<Group>, groups: &mut MutableVecLockMut<Arc<Group>>, tab: &TabState, _tab_index: usize) {
+fn tab_removed(state: &State, sort: SortTabs, pinned: &Arc<Group>, groups: &mut MutableVecLockMut<Arc<Group>>, tab: &TabState, _tab_index: usize) {
     // TODO make this more efficient
-    sorted_groups(sort, pinned, groups, tab, true).each(|group| {
+    sorted_groups(state, sort, pinned, groups, tab, true).each(|group| {
         remove_tab_from_group(groups, &group, tab, true);
     });
 }
 
 fn tab_updated<A>(state: &State, sort: SortTabs, pinned: &Arc<Group>, groups: &mut A, old_groups: StackVec<Arc<Group>>, tab: Arc<TabState>, tab_index: usize) where A: Insertable<Arc<Group>> {
-    let new_groups = sorted_groups(sort, pinned, groups, &tab, true);
+    let new_groups = sorted_groups(state, sort, pinned, groups, &tab, true);
 
     // TODO make this more efficient
     old_groups.each(|group| {
@@ -512,6 +595,13 @@ impl Groups {
 
         let new_groups = time!("Creating initial groups", { initialize(state, sort, &self.pinned, &tabs, false, false) });
         groups.replace_cloned(new_groups);
+
+        update_tree(sort == SortTabs::Tree, &tabs);
+    }
+
+    fn update_tree(&self, tabs: &[Arc<TabState>]) {
+        let sort = *self.sort.lock().unwrap();
+        update_tree(sort == SortTabs::Tree, tabs);
     }
 
     fn update_group_titles(&self) {
@@ -563,6 +653,8 @@ impl Groups {
         let new_groups = time!("Creating new groups", { initialize(state, *sort, &self.pinned, tabs, true, false) });
 
         groups.replace_cloned(new_groups);
+
+        update_tree(*sort == SortTabs::Tree, tabs);
     }
 
     fn tab_inserted(&self, state: &State, tab_index: usize, tab: Arc<TabState>) {
@@ -571,10 +663,10 @@ impl Groups {
         tab_inserted(state, sort, &self.pinned, &mut groups, tab, tab_index, true, false);
     }
 
-    fn tab_removed(&self, tab_index: usize, tab: &TabState) {
+    fn tab_removed(&self, state: &State, tab_index: usize, tab: &TabState) {
         let sort = *self.sort.lock().unwrap();
         let mut groups = self.groups.lock_mut();
-        tab_removed(sort, &self.pinned, &mut groups, tab, tab_index);
+        tab_removed(state, sort, &self.pinned, &mut groups, tab, tab_index);
     }
 
     fn tab_updated<F>(&self, state: &State, tab_index: usize, tab: Arc<TabState>, change: F) where F: FnOnce() {
@@ -582,7 +674,7 @@ impl Groups {
         let mut groups = self.groups.lock_mut();
 
         // TODO should this be animated ?
-        let group_indexes = sorted_groups(sort, &self.pinned, &mut groups, &tab, true);
+        let group_indexes = sorted_groups(state, sort, &self.pinned, &mut groups, &tab, true);
 
         change();
 
@@ -670,6 +762,8 @@ impl State {
         tabs.insert(tab_index, tab.clone());
 
         self.groups.tab_inserted(self, tab_index, tab);
+
+        self.groups.update_tree(&tabs);
     }
 
     pub(crate) fn remove_tab(&self, tab_index: usize) {
@@ -683,9 +777,11 @@ impl State {
 
         tab.removed.set_neq(true);
 
-        self.groups.tab_removed(tab_index, &tab);
+        self.groups.tab_removed(self, tab_index, &tab);
 
         decrement_indexes(&tabs[tab_index..]);
+
+        self.groups.update_tree(&tabs);
     }
 
     // TODO test this
@@ -709,6 +805,8 @@ impl State {
 
             tab.index.set(new_tab_index);
         });
+
+        self.groups.update_tree(&tabs);
     }
 
     pub(crate) fn change_tab(&self, tab_index: usize, changes: Vec<TabChange>) {
@@ -716,6 +814,9 @@ impl State {
 
         let tab = &tabs[tab_index];
 
+        let mut pinned_changed = false;
+        let mut collapsed_changed = false;
+
         // TODO this can be optimized based on the specific grouping (e.g. Window)
         self.groups.tab_updated(self, tab_index, tab.clone(), || {
             for change in changes {
@@ -742,8 +843,13 @@ impl State {
                         }
                     },
                     TabChange::Pinned { pinned } => {
+                        pinned_changed = tab.pinned.get() != pinned;
                         tab.pinned.set_neq(pinned);
                     },
+                    TabChange::Collapsed { collapsed } => {
+                        collapsed_changed = tab.collapsed.get() != collapsed;
+                        tab.collapsed.set_neq(collapsed);
+                    },
                     TabChange::AddedToLabel { label } => {
                         self.add_label_count(&label.name);
                         let mut labels = tab.labels.lock_mut();
@@ -784,6 +890,23 @@ impl State {
                 }
             }
         });
+
+        // The pinned tabs aren't in the tree
+        if pinned_changed || collapsed_changed {
+            self.groups.update_tree(&tabs);
+        }
+    }
+
+    pub(crate) fn toggle_collapsed(&self, tab: &TabState) {
+        let collapsed = !tab.collapsed.get();
+
+        tab.collapsed.set(collapsed);
+
+        // The background page saves it, so the tab stays collapsed after reloading
+        self.port.send_message(&ClientMessage::CollapseTab { uuid: tab.id, collapsed });
+
+        let tabs = self.tabs.read().unwrap();
+        self.groups.update_tree(&tabs);
     }
 
     pub(crate) fn change_sort(&self, sort_tabs: SortTabs) {
@@ -20,6 +20,7 @@ mod constants;
 mod types;
 mod search;
 mod url_bar;
+mod public_suffix;
 mod menu;
 mod groups;
 mod scrolling;
@@ -413,12 +414,12 @@ pub async fn main_js() -> Result<(), JsValue> {
                 info!("Received message {:#?}", message);
 
                 match message {
-                    sidebar::ServerMessage::Initial { tabs, options } => {
+                    sidebar::ServerMessage::Initial { tabs, options, containers } => {
                         assert!(state.is_none());
 
                         state = time!("Initializing", {
                             let options = Options::new(port.clone(), options);
-                            let state = Arc::new(State::new(port, options, tabs));
+                            let state = Arc::new(State::new(port, options, tabs, containers));
                             initialize(state.clone());
                             Some(state)
                         });
@@ -439,6 +440,24 @@ pub async fn main_js() -> Result<(), JsValue> {
                     sidebar::ServerMessage::TabMoved { old_tab_index, new_tab_index } => {
                         state.as_ref().unwrap().move_tab(old_tab_index, new_tab_index);
                     },
+
+                    sidebar::ServerMessage::RecentlyClosed { sessions } => {
+                        state.as_ref().unwrap().recently_closed.set(sessions);
+                    },
+
+                    sidebar::ServerMessage::Containers { containers } => {
+                        state.as_ref().unwrap().set_containers(containers);
+                    },
+
+                    sidebar::ServerMessage::History { undo, redo } => {
+                        let state = state.as_ref().unwrap();
+                        state.undo.set(undo.map(Arc::new));
+                        state.redo.set(redo.map(Arc::new));
+                    },
+
+                    sidebar::ServerMessage::Performed { description } => {
+                        State::show_toast(state.as_ref().unwrap(), description);
+                    },
                 }
 
                 Ok(state)
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use lazy_static::lazy_static;
+
+
+// https://publicsuffix.org/list/
+// The list is in Unicode, but the browser gives Punycode for the URL, so internationalized suffixes fall back to the "*" rule
+struct Rules {
+    normal: HashSet<&'static str>,
+    /// "*.ck" is stored as "ck"
+    wildcard: HashSet<&'static str>,
+    /// "!www.ck" is stored as "www.ck"
+    exception: HashSet<&'static str>,
+}
+
+impl Rules {
+    fn parse(list: &'static str) -> Self {
+        let mut rules = Self {
+            normal: HashSet::new(),
+            wildcard: HashSet::new(),
+            exception: HashSet::new(),
+        };
+
+        for line in list.lines() {
+            // Everything after the first whitespace is ignored
+            if let Some(rule) = line.split_whitespace().next() {
+                if rule.starts_with("//") {
+                    continue;
+
+                } else if rule.starts_with("*.") {
+                    rules.wildcard.insert(&rule[2..]);
+
+                } else if rule.starts_with("!") {
+                    rules.exception.insert(&rule[1..]);
+
+                } else {
+                    rules.normal.insert(rule);
+                }
+            }
+        }
+
+        rules
+    }
+
+    /// Returns the byte index where the public suffix starts
+    fn suffix_start(&self, starts: &[usize], host: &str) -> usize {
+        // The suffixes are checked from longest to shortest, so the first rule which matches is the prevailing rule
+        for (index, &start) in starts.iter().enumerate() {
+            let suffix = &host[start..];
+            let parent = starts.get(index + 1).cloned();
+
+            if let Some(parent) = parent {
+                // The exception rules remove the left-most label of the suffix
+                if self.exception.contains(suffix) {
+                    return parent;
+
+                } else if self.wildcard.contains(&host[parent..]) {
+                    return start;
+                }
+            }
+
+            if self.normal.contains(suffix) {
+                return start;
+            }
+        }
+
+        // If no rules match then the prevailing rule is "*"
+        *starts.last().unwrap()
+    }
+}
+
+
+lazy_static! {
+    static ref RULES: Rules = Rules::parse(include_str!("public_suffix_list.dat"));
+}
+
+
+fn is_ip_address(host: &str) -> bool {
+    // IPv6 addresses are always wrapped in [], and the last label of an IPv4 address is always a number
+    host.starts_with("[") || host.rsplit('.').next().map(|label| label.bytes().all(|x| x.is_ascii_digit())).unwrap_or(false)
+}
+
+
+/// Returns the public suffix plus one more label (e.g. "docs.google.co.uk" returns "google.co.uk").
+///
+/// The host must be lower case. It returns `None` for IP addresses, and for hosts which are a public suffix.
+pub(crate) fn registrable_domain(host: &str) -> Option<&str> {
+    let host = host.trim_end_matches('.');
+
+    if host == "" || is_ip_address(host) {
+        return None;
+    }
+
+    // The byte index where each label starts
+    let starts: Vec<usize> = std::iter::once(0)
+        .chain(host.match_indices('.').map(|(index, _)| index + 1))
+        .collect();
+
+    let suffix_start = RULES.suffix_start(&starts, host);
+
+    starts.iter().rev()
+        .find(|&&start| start < suffix_start)
+        .map(|&start| &host[start..])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::registrable_domain;
+
+    // Based on https://raw.githubusercontent.com/publicsuffix/list/master/tests/test_psl.txt
+    fn check(host: &str, expected: Option<&str>) {
+        assert_eq!(registrable_domain(host), expected, "{}", host);
+    }
+
+    #[test]
+    fn unlisted() {
+        check("example", None);
+        check("example.example", Some("example.example"));
+        check("b.example.example", Some("example.example"));
+        check("a.b.example.example", Some("example.example"));
+    }
+
+    #[test]
+    fn single_label() {
+        check("com", None);
+        check("localhost", None);
+    }
+
+    #[test]
+    fn normal() {
+        check("example.com", Some("example.com"));
+        check("b.example.com", Some("example.com"));
+        check("a.b.example.com", Some("example.com"));
+        check("uk", None);
+        check("co.uk", None);
+        check("b.co.uk", Some("b.co.uk"));
+        check("a.b.co.uk", Some("b.co.uk"));
+        check("k12.ak.us", None);
+        check("test.k12.ak.us", Some("test.k12.ak.us"));
+        check("www.test.k12.ak.us", Some("test.k12.ak.us"));
+    }
+
+    #[test]
+    fn wildcard() {
+        check("ck", None);
+        check("test.ck", None);
+        check("b.test.ck", Some("b.test.ck"));
+        check("a.b.test.ck", Some("b.test.ck"));
+        check("foo.bar.ck", Some("foo.bar.ck"));
+        check("c.kawasaki.jp", None);
+        check("b.c.kawasaki.jp", Some("b.c.kawasaki.jp"));
+        check("a.b.c.kawasaki.jp", Some("b.c.kawasaki.jp"));
+    }
+
+    #[test]
+    fn exception() {
+        check("www.ck", Some("www.ck"));
+        check("www.www.ck", Some("www.ck"));
+        check("city.kawasaki.jp", Some("city.kawasaki.jp"));
+        check("www.city.kawasaki.jp", Some("city.kawasaki.jp"));
+    }
+
+    #[test]
+    fn ip_address() {
+        check("127.0.0.1", None);
+        check("192.168.1.254", None);
+        check("[::1]", None);
+        check("[2001:db8::ff00:42:8329]", None);
+    }
+
+    #[test]
+    fn trailing_dot() {
+        check("example.com.", Some("example.com"));
+        check("a.b.co.uk.", Some("b.co.uk"));
+        check("www.ck.", Some("www.ck"));
+        check("com.", None);
+        check(".", None);
+    }
+}
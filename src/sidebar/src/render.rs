@@ -3,6 +3,7 @@ use dominator::{Dom, html, clone, events, with_node, apply_methods, RefFn, DomBu
 use dominator::animation::{MutableAnimation, Percentage};
 use dominator::traits::*;
 use web_sys::{HtmlElement, HtmlInputElement};
+use js_sys::Date;
 use futures_signals::map_ref;
 use futures_signals::signal::{Signal, SignalExt, Mutable, and, or, not, always};
 use futures_signals::signal_vec::SignalVecExt;
@@ -14,8 +15,9 @@ use crate::constants::*;
 use crate::{cursor, culling, search, url_bar, FAILED, IS_LOADED};
 use crate::types::{State, DragState, Group, Tab, TabMenuState, WindowSize, MenuMode};
 use crate::menu;
-use tab_organizer::{none_if, px, px_range, option_str_default, float_range, is_empty, option_str_default_fn, local_storage_set, none_if_px, ease};
-use tab_organizer::state::SortTabs;
+use tab_organizer::{none_if, px, px_range, option_str_default, float_range, is_empty, option_str_default_fn, local_storage_set, none_if_px, ease, TimeDifference};
+use tab_organizer::state::{SortTabs, sidebar};
+use tab_organizer::state::options::ExportFormat;
 
 
 fn make_url_bar_child<A, D, F>(state: &State, name: &str, mut display: D, f: F) -> Dom
@@ -41,6 +43,53 @@ fn make_url_bar_child<A, D, F>(state: &State, name: &str, mut display: D, f: F)
     })
 }
 
+fn make_menu_export<F>(parent: &menu::Parent, on_click: F) -> Vec<menu::Child> where F: Fn(ExportFormat) + Clone + 'static {
+    [
+        ("JSON", ExportFormat::Json),
+        ("Bookmarks", ExportFormat::BookmarksHtml),
+        ("Markdown", ExportFormat::Markdown),
+        ("CSV", ExportFormat::Csv),
+        ("URLs", ExportFormat::Urls),
+    ].iter().map(|(name, format)| {
+        let format = *format;
+        let on_click = on_click.clone();
+
+        parent.action(name, None, always(true), move || {
+            on_click(format);
+        })
+    }).collect()
+}
+
+fn make_menu_closed<F>(parent: &menu::Parent, sessions: &[sidebar::ClosedSession], on_click: F) -> Vec<menu::Child> where F: Fn(sidebar::ClosedSession) + Clone + 'static {
+    // The time is only updated when the list changes, but that's okay because it's only accurate to the hour
+    let current_time = Date::now();
+
+    sessions.into_iter().map(|session| {
+        let on_click = on_click.clone();
+
+        let (name, icon_url) = match session {
+            sidebar::ClosedSession::Tab { timestamp_closed, title, url, favicon_url, .. } => {
+                let title = title.as_ref().or(url.as_ref()).map(|x| x.as_str()).unwrap_or("Untitled tab");
+                let time = TimeDifference::new(*timestamp_closed, current_time).pretty();
+
+                (format!("{} ({})", title, time), favicon_url.as_ref().map(|x| x.as_str()))
+            },
+            sidebar::ClosedSession::Window { timestamp_closed, title, tabs, .. } => {
+                let title = title.as_ref().map(|x| x.as_str()).unwrap_or("Window");
+                let time = TimeDifference::new(*timestamp_closed, current_time).pretty();
+
+                (format!("{} ({} tabs, {})", title, tabs, time), Some("/icons/iconic/browser.svg"))
+            },
+        };
+
+        let session = session.clone();
+
+        parent.action(&name, icon_url, always(true), move || {
+            on_click(session.clone());
+        })
+    }).collect()
+}
+
 fn tab_favicon<A>(tab: &Tab, mixin: A) -> Dom where A: FnOnce(DomBuilder<HtmlElement>) -> DomBuilder<HtmlElement> {
     let favicon_url = tab.favicon_url.clone();
 
@@ -161,6 +210,74 @@ fn tab_text<A>(tab: &Tab, mixin: A) -> Dom where A: FnOnce(DomBuilder<HtmlElemen
     })
 }
 
+// The space which the tab is indented by, with the button which collapses its children
+fn tab_tree(state: &Arc<State>, tab: &Arc<Tab>) -> Dom {
+    html!("div", {
+        .class(&*TAB_TREE_STYLE)
+
+        .visible_signal(state.options.signal_ref(|x| x.sort_tabs == SortTabs::Tree))
+
+        .style_signal("width", tab.depth.signal().map(|depth| px((depth + 1) as f64 * TREE_INDENT)))
+
+        .children(&mut [
+            html!("img", {
+                .class(&*TAB_TREE_TWISTY_STYLE)
+
+                .visible_signal(tab.has_children.signal())
+
+                .attribute_signal("src", tab.collapsed.signal().map(|collapsed| {
+                    if collapsed {
+                        intern("/icons/iconic/chevron-right.svg")
+
+                    } else {
+                        intern("/icons/iconic/chevron-bottom.svg")
+                    }
+                }))
+
+                .attribute_signal("title", tab.collapsed.signal().map(|collapsed| {
+                    if collapsed {
+                        "Expand"
+
+                    } else {
+                        "Collapse"
+                    }
+                }))
+
+                // This stops the tab from being focused
+                .event(|e: events::MouseDown| {
+                    e.stop_propagation();
+                })
+
+                .event(clone!(state, tab => move |_: events::Click| {
+                    state.toggle_collapsed(&tab);
+                }))
+            }),
+        ])
+    })
+}
+
+fn tab_container(state: &State, tab: &Tab) -> Dom {
+    let container = |f: fn(&sidebar::Container) -> String| {
+        let cookie_store_id = tab.cookie_store_id.clone();
+
+        state.containers.signal_ref(move |containers| {
+            cookie_store_id.as_ref()
+                .and_then(|id| containers.get(id.as_str()))
+                .map(f)
+        })
+    };
+
+    html!("div", {
+        .class(&*TAB_CONTAINER_STYLE)
+
+        .visible_signal(container(|_| String::new()).map(|container| container.is_some()))
+
+        .style_signal("background-color", container(|container| container.color_code.clone()))
+
+        .attribute_signal("title", container(|container| container.name.clone()))
+    })
+}
+
 fn tab_close<A>(mixin: A) -> Dom where A: FnOnce(DomBuilder<HtmlElement>) -> DomBuilder<HtmlElement> {
     html!("div", {
         .class(&*TAB_CLOSE_STYLE)
@@ -194,6 +311,7 @@ fn tab_base_template<A>(state: &State, tab: &Tab, mixin: A) -> Dom
         .cursor!(state.is_dragging(), intern("pointer"))
 
         .class_signal(&*TAB_UNLOADED_STYLE, and(tab.is_unloaded(), not(state.is_tab_hovered(&tab))))
+        .class_signal(&*TAB_DISCARDED_STYLE, and(tab.is_discarded(), not(state.is_tab_hovered(&tab))))
         .class_signal(&*TAB_FOCUSED_STYLE, tab.is_focused())
 
         .apply(mixin)
@@ -531,6 +649,8 @@ impl State {
                                 }))
 
                                 .children(&mut [
+                                    tab_tree(&state, &tab),
+
                                     tab_favicon(&tab, |dom| { dom
                                         .style_signal("height", none_if(tab.insert_animation.signal(), 1.0, px_range, 0.0, TAB_FAVICON_SIZE))
                                     }),
@@ -541,6 +661,8 @@ impl State {
 
                                     tab_text(&tab, |dom| { dom }),
 
+                                    tab_container(&state, &tab),
+
                                     tab_close(|dom| { dom
                                         .class_signal(&*TAB_CLOSE_HOVER_STYLE, tab.close_hovered.signal())
                                         .class_signal(&*TAB_CLOSE_HOLD_STYLE, and(tab.close_hovered.signal(), tab.close_holding.signal()))
@@ -582,6 +704,16 @@ impl State {
 
     fn render_global_menu(state: &Arc<Self>) -> Dom {
         state.menus.global.render(|parent| vec![
+            parent.action("Undo", Some("/icons/iconic/action-undo.svg"), state.undo.signal_ref(|undo| undo.is_some()), clone!(state => move || {
+                state.undo();
+            })),
+
+            parent.action("Redo", Some("/icons/iconic/action-redo.svg"), state.redo.signal_ref(|redo| redo.is_some()), clone!(state => move || {
+                state.redo();
+            })),
+
+            parent.separator(),
+
             parent.submenu("Sort tabs by...", Some("/icons/iconic/sort-ascending.svg"), |parent| vec![
                 parent.toggle("Label", state.options.signal_ref(|x| x.sort_tabs == SortTabs::Label), clone!(state => move || {
                     state.options.lock_mut().sort_tabs = SortTabs::Label;
@@ -607,11 +739,63 @@ impl State {
                     state.options.lock_mut().sort_tabs = SortTabs::Url;
                 })),
 
+                parent.toggle("Domain", state.options.signal_ref(|x| x.sort_tabs == SortTabs::Domain), clone!(state => move || {
+                    state.options.lock_mut().sort_tabs = SortTabs::Domain;
+                })),
+
                 parent.toggle("Name", state.options.signal_ref(|x| x.sort_tabs == SortTabs::Name), clone!(state => move || {
                     state.options.lock_mut().sort_tabs = SortTabs::Name;
                 })),
+
+                parent.subseparator(),
+
+                parent.toggle("Container", state.options.signal_ref(|x| x.sort_tabs == SortTabs::Container), clone!(state => move || {
+                    state.options.lock_mut().sort_tabs = SortTabs::Container;
+                })),
+
+                parent.toggle("Tree", state.options.signal_ref(|x| x.sort_tabs == SortTabs::Tree), clone!(state => move || {
+                    state.options.lock_mut().sort_tabs = SortTabs::Tree;
+                })),
             ]),
 
+            parent.toggle("Restore tabs lazily", state.options.signal_ref(|x| x.restore_lazily), clone!(state => move || {
+                let mut options = state.options.lock_mut();
+                options.restore_lazily = !options.restore_lazily;
+            })),
+
+            parent.submenu("Export search results...", Some("/icons/iconic/data-transfer-download.svg"), clone!(state => move |parent| {
+                make_menu_export(&parent, move |format| {
+                    state.export_search_results(format);
+                })
+            })),
+
+            parent.submenu("Recently closed...", Some("/icons/iconic/clock.svg"), clone!(state => move |parent| vec![
+                parent.children_signal_vec(clone!(state => move |parent| {
+                    state.recently_closed.signal_ref(clone!(state => move |sessions| {
+                        make_menu_closed(&parent, sessions, clone!(state => move |session| {
+                            match session {
+                                sidebar::ClosedSession::Tab { session_id, .. } |
+                                sidebar::ClosedSession::Window { session_id, .. } => {
+                                    state.restore_closed(session_id);
+                                },
+                            }
+                        }))
+                    })).to_signal_vec()
+                })),
+
+                parent.subseparator(),
+
+                parent.submenu("Forget...", Some("/icons/iconic/trash.svg"), clone!(state => move |parent| vec![
+                    parent.children_signal_vec(clone!(state => move |parent| {
+                        state.recently_closed.signal_ref(clone!(state => move |sessions| {
+                            make_menu_closed(&parent, sessions, clone!(state => move |session| {
+                                state.forget_closed(session);
+                            }))
+                        })).to_signal_vec()
+                    })),
+                ])),
+            ])),
+
             parent.submenu("Foo", None, |parent| vec![
                 parent.submenu("Bar", None, |parent| vec![
                     parent.submenu("Qux", None, |parent| vec![
@@ -793,8 +977,34 @@ impl State {
                 })),
             ]),
 
+            parent.submenu("Export...", Some("/icons/iconic/data-transfer-download.svg"), clone!(state => move |parent| {
+                make_menu_export(&parent, move |format| {
+                    with_tabs(&state, |tabs| {
+                        state.export_tabs(tabs, format);
+                    });
+                })
+            })),
+
             parent.subseparator(),
 
+            parent.action(
+                "Restore all",
+                Some("/icons/iconic/account-login.svg"),
+                state.menus.state.signal_ref(move |state| {
+                    if let Some(ref state) = state {
+                        state.with_tabs(|tabs| tabs.into_iter().any(|tab| tab.status.get().is_unloaded()))
+
+                    } else {
+                        false
+                    }
+                }),
+                clone!(state => move || {
+                    with_tabs(&state, |tabs| {
+                        state.restore_tabs(tabs);
+                    });
+                }),
+            ),
+
             // TODO put a confirmation box ?
             parent.action(
                 "Unload",
@@ -814,6 +1024,27 @@ impl State {
                 }),
             ),
 
+            parent.action(
+                "Discard",
+                Some("/icons/iconic/moon.svg"),
+                state.menus.state.signal_ref(move |state| {
+                    if let Some(ref state) = state {
+                        state.with_tabs(|tabs| tabs.into_iter().any(|tab| {
+                            let status = tab.status.get();
+                            !status.is_unloaded() && !status.is_discarded()
+                        }))
+
+                    } else {
+                        false
+                    }
+                }),
+                clone!(state => move || {
+                    with_tabs(&state, |tabs| {
+                        state.discard_tabs(tabs);
+                    });
+                }),
+            ),
+
             parent.subseparator(),
 
             // TODO put a spacer/separator to make it harder to click this by accident
@@ -943,6 +1174,22 @@ impl State {
                 }
             })
 
+            .global_event_preventable(clone!(state => move |e: events::KeyDown| {
+                // The search box has its own undo
+                if let None = e.dyn_target::<HtmlInputElement>() {
+                    if e.ctrl_key() && e.key().eq_ignore_ascii_case("z") {
+                        e.prevent_default();
+
+                        if e.shift_key() {
+                            state.redo();
+
+                        } else {
+                            state.undo();
+                        }
+                    }
+                }
+            }))
+
             .children(&mut [
                 html!("div", {
                     .class([
@@ -1204,6 +1451,45 @@ impl State {
                         }),
                     ])
                 }),
+
+                html!("div", {
+                    .class([
+                        &*ROW_STYLE,
+                        &*TOAST_STYLE,
+                    ])
+
+                    .visible_signal(state.toast.signal_ref(|toast| toast.is_some()))
+
+                    .children(&mut [
+                        html!("div", {
+                            .class(&*STRETCH_STYLE)
+                            .text_signal(state.toast.signal_cloned().map(|toast| option_str_default(toast, "")))
+                        }),
+
+                        {
+                            let hovering = Mutable::new(false);
+
+                            html!("div", {
+                                .class(&*TOAST_BUTTON_STYLE)
+                                .class_signal(&*TOAST_BUTTON_HOVER_STYLE, hovering.signal())
+
+                                .event(clone!(hovering => move |_: events::MouseEnter| {
+                                    hovering.set_neq(true);
+                                }))
+
+                                .event(move |_: events::MouseLeave| {
+                                    hovering.set_neq(false);
+                                })
+
+                                .event(clone!(state => move |_: events::Click| {
+                                    state.undo();
+                                }))
+
+                                .text("Undo")
+                            })
+                        },
+                    ])
+                }),
             ])
         })
     }
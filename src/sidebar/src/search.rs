@@ -1,6 +1,7 @@
 //use nom::types::CompleteStr;
 use regex::{Regex, RegexBuilder, escape};
-use crate::types::{State, Group, Tab};
+use uuid::Uuid;
+use crate::types::{State, Group, Tab, TabState};
 
 
 /*named!(atom<CompleteStr, Parsed>,
@@ -41,11 +42,22 @@ impl State {
     pub(crate) fn search_tab(&self, tab: &Tab) {
         let tab_matches = {
             let search_parser = self.search_parser.lock_ref();
-            search_parser.matches_tab(tab)
+            search_parser.matches_tab(&tab.state)
         };
 
         tab.set_matches_search(tab_matches);
     }
+
+    /// Every tab in the window which matches the current search, including the tabs in collapsed groups
+    pub(crate) fn search_results(&self) -> Vec<Uuid> {
+        let search_parser = self.search_parser.lock_ref();
+
+        self.tabs.read().unwrap()
+            .iter()
+            .filter(|tab| search_parser.matches_tab(tab))
+            .map(|tab| tab.id)
+            .collect()
+    }
 }
 
 
@@ -55,6 +67,7 @@ pub(crate) enum Parsed {
     Literal(Regex),
     And(Box<Parsed>, Box<Parsed>),
     IsLoaded,
+    IsDiscarded,
 }
 
 impl Parsed {
@@ -79,6 +92,9 @@ impl Parsed {
                         if *x == "is" && *y == "loaded" {
                             Parsed::IsLoaded
 
+                        } else if *x == "is" && *y == "discarded" {
+                            Parsed::IsDiscarded
+
                         } else {
                             // TODO error on invalid input
                             Parsed::True
@@ -97,7 +113,7 @@ impl Parsed {
             })
     }
 
-    pub(crate) fn matches_tab(&self, tab: &Tab) -> bool {
+    pub(crate) fn matches_tab(&self, tab: &TabState) -> bool {
         match self {
             Parsed::True => true,
 
@@ -114,7 +130,13 @@ impl Parsed {
 
             Parsed::And(left, right) => left.matches_tab(tab) && right.matches_tab(tab),
 
-            Parsed::IsLoaded => !tab.state.status.get().is_unloaded(),
+            // Discarded tabs are in the browser, but their page isn't loaded
+            Parsed::IsLoaded => {
+                let status = tab.status.get();
+                !status.is_unloaded() && !status.is_discarded()
+            },
+
+            Parsed::IsDiscarded => tab.status.get().is_discarded(),
         }
     }
 }
@@ -1,10 +1,12 @@
-use crate::constants::{DRAG_ANIMATION_DURATION, INSERT_ANIMATION_DURATION};
+use crate::constants::{DRAG_ANIMATION_DURATION, INSERT_ANIMATION_DURATION, TOAST_DURATION};
 use std::ops::{Deref, DerefMut};
+use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicU32, Ordering};
-use tab_organizer::{local_storage_get, Port};
+use tab_organizer::{local_storage_get, Port, Timer};
 use tab_organizer::state as shared;
-use tab_organizer::state::{sidebar, TabStatus};
+use tab_organizer::state::{sidebar, SortTabs, TabStatus};
+use tab_organizer::state::options::ExportFormat;
 use crate::url_bar::UrlBar;
 use crate::search;
 use crate::menu::Menu;
@@ -12,6 +14,7 @@ use crate::groups::Groups;
 use uuid::Uuid;
 use web_sys::DomRect;
 use js_sys::Date;
+use futures_signals::map_ref;
 use futures_signals::signal::{Signal, Mutable, MutableLockRef, MutableLockMut};
 use futures_signals::signal_vec::MutableVec;
 use futures_signals::signal_map::MutableBTreeMap;
@@ -237,12 +240,25 @@ pub(crate) struct State {
 
     pub(crate) all_labels: MutableBTreeMap<String, u32>,
 
+    pub(crate) recently_closed: Mutable<Vec<sidebar::ClosedSession>>,
+
+    /// The containers indexed by their cookie store id
+    pub(crate) containers: Mutable<Arc<BTreeMap<String, sidebar::Container>>>,
+
+    pub(crate) undo: Mutable<Option<Arc<String>>>,
+    pub(crate) redo: Mutable<Option<Arc<String>>>,
+    pub(crate) toast: Mutable<Option<Arc<String>>>,
+
     pub(crate) menus: Menus,
     pub(crate) port: Arc<Port<sidebar::ClientMessage, sidebar::ServerMessage>>,
 }
 
 impl State {
-    pub(crate) fn new(port: Arc<Port<sidebar::ClientMessage, sidebar::ServerMessage>>, options: Options, tabs: Vec<shared::Tab>) -> Self {
+    fn containers_map(containers: Vec<sidebar::Container>) -> Arc<BTreeMap<String, sidebar::Container>> {
+        Arc::new(containers.into_iter().map(|container| (container.cookie_store_id.clone(), container)).collect())
+    }
+
+    pub(crate) fn new(port: Arc<Port<sidebar::ClientMessage, sidebar::ServerMessage>>, options: Options, tabs: Vec<shared::Tab>, containers: Vec<sidebar::Container>) -> Self {
         let tabs = tabs.into_iter().enumerate().map(|(index, tab)| Arc::new(TabState::new(tab, index))).collect();
 
         let search_value = local_storage_get("tab-organizer.search").unwrap_or_else(|| "".to_string());
@@ -263,6 +279,14 @@ impl State {
 
             all_labels: MutableBTreeMap::new(),
 
+            recently_closed: Mutable::new(vec![]),
+
+            containers: Mutable::new(Self::containers_map(containers)),
+
+            undo: Mutable::new(None),
+            redo: Mutable::new(None),
+            toast: Mutable::new(None),
+
             dragging: Dragging::new(),
             scrolling: Scrolling::new(scroll_y),
             window_size: Mutable::new(WindowSize::new()),
@@ -276,6 +300,20 @@ impl State {
         state
     }
 
+    pub(crate) fn set_containers(&self, containers: Vec<sidebar::Container>) {
+        self.containers.set_neq(Self::containers_map(containers));
+    }
+
+    /// The groups are recreated whenever this changes, it includes the containers because their names are used for the groups
+    pub(crate) fn sort_tabs_signal(&self) -> impl Signal<Item = SortTabs> {
+        map_ref! {
+            let sort_tabs = self.options.signal_ref(|x| x.sort_tabs),
+            let _containers = self.containers.signal_cloned() => {
+                *sort_tabs
+            }
+        }
+    }
+
     /*fn is_dragging_group(&self, group_id: usize) -> impl Signal<Item = bool> {
         self.dragging.state.signal_ref(move |dragging| {
             if let Some(DragState::Dragging { group, .. }) = dragging {
@@ -322,6 +360,21 @@ impl State {
         self.port.send_message(&sidebar::ClientMessage::UnloadTabs { uuids });
     }
 
+    pub(crate) fn discard_tabs(&self, tabs: &[Arc<Tab>]) {
+        let uuids = tabs.into_iter().map(|tab| tab.id).collect();
+
+        self.port.send_message(&sidebar::ClientMessage::DiscardTabs { uuids });
+    }
+
+    pub(crate) fn restore_tabs(&self, tabs: &[Arc<Tab>]) {
+        let uuids = tabs.into_iter()
+            .filter(|tab| tab.status.get().is_unloaded())
+            .map(|tab| tab.id)
+            .collect();
+
+        self.port.send_message(&sidebar::ClientMessage::RestoreTabs { uuids });
+    }
+
     pub(crate) fn pin_tabs(&self, tabs: &[Arc<Tab>], pinned: bool) {
         let uuids = tabs.into_iter().map(|tab| tab.id).collect();
 
@@ -344,12 +397,61 @@ impl State {
 
         self.port.send_message(&sidebar::ClientMessage::RemoveLabelFromTabs { uuids, label_name });
     }
+
+    pub(crate) fn export_tabs(&self, tabs: &[Arc<Tab>], format: ExportFormat) {
+        let uuids = tabs.into_iter().map(|tab| tab.id).collect();
+
+        self.port.send_message(&sidebar::ClientMessage::ExportTabs { uuids, format });
+    }
+
+    pub(crate) fn export_search_results(&self, format: ExportFormat) {
+        let uuids = self.search_results();
+
+        self.port.send_message(&sidebar::ClientMessage::ExportTabs { uuids, format });
+    }
+
+    pub(crate) fn restore_closed(&self, session_id: String) {
+        self.port.send_message(&sidebar::ClientMessage::RestoreClosed { session_id });
+    }
+
+    pub(crate) fn forget_closed(&self, session: sidebar::ClosedSession) {
+        self.port.send_message(&sidebar::ClientMessage::ForgetClosed { session });
+    }
+
+    pub(crate) fn undo(&self) {
+        self.toast.set_neq(None);
+        self.port.send_message(&sidebar::ClientMessage::Undo);
+    }
+
+    pub(crate) fn redo(&self) {
+        self.toast.set_neq(None);
+        self.port.send_message(&sidebar::ClientMessage::Redo);
+    }
+
+    pub(crate) fn show_toast(state: &Arc<Self>, description: String) {
+        let description = Arc::new(description);
+
+        state.toast.set(Some(description.clone()));
+
+        let state = state.clone();
+
+        Timer::new(TOAST_DURATION, move || {
+            let mut toast = state.toast.lock_mut();
+
+            // It only hides the toast if it wasn't replaced by a newer toast
+            if toast.as_ref().map(|toast| Arc::ptr_eq(toast, &description)).unwrap_or(false) {
+                *toast = None;
+            }
+        }).forget();
+    }
 }
 
 
 #[derive(Debug)]
 pub(crate) struct TabState {
     pub(crate) id: Uuid,
+    pub(crate) cookie_store_id: Option<Arc<String>>,
+    pub(crate) opener: Option<Uuid>,
     pub(crate) favicon_url: Mutable<Option<Arc<String>>>,
     pub(crate) title: Mutable<Option<Arc<String>>>,
     pub(crate) url: Mutable<Option<Arc<String>>>,
@@ -365,12 +467,21 @@ pub(crate) struct TabState {
     pub(crate) timestamp_created: Mutable<f64>,
     pub(crate) timestamp_focused: Mutable<Option<f64>>,
     pub(crate) labels: Mutable<Vec<shared::Label>>,
+
+    // These are only used for SortTabs::Tree
+    pub(crate) depth: Mutable<usize>,
+    pub(crate) has_children: Mutable<bool>,
+    pub(crate) collapsed: Mutable<bool>,
+    /// One of the tab's ancestors is collapsed
+    pub(crate) hidden_in_tree: Mutable<bool>,
 }
 
 impl TabState {
     pub(crate) fn new(state: shared::Tab, index: usize) -> Self {
         Self {
             id: state.serialized.uuid,
+            cookie_store_id: state.serialized.cookie_store_id.map(Arc::new),
+            opener: state.serialized.opener,
             favicon_url: Mutable::new(state.serialized.favicon_url.map(Arc::new)),
             title: Mutable::new(state.serialized.title.map(Arc::new)),
             url: Mutable::new(state.serialized.url.map(Arc::new)),
@@ -386,6 +497,11 @@ impl TabState {
             timestamp_created: Mutable::new(state.serialized.timestamp_created),
             timestamp_focused: Mutable::new(state.serialized.timestamp_focused),
             labels: Mutable::new(state.serialized.labels),
+
+            depth: Mutable::new(0),
+            has_children: Mutable::new(false),
+            collapsed: Mutable::new(state.serialized.collapsed),
+            hidden_in_tree: Mutable::new(false),
         }
     }
 
@@ -459,11 +575,15 @@ impl Tab {
         self.status.signal_ref(|status| status.is_unloaded())
     }
 
+    pub(crate) fn is_discarded(&self) -> impl Signal<Item = bool> {
+        self.status.signal_ref(|status| status.is_discarded())
+    }
+
     pub(crate) fn is_loading(&self) -> impl Signal<Item = bool> {
         self.status.signal_ref(|status| {
             match status {
                 TabStatus::New | TabStatus::Loading => true,
-                TabStatus::Unloaded | TabStatus::Complete => false,
+                TabStatus::Unloaded | TabStatus::Complete | TabStatus::Discarded => false,
             }
         })
     }
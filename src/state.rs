@@ -7,6 +7,7 @@ use crate::browser;
 
 pub mod sidebar {
     use super::{Label, Tab, TabStatus, WindowOptions};
+    use super::options::ExportFormat;
     use serde_derive::{Serialize, Deserialize};
     use uuid::Uuid;
 
@@ -29,6 +30,10 @@ pub mod sidebar {
         UnloadTabs {
             uuids: Vec<Uuid>,
         },
+        /// Uses `tabs.discard`, so the tabs stay in the browser but their page is unloaded
+        DiscardTabs {
+            uuids: Vec<Uuid>,
+        },
         MuteTabs {
             uuids: Vec<Uuid>,
             muted: bool,
@@ -41,6 +46,11 @@ pub mod sidebar {
             uuids: Vec<Uuid>,
             pinned: bool,
         },
+        /// Hides or shows the children of the tab in `SortTabs::Tree`
+        CollapseTab {
+            uuid: Uuid,
+            collapsed: bool,
+        },
         AddLabelToTabs {
             uuids: Vec<Uuid>,
             label: Label,
@@ -49,6 +59,53 @@ pub mod sidebar {
             uuids: Vec<Uuid>,
             label_name: String,
         },
+        /// Recreates the unloaded tabs in the browser, they are discarded if `WindowOptions::restore_lazily` is true
+        RestoreTabs {
+            uuids: Vec<Uuid>,
+        },
+        ExportTabs {
+            uuids: Vec<Uuid>,
+            format: ExportFormat,
+        },
+        RestoreClosed {
+            session_id: String,
+        },
+        ForgetClosed {
+            session: ClosedSession,
+        },
+        Undo,
+        Redo,
+    }
+
+
+    /// A container from `browser.contextualIdentities`, the tabs refer to it with `SerializedTab::cookie_store_id`
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Container {
+        pub cookie_store_id: String,
+        pub name: String,
+        /// A CSS hex color
+        pub color_code: String,
+    }
+
+
+    /// A tab or window from `browser.sessions.getRecentlyClosed`
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum ClosedSession {
+        Tab {
+            session_id: String,
+            window_id: i32,
+            timestamp_closed: f64,
+            title: Option<String>,
+            url: Option<String>,
+            favicon_url: Option<String>,
+        },
+        Window {
+            session_id: String,
+            timestamp_closed: f64,
+            title: Option<String>,
+            tabs: usize,
+        },
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -66,6 +123,9 @@ pub mod sidebar {
         Pinned {
             pinned: bool,
         },
+        Collapsed {
+            collapsed: bool,
+        },
         AddedToLabel {
             label: Label,
         },
@@ -96,6 +156,7 @@ pub mod sidebar {
         Initial {
             tabs: Vec<Tab>,
             options: WindowOptions,
+            containers: Vec<Container>,
         },
         TabInserted {
             tab_index: usize,
@@ -112,32 +173,416 @@ pub mod sidebar {
             old_tab_index: usize,
             new_tab_index: usize,
         },
+        RecentlyClosed {
+            sessions: Vec<ClosedSession>,
+        },
+        /// A container was created, renamed, recolored, or removed
+        Containers {
+            containers: Vec<Container>,
+        },
+        /// The descriptions of the actions which will be undone or redone next
+        History {
+            undo: Option<String>,
+            redo: Option<String>,
+        },
+        /// A new action was done which can be undone
+        Performed {
+            description: String,
+        },
     }
 }
 
 
 pub mod options {
     use serde_derive::{Serialize, Deserialize};
-    use super::SerializedTab;
+    use uuid::Uuid;
+
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ImportMode {
+        /// Removes every window which isn't open in the browser, and then adds the imported windows as unloaded windows.
+        ///
+        /// Imported windows which are open in the browser are merged into the open window instead.
+        Replace,
+        /// Windows and tabs which already exist are merged together, everything else is added as unloaded windows
+        Merge,
+        /// Every imported tab is added as an unloaded tab to the end of the focused window
+        Append,
+    }
+
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ExportFormat {
+        Json,
+        /// Netscape bookmarks file, it can be imported into any browser
+        BookmarksHtml,
+        Markdown,
+        Csv,
+        /// One URL per line
+        Urls,
+    }
+
+    impl ExportFormat {
+        pub fn extension(&self) -> &'static str {
+            match self {
+                Self::Json => "json",
+                Self::BookmarksHtml => "html",
+                Self::Markdown => "md",
+                Self::Csv => "csv",
+                Self::Urls => "txt",
+            }
+        }
+
+        pub fn mime_type(&self) -> &'static str {
+            match self {
+                Self::Json => "application/json",
+                Self::BookmarksHtml => "text/html",
+                Self::Markdown => "text/markdown",
+                Self::Csv => "text/csv",
+                Self::Urls => "text/plain",
+            }
+        }
+    }
+
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum ImportFormat {
+        TabOrganizer,
+        /// One tab per line as `url | title`, with blank lines between the groups
+        OneTab,
+        SessionBuddy,
+        /// The decompressed contents of Firefox's `sessionstore.jsonlz4`
+        FirefoxSession,
+        /// One URL per line
+        Urls,
+    }
+
+    impl ImportFormat {
+        pub fn name(&self) -> &'static str {
+            match self {
+                Self::TabOrganizer => "Tab Organizer",
+                Self::OneTab => "OneTab",
+                Self::SessionBuddy => "Session Buddy",
+                Self::FirefoxSession => "Firefox session",
+                Self::Urls => "URL list",
+            }
+        }
+    }
+
+
+    /// Which tabs should be exported from the options page
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum ExportFilter {
+        All,
+        Window {
+            uuid: Uuid,
+        },
+        Label {
+            name: String,
+        },
+    }
+
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct WindowSummary {
+        pub uuid: Uuid,
+        pub name: String,
+        pub tabs: usize,
+    }
+
+
+    /// Why a backup was made, the oldest backups are removed separately for each reason
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum BackupReason {
+        /// The periodic backups, and the backups which were made from the options page
+        Regular,
+        BeforeRestore,
+        BeforeMigration,
+    }
+
+    impl Default for BackupReason {
+        #[inline]
+        fn default() -> Self {
+            BackupReason::Regular
+        }
+    }
+
+
+    /// A compressed snapshot of the database, which is stored separately from the windows and tabs
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct BackupSummary {
+        pub uuid: Uuid,
+        pub timestamp_created: f64,
+        pub windows: usize,
+        pub tabs: usize,
+        #[serde(default)]
+        pub reason: BackupReason,
+    }
+
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct RecordUsage {
+        pub name: String,
+        pub count: usize,
+        pub bytes: usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct StorageUsage {
+        /// The size of the keys and values for each type of record
+        pub records: Vec<RecordUsage>,
+        /// This is what the browser reports, it is `None` if the browser doesn't support it
+        pub bytes_in_use: Option<f64>,
+        pub compact: bool,
+    }
+
+
+    /// A record which couldn't be migrated to the latest version, so it was removed
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct MigrationFailure {
+        pub from_version: u32,
+        pub to_version: u32,
+        pub key: String,
+        pub message: String,
+    }
+
+    impl std::fmt::Display for MigrationFailure {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{} could not be migrated from version {} to {}: {}", self.key, self.from_version, self.to_version, self.message)
+        }
+    }
+
+
+    /// Tabs with this label are never unloaded automatically
+    pub const KEEP_LOADED_LABEL: &str = "keep-loaded";
+
+    /// Unloads tabs which haven't been used for a while, so they don't use any memory
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct AutoUnload {
+        pub enabled: bool,
+        /// Tabs which haven't been focused for this many milliseconds are unloaded
+        pub idle_duration: f64,
+        /// If a window has more loaded tabs than this, then the least recently focused tabs are unloaded
+        pub max_loaded_tabs: Option<usize>,
+        /// Tabs on these domains (or their subdomains) are never unloaded
+        pub excluded_domains: Vec<String>,
+        /// The tabs are discarded instead of unloaded, so they stay in the browser's tab strip
+        #[serde(default)]
+        pub discard: bool,
+    }
+
+    impl Default for AutoUnload {
+        fn default() -> Self {
+            Self {
+                enabled: false,
+                idle_duration: crate::TimeDifference::HOUR,
+                max_loaded_tabs: None,
+                excluded_domains: vec![],
+                discard: false,
+            }
+        }
+    }
+
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct SyncStatus {
+        pub enabled: bool,
+        pub timestamp_synced: Option<f64>,
+        /// The error from the last sync, if it failed
+        pub error: Option<String>,
+    }
+
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum DatabaseProblem {
+        /// A tab record which isn't in any window, it is repaired by moving it into a new window
+        OrphanTab {
+            tab_uuid: Uuid,
+        },
+        /// A window refers to a tab record which doesn't exist
+        MissingTab {
+            window_uuid: Uuid,
+            tab_uuid: Uuid,
+        },
+        /// The same tab is in multiple windows, or multiple times in the same window
+        DuplicateTab {
+            window_uuid: Uuid,
+            tab_uuid: Uuid,
+        },
+        /// A window record which isn't in `windows`
+        OrphanWindow {
+            window_uuid: Uuid,
+        },
+        /// `windows` refers to a window record which doesn't exist
+        MissingWindow {
+            window_uuid: Uuid,
+        },
+        DuplicateWindow {
+            window_uuid: Uuid,
+        },
+        /// This is never repaired, because that would lose data
+        InvalidRecord {
+            key: String,
+            message: String,
+        },
+    }
+
+    impl std::fmt::Display for DatabaseProblem {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Self::OrphanTab { tab_uuid } => write!(f, "Tab {} is not in any window", tab_uuid),
+                Self::MissingTab { window_uuid, tab_uuid } => write!(f, "Tab {} in window {} does not exist", tab_uuid, window_uuid),
+                Self::DuplicateTab { window_uuid, tab_uuid } => write!(f, "Tab {} in window {} is a duplicate", tab_uuid, window_uuid),
+                Self::OrphanWindow { window_uuid } => write!(f, "Window {} is not in the window list", window_uuid),
+                Self::MissingWindow { window_uuid } => write!(f, "Window {} does not exist", window_uuid),
+                Self::DuplicateWindow { window_uuid } => write!(f, "Window {} is in the window list multiple times", window_uuid),
+                Self::InvalidRecord { key, message } => write!(f, "{} is invalid: {}", key, message),
+            }
+        }
+    }
+
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    pub enum ImportError {
+        InvalidJson {
+            message: String,
+        },
+        InvalidMozLz4 {
+            message: String,
+        },
+        MissingVersion,
+        UnsupportedVersion {
+            version: u32,
+        },
+        MissingWindows,
+        MissingWindow {
+            window_uuid: Uuid,
+        },
+        MissingTab {
+            window_uuid: Uuid,
+            tab_uuid: Uuid,
+        },
+        InvalidRecord {
+            key: String,
+            message: String,
+        },
+        NoTabs,
+        MissingBackup {
+            uuid: Uuid,
+        },
+        InvalidBackup {
+            message: String,
+        },
+    }
+
+    impl std::fmt::Display for ImportError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Self::InvalidJson { message } => write!(f, "The file is not valid JSON: {}", message),
+                Self::InvalidMozLz4 { message } => write!(f, "The file is not a valid mozLz4 file: {}", message),
+                Self::MissingVersion => write!(f, "The file does not have a version"),
+                Self::UnsupportedVersion { version } => write!(f, "Version {} is not supported", version),
+                Self::MissingWindows => write!(f, "The file does not have any windows"),
+                Self::MissingWindow { window_uuid } => write!(f, "Window {} does not exist", window_uuid),
+                Self::MissingTab { window_uuid, tab_uuid } => write!(f, "Tab {} in window {} does not exist", tab_uuid, window_uuid),
+                Self::InvalidRecord { key, message } => write!(f, "{} is invalid: {}", key, message),
+                Self::NoTabs => write!(f, "The file does not have any tabs"),
+                Self::MissingBackup { uuid } => write!(f, "Backup {} does not exist", uuid),
+                Self::InvalidBackup { message } => write!(f, "The backup is corrupted: {}", message),
+            }
+        }
+    }
 
 
     #[derive(Debug, Serialize, Deserialize)]
     #[serde(tag = "type")]
     pub enum ClientMessage {
         Initialize,
+        PreviewImport {
+            data: String,
+        },
         Import {
             data: String,
+            mode: ImportMode,
+        },
+        Export {
+            format: ExportFormat,
+            filter: ExportFilter,
+        },
+        CheckDatabase {
+            repair: bool,
+        },
+        CreateBackup,
+        RestoreBackup {
+            uuid: Uuid,
+            mode: ImportMode,
+        },
+        GetStorageUsage,
+        /// Rewrites every tab record in the compact (or normal) format
+        SetCompact {
+            compact: bool,
+        },
+        /// Syncs the labels and window names with the other devices through `storage.sync`
+        SetSync {
+            enabled: bool,
+        },
+        SyncNow,
+        DismissMigrationFailures,
+        /// How many milliseconds the closed tabs are kept for, so they can be restored when they are reopened
+        SetTombstoneDuration {
+            duration: f64,
+        },
+        SetAutoUnload {
+            settings: AutoUnload,
         },
-        Export,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
     #[serde(tag = "type")]
     pub enum ServerMessage {
         Initial,
+        Summary {
+            windows: Vec<WindowSummary>,
+            labels: Vec<String>,
+        },
         ExportFinished,
+        ImportPreview {
+            format: ImportFormat,
+            windows: Vec<WindowSummary>,
+            labels: Vec<String>,
+        },
         Imported {
-            tabs: Vec<SerializedTab>,
+            windows: usize,
+            tabs: usize,
+        },
+        ImportFailed {
+            errors: Vec<ImportError>,
+        },
+        DatabaseChecked {
+            problems: Vec<DatabaseProblem>,
+            repaired: usize,
+        },
+        StorageUsage {
+            usage: StorageUsage,
+        },
+        /// The tab records were not changed
+        CompactFailed {
+            message: String,
+        },
+        SyncStatus {
+            status: SyncStatus,
+        },
+        MigrationFailures {
+            failures: Vec<MigrationFailure>,
+        },
+        TombstoneDuration {
+            duration: f64,
+        },
+        AutoUnload {
+            settings: AutoUnload,
         },
     }
 }
@@ -151,19 +596,28 @@ pub enum SortTabs {
     TimeFocused,
     TimeCreated,
     Url,
+    /// The tabs are grouped by their registrable domain, so subdomains are in the same group
+    Domain,
     Name,
+    Container,
+    /// The tabs are shown underneath the tab which opened them
+    Tree,
 }
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowOptions {
     pub sort_tabs: SortTabs,
+    /// Unloaded tabs are restored as discarded tabs, so their page isn't loaded until they are focused
+    #[serde(default)]
+    pub restore_lazily: bool,
 }
 
 impl WindowOptions {
     pub fn new() -> Self {
         Self {
             sort_tabs: SortTabs::Label,
+            restore_lazily: false,
         }
     }
 }
@@ -187,6 +641,13 @@ pub struct SerializedTab {
     pub url: Option<String>,
     pub title: Option<String>,
     pub muted: bool,
+    /// The container which the tab is in, this is `None` for tabs which were saved before containers were supported
+    pub cookie_store_id: Option<String>,
+    /// The tab which opened this tab, it is used for `SortTabs::Tree`
+    pub opener: Option<Uuid>,
+    /// The tab's children are hidden in `SortTabs::Tree`
+    #[serde(default)]
+    pub collapsed: bool,
 }
 
 impl SerializedTab {
@@ -201,6 +662,9 @@ impl SerializedTab {
             url: None,
             title: None,
             muted: false,
+            cookie_store_id: None,
+            opener: None,
+            collapsed: false,
         }
     }
 
@@ -229,10 +693,6 @@ impl SerializedTab {
         removed
     }
 
-    pub fn key(uuid: Uuid) -> String {
-        format!("tab-ids.{}", uuid)
-    }
-
     pub fn has_good_url(&self) -> bool {
         self.url.as_deref().map(|url| {
             // Based on the restrictions here: https://developer.mozilla.org/en-US/docs/Mozilla/Add-ons/WebExtensions/API/tabs/create
@@ -311,11 +771,28 @@ impl SerializedTab {
             changed = true;
         }
 
+        // The container of a tab never changes, but the records from older versions don't have it
+        if self.cookie_store_id != tab.cookie_store_id {
+            self.cookie_store_id = tab.cookie_store_id.clone();
+            changed = true;
+        }
+
         changed
     }
 }
 
 
+/// A tab which was closed, it is kept for a while so that it can be restored when the tab is reopened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializedTombstone {
+    pub tab: SerializedTab,
+    pub window_uuid: Uuid,
+    /// The index of the tab in `SerializedWindow::tabs` when it was closed
+    pub tab_index: usize,
+    pub timestamp_removed: f64,
+}
+
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SerializedWindow {
     pub uuid: Uuid,
@@ -336,10 +813,6 @@ impl SerializedWindow {
         }
     }
 
-    pub fn key(uuid: Uuid) -> String {
-        format!("window-ids.{}", uuid)
-    }
-
     pub fn tab_index(&self, tab_uuid: Uuid) -> Option<usize> {
         self.tabs.iter().position(|x| *x == tab_uuid)
     }
@@ -352,6 +825,8 @@ pub enum TabStatus {
     New,
     Loading,
     Complete,
+    /// The tab is in the browser, but its page isn't loaded
+    Discarded,
 }
 
 impl TabStatus {
@@ -362,6 +837,14 @@ impl TabStatus {
             _ => false,
         }
     }
+
+    #[inline]
+    pub fn is_discarded(&self) -> bool {
+        match self {
+            Self::Discarded => true,
+            _ => false,
+        }
+    }
 }
 
 
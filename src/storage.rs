@@ -0,0 +1,507 @@
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use futures::future::{ready, LocalBoxFuture};
+use futures::FutureExt;
+use js_sys::{Object, Array, Reflect, Promise};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_extension::{browser, StorageChange};
+use crate::Listener;
+
+
+// IndexedDB is written in JS because it is a lot simpler than using web_sys
+#[wasm_bindgen(inline_js = "
+    var STORE = \"values\";
+
+    function wait(request) {
+        return new Promise(function (resolve, reject) {
+            request.onsuccess = function () { resolve(request.result); };
+            request.onerror = function () { reject(request.error); };
+        });
+    }
+
+    function store(db, mode) {
+        return db.transaction(STORE, mode).objectStore(STORE);
+    }
+
+    export function idb_open(name) {
+        var request = indexedDB.open(name, 1);
+
+        request.onupgradeneeded = function () {
+            request.result.createObjectStore(STORE);
+        };
+
+        return wait(request);
+    }
+
+    export function idb_count(db) {
+        return wait(store(db, \"readonly\").count());
+    }
+
+    export function idb_get_outside(db, lower, upper) {
+        var values = store(db, \"readonly\");
+
+        var ranges = [IDBKeyRange.upperBound(lower, true), IDBKeyRange.lowerBound(upper)];
+
+        return Promise.all(ranges.map(function (range) {
+            return Promise.all([wait(values.getAllKeys(range)), wait(values.getAll(range))]);
+        })).then(function (results) {
+            return [results[0][0].concat(results[1][0]), results[0][1].concat(results[1][1])];
+        });
+    }
+
+    export function idb_get_keys_inside(db, lower, upper) {
+        return wait(store(db, \"readonly\").getAllKeys(IDBKeyRange.bound(lower, upper, false, true)));
+    }
+
+    export function idb_get(db, keys) {
+        var values = store(db, \"readonly\");
+
+        return Promise.all(keys.map(function (key) {
+            return wait(values.get(key));
+        }));
+    }
+
+    export function idb_write(db, updated, removed) {
+        return new Promise(function (resolve, reject) {
+            var transaction = db.transaction(STORE, \"readwrite\");
+            var values = transaction.objectStore(STORE);
+
+            updated.forEach(function (pair) { values.put(pair[1], pair[0]); });
+            removed.forEach(function (key) { values.delete(key); });
+
+            transaction.oncomplete = function () { resolve(); };
+            transaction.onerror = function () { reject(transaction.error); };
+            transaction.onabort = function () { reject(transaction.error); };
+        });
+    }
+
+    // This includes everything which the extension stores, but most of it is in IndexedDB
+    export function storage_usage() {
+        if (navigator.storage && navigator.storage.estimate) {
+            return navigator.storage.estimate().then(function (estimate) { return estimate.usage; });
+
+        } else {
+            return Promise.resolve(undefined);
+        }
+    }
+
+    export function channel_new(name) {
+        return new BroadcastChannel(name);
+    }
+
+    export function channel_listen(name, f) {
+        var channel = new BroadcastChannel(name);
+        channel.onmessage = function (event) { f(event.data); };
+        return channel;
+    }
+
+    export function channel_post(channel, message) {
+        channel.postMessage(message);
+    }
+
+    export function channel_close(channel) {
+        channel.close();
+    }
+")]
+extern "C" {
+    fn idb_open(name: &str) -> Promise;
+    fn idb_count(db: &JsValue) -> Promise;
+    fn idb_get_outside(db: &JsValue, lower: &str, upper: &str) -> Promise;
+    fn idb_get_keys_inside(db: &JsValue, lower: &str, upper: &str) -> Promise;
+    fn idb_get(db: &JsValue, keys: &Array) -> Promise;
+    fn idb_write(db: &JsValue, updated: &Array, removed: &Array) -> Promise;
+
+    fn storage_usage() -> Promise;
+
+    fn channel_new(name: &str) -> JsValue;
+    fn channel_listen(name: &str, f: &Closure<dyn FnMut(JsValue)>) -> JsValue;
+    fn channel_post(channel: &JsValue, message: &JsValue);
+    fn channel_close(channel: &JsValue);
+}
+
+
+/// Stops listening for changes when it is dropped
+pub struct StorageListener(Box<dyn std::any::Any>);
+
+impl std::fmt::Debug for StorageListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("StorageListener").finish()
+    }
+}
+
+
+/// The place where a `Database` loads its values from and flushes its changes to.
+///
+/// All keys and values are strings, the values are the JSON serialization of the records.
+pub trait StorageBackend: std::fmt::Debug {
+    fn get_all(&self) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>>;
+
+    fn set(&self, values: Vec<(String, String)>) -> LocalBoxFuture<'static, Result<(), JsValue>>;
+
+    fn remove(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<(), JsValue>>;
+
+    /// Returns the values for the keys, keys which don't exist are ignored
+    fn get(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>>;
+
+    /// Keys which aren't returned by `get_all`, they are loaded later with `get`
+    fn lazy_keys(&self) -> LocalBoxFuture<'static, Result<Vec<String>, JsValue>> {
+        ready(Ok(vec![])).boxed_local()
+    }
+
+    /// Returns `None` if the backend cannot measure how much space it uses
+    fn bytes_in_use(&self) -> LocalBoxFuture<'static, Result<Option<f64>, JsValue>> {
+        ready(Ok(None)).boxed_local()
+    }
+
+    /// Calls `f` with the new values whenever the storage is changed, including changes from other extension pages.
+    ///
+    /// The new value is `None` if the key was removed. It returns `None` if the storage cannot be shared with other pages.
+    fn on_changed(&self, _f: Box<dyn FnMut(Vec<(String, Option<String>)>)>) -> Option<StorageListener> {
+        None
+    }
+}
+
+
+/// Stores everything inside of `browser.storage.local`
+#[derive(Debug)]
+pub struct LocalStorage;
+
+impl LocalStorage {
+    #[inline]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl LocalStorage {
+    fn get_values(keys: &JsValue) -> impl Future<Output = Result<HashMap<String, String>, JsValue>> {
+        let fut = JsFuture::from(browser.storage().local().get(keys));
+
+        async move {
+            let db: Object = fut.await?.unchecked_into();
+
+            Ok(Object::entries(&db).iter().map(|entry| {
+                let entry: Array = entry.unchecked_into();
+                (entry.get(0).as_string().unwrap(), entry.get(1).as_string().unwrap())
+            }).collect())
+        }
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn get_all(&self) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        Self::get_values(&JsValue::null()).boxed_local()
+    }
+
+    fn get(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        Self::get_values(&keys.into_iter().map(JsValue::from).collect::<Array>()).boxed_local()
+    }
+
+    fn set(&self, values: Vec<(String, String)>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let updated = Object::new();
+
+        for (key, value) in values {
+            Reflect::set(&updated, &JsValue::from(key), &JsValue::from(value)).unwrap();
+        }
+
+        let fut = JsFuture::from(browser.storage().local().set(&updated));
+
+        async move {
+            let _ = fut.await?;
+            Ok(())
+        }.boxed_local()
+    }
+
+    fn remove(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let removed = keys.into_iter().map(JsValue::from).collect::<Array>();
+
+        let fut = JsFuture::from(browser.storage().local().remove(&removed));
+
+        async move {
+            let _ = fut.await?;
+            Ok(())
+        }.boxed_local()
+    }
+
+    fn bytes_in_use(&self) -> LocalBoxFuture<'static, Result<Option<f64>, JsValue>> {
+        // Firefox doesn't implement getBytesInUse for storage.local, so it throws an error
+        let promise = match browser.storage().local().get_bytes_in_use(&JsValue::null()) {
+            Ok(promise) => promise,
+            Err(_) => return ready(Ok(None)).boxed_local(),
+        };
+
+        let fut = JsFuture::from(promise);
+
+        async move {
+            Ok(fut.await?.as_f64())
+        }.boxed_local()
+    }
+
+    fn on_changed(&self, mut f: Box<dyn FnMut(Vec<(String, Option<String>)>)>) -> Option<StorageListener> {
+        let listener: Listener<dyn FnMut(Object, String)> = Listener::new(browser.storage().on_changed(), Closure::new(move |changes: Object, area_name: String| {
+            if area_name == "local" {
+                f(Object::entries(&changes).iter().map(|entry| {
+                    let entry: Array = entry.unchecked_into();
+                    let change: StorageChange = entry.get(1).unchecked_into();
+                    (entry.get(0).as_string().unwrap(), change.new_value().as_string())
+                }).collect());
+            }
+        }));
+
+        Some(StorageListener(Box::new(listener)))
+    }
+}
+
+
+type MemoryListener = RefCell<Box<dyn FnMut(Vec<(String, Option<String>)>)>>;
+
+/// Stores everything inside of a `HashMap`, it doesn't touch any browser APIs.
+///
+/// This is cheap to clone, the clones share the same values. A change made through one clone
+/// is sent to the `on_changed` listeners of every clone, just like `storage.onChanged`.
+#[derive(Clone)]
+pub struct MemoryStorage {
+    values: Rc<RefCell<HashMap<String, String>>>,
+    listeners: Rc<RefCell<Vec<Weak<MemoryListener>>>>,
+}
+
+impl std::fmt::Debug for MemoryStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MemoryStorage")
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl MemoryStorage {
+    #[inline]
+    pub fn new() -> Self {
+        Self::from_values(HashMap::new())
+    }
+
+    pub fn from_values(values: HashMap<String, String>) -> Self {
+        Self {
+            values: Rc::new(RefCell::new(values)),
+            listeners: Rc::new(RefCell::new(vec![])),
+        }
+    }
+
+    /// Returns a snapshot of the values which have been flushed so far
+    pub fn values(&self) -> HashMap<String, String> {
+        self.values.borrow().clone()
+    }
+
+    fn notify(&self, changes: Vec<(String, Option<String>)>) {
+        // The listeners are removed when their StorageListener is dropped
+        let listeners: Vec<Rc<MemoryListener>> = {
+            let mut listeners = self.listeners.borrow_mut();
+            listeners.retain(|listener| listener.strong_count() > 0);
+            listeners.iter().filter_map(|listener| listener.upgrade()).collect()
+        };
+
+        for listener in listeners {
+            (listener.borrow_mut())(changes.clone());
+        }
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn get_all(&self) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        ready(Ok(self.values())).boxed_local()
+    }
+
+    fn get(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        let values = self.values.borrow();
+
+        ready(Ok(keys.into_iter().filter_map(|key| {
+            let value = values.get(&key)?.clone();
+            Some((key, value))
+        }).collect())).boxed_local()
+    }
+
+    fn set(&self, values: Vec<(String, String)>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let changes = values.iter().map(|(key, value)| (key.clone(), Some(value.clone()))).collect();
+
+        self.values.borrow_mut().extend(values);
+
+        self.notify(changes);
+
+        ready(Ok(())).boxed_local()
+    }
+
+    fn remove(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        {
+            let mut lock = self.values.borrow_mut();
+
+            for key in keys.iter() {
+                lock.remove(key);
+            }
+        }
+
+        self.notify(keys.into_iter().map(|key| (key, None)).collect());
+
+        ready(Ok(())).boxed_local()
+    }
+
+    fn on_changed(&self, f: Box<dyn FnMut(Vec<(String, Option<String>)>)>) -> Option<StorageListener> {
+        let listener: Rc<MemoryListener> = Rc::new(RefCell::new(f));
+
+        self.listeners.borrow_mut().push(Rc::downgrade(&listener));
+
+        Some(StorageListener(Box::new(listener)))
+    }
+}
+
+
+// Every key which starts with `prefix` is between `prefix` and this
+fn prefix_end(prefix: &str) -> String {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    let last = chars.pop().unwrap();
+    chars.push(std::char::from_u32(last as u32 + 1).unwrap());
+    chars.into_iter().collect()
+}
+
+fn array_to_strings(array: &JsValue) -> Vec<String> {
+    array.unchecked_ref::<Array>().iter().map(|key| key.as_string().unwrap()).collect()
+}
+
+
+struct ChannelListener {
+    channel: JsValue,
+    _closure: Closure<dyn FnMut(JsValue)>,
+}
+
+impl Drop for ChannelListener {
+    fn drop(&mut self) {
+        channel_close(&self.channel);
+    }
+}
+
+
+/// Stores everything inside of IndexedDB, the keys which start with `lazy_prefix` are loaded lazily.
+///
+/// The changes are sent to the other extension pages with a `BroadcastChannel`, because IndexedDB doesn't have change events.
+#[derive(Debug)]
+pub struct IndexedDbStorage {
+    db: JsValue,
+    name: String,
+    channel: JsValue,
+    lazy_prefix: &'static str,
+}
+
+impl IndexedDbStorage {
+    pub fn open(name: &str, lazy_prefix: &'static str) -> impl Future<Output = Result<Self, JsValue>> {
+        let name = name.to_string();
+        let fut = JsFuture::from(idb_open(&name));
+
+        async move {
+            let db = fut.await?;
+
+            Ok(Self {
+                db,
+                channel: channel_new(&name),
+                name,
+                lazy_prefix,
+            })
+        }
+    }
+
+    pub fn is_empty(&self) -> impl Future<Output = Result<bool, JsValue>> {
+        let fut = JsFuture::from(idb_count(&self.db));
+
+        async move {
+            Ok(fut.await?.as_f64().unwrap() == 0.0)
+        }
+    }
+
+    // The message is an array of `[key, value]`, the value is missing if the key was removed
+    fn write(&self, updated: Array, removed: Array, message: Array) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let fut = JsFuture::from(idb_write(&self.db, &updated, &removed));
+        let channel = self.channel.clone();
+
+        async move {
+            let _ = fut.await?;
+            channel_post(&channel, &message);
+            Ok(())
+        }.boxed_local()
+    }
+}
+
+impl StorageBackend for IndexedDbStorage {
+    fn get_all(&self) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        let fut = JsFuture::from(idb_get_outside(&self.db, self.lazy_prefix, &prefix_end(self.lazy_prefix)));
+
+        async move {
+            let result: Array = fut.await?.unchecked_into();
+
+            let keys = array_to_strings(&result.get(0));
+            let values = array_to_strings(&result.get(1));
+
+            Ok(keys.into_iter().zip(values).collect())
+        }.boxed_local()
+    }
+
+    fn lazy_keys(&self) -> LocalBoxFuture<'static, Result<Vec<String>, JsValue>> {
+        let fut = JsFuture::from(idb_get_keys_inside(&self.db, self.lazy_prefix, &prefix_end(self.lazy_prefix)));
+
+        async move {
+            Ok(array_to_strings(&fut.await?))
+        }.boxed_local()
+    }
+
+    fn get(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<HashMap<String, String>, JsValue>> {
+        let fut = JsFuture::from(idb_get(&self.db, &keys.iter().map(JsValue::from).collect::<Array>()));
+
+        async move {
+            let values: Array = fut.await?.unchecked_into();
+
+            Ok(keys.into_iter().zip(values.iter()).filter_map(|(key, value)| {
+                Some((key, value.as_string()?))
+            }).collect())
+        }.boxed_local()
+    }
+
+    fn set(&self, values: Vec<(String, String)>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let updated = values.into_iter().map(|(key, value)| {
+            Array::of2(&JsValue::from(key), &JsValue::from(value))
+        }).collect::<Array>();
+
+        self.write(updated.clone(), Array::new(), updated)
+    }
+
+    fn remove(&self, keys: Vec<String>) -> LocalBoxFuture<'static, Result<(), JsValue>> {
+        let removed = keys.into_iter().map(JsValue::from).collect::<Array>();
+
+        let message = removed.iter().map(|key| Array::of1(&key)).collect::<Array>();
+
+        self.write(Array::new(), removed, message)
+    }
+
+    fn bytes_in_use(&self) -> LocalBoxFuture<'static, Result<Option<f64>, JsValue>> {
+        let fut = JsFuture::from(storage_usage());
+
+        async move {
+            Ok(fut.await?.as_f64())
+        }.boxed_local()
+    }
+
+    fn on_changed(&self, mut f: Box<dyn FnMut(Vec<(String, Option<String>)>)>) -> Option<StorageListener> {
+        let closure = Closure::new(move |message: JsValue| {
+            f(message.unchecked_into::<Array>().iter().map(|change| {
+                let change: Array = change.unchecked_into();
+                (change.get(0).as_string().unwrap(), change.get(1).as_string())
+            }).collect());
+        });
+
+        let channel = channel_listen(&self.name, &closure);
+
+        Some(StorageListener(Box::new(ChannelListener {
+            channel,
+            _closure: closure,
+        })))
+    }
+}
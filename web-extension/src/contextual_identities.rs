@@ -0,0 +1,58 @@
+use wasm_bindgen::prelude::*;
+use js_sys::{Object, Promise};
+use crate::Event;
+
+
+#[wasm_bindgen]
+extern "C" {
+    #[derive(Debug)]
+    pub type ContextualIdentity;
+
+    #[wasm_bindgen(method, getter, js_name = cookieStoreId)]
+    pub fn cookie_store_id(this: &ContextualIdentity) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn name(this: &ContextualIdentity) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn color(this: &ContextualIdentity) -> String;
+
+    #[wasm_bindgen(method, getter, js_name = colorCode)]
+    pub fn color_code(this: &ContextualIdentity) -> String;
+
+    #[wasm_bindgen(method, getter)]
+    pub fn icon(this: &ContextualIdentity) -> String;
+
+    #[wasm_bindgen(method, getter, js_name = iconUrl)]
+    pub fn icon_url(this: &ContextualIdentity) -> String;
+}
+
+
+#[wasm_bindgen]
+extern "C" {
+    pub type ContextualIdentities;
+
+    #[wasm_bindgen(method)]
+    pub fn create(this: &ContextualIdentities, details: &Object) -> Promise;
+
+    #[wasm_bindgen(method)]
+    pub fn get(this: &ContextualIdentities, cookie_store_id: &str) -> Promise;
+
+    #[wasm_bindgen(method)]
+    pub fn query(this: &ContextualIdentities, details: &Object) -> Promise;
+
+    #[wasm_bindgen(method)]
+    pub fn update(this: &ContextualIdentities, cookie_store_id: &str, details: &Object) -> Promise;
+
+    #[wasm_bindgen(method)]
+    pub fn remove(this: &ContextualIdentities, cookie_store_id: &str) -> Promise;
+
+    #[wasm_bindgen(method, getter, js_name = onCreated)]
+    pub fn on_created(this: &ContextualIdentities) -> Event;
+
+    #[wasm_bindgen(method, getter, js_name = onRemoved)]
+    pub fn on_removed(this: &ContextualIdentities) -> Event;
+
+    #[wasm_bindgen(method, getter, js_name = onUpdated)]
+    pub fn on_updated(this: &ContextualIdentities) -> Event;
+}
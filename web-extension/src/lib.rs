@@ -16,6 +16,9 @@ pub use windows::*;
 mod sessions;
 pub use sessions::*;
 
+mod contextual_identities;
+pub use contextual_identities::*;
+
 mod sidebar_action;
 pub use sidebar_action::*;
 
@@ -58,6 +61,9 @@ extern "C" {
 
     #[wasm_bindgen(method, getter)]
     pub fn sessions(this: &Browser) -> Sessions;
+
+    #[wasm_bindgen(method, getter, js_name = contextualIdentities)]
+    pub fn contextual_identities(this: &Browser) -> ContextualIdentities;
 }
 
 
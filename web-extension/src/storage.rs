@@ -7,8 +7,8 @@ use crate::Event;
 extern "C" {
     pub type StorageAreaRead;
 
-    #[wasm_bindgen(method, js_name = "getBytesInUse")]
-    pub fn get_bytes_in_use(this: &StorageAreaRead, keys: &JsValue) -> Promise;
+    #[wasm_bindgen(method, catch, js_name = "getBytesInUse")]
+    pub fn get_bytes_in_use(this: &StorageAreaRead, keys: &JsValue) -> Result<Promise, JsValue>;
 
     #[wasm_bindgen(method)]
     pub fn get(this: &StorageAreaRead, keys: &JsValue) -> Promise;